@@ -1,22 +1,297 @@
 
-use anyhow::{Context, Result};
+use anyhow::Result;
+#[cfg(feature = "sqlite")]
+use anyhow::Context;
+#[cfg(feature = "sqlite")]
 use rusqlite::{
     Connection,
     OptionalExtension,
+    Row,
     params,
+    params_from_iter,
     named_params,
+    types::ValueRef,
+    ToSql,
 };
 use serde::{Serialize, Deserialize};
+#[cfg(feature = "sqlite")]
+use std::collections::{HashMap, HashSet};
 use url::Url;
 
 pub use dbent::prelude::*;
 
+/// Most keys looked up by a single `IN` query, well under the limit of SQLite
+/// host parameters
+pub const KEYS_PER_QUERY: usize = 500;
+
+#[cfg(feature = "sqlite")]
 pub trait Model {
     fn all(conn: &Connection, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> where Self: Sized;
     fn count(conn: &Connection) -> Result<usize>;
     fn get(conn: &Connection, key: &str) -> Result<Self> where Self: Sized;
 }
 
+/// Model that can be read with only some of its fields
+///
+/// Each field is backed by one or more columns, like the key and label of an
+/// `EntityLabel`, and only the columns of the given fields are selected. The key
+/// is always read so the object can still be related to others. Fields not
+/// read keep their default value.
+#[cfg(feature = "sqlite")]
+pub trait Selectable: Model + Default {
+    /// Table, or join of tables, the fields are read from
+    const TABLE: &'static str;
+    /// Name of the key field
+    const KEY: &'static str;
+    /// Every field that can be selected, with the columns backing it
+    const FIELDS: &'static [(&'static str, &'static [&'static str])];
+
+    /// Reads a field from the row, its first column being at `index`
+    fn read_field(&mut self, field: &str, row: &Row<'_>, index: usize) -> rusqlite::Result<()>;
+
+    fn columns(field: &str) -> &'static [&'static str] {
+        Self::FIELDS
+            .iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, columns)| *columns)
+            .unwrap_or_default()
+    }
+
+    /// Reads the given fields of the objects, filtered by columns matching keys
+    fn select(
+        conn: &Connection,
+        fields: &[&str],
+        filters: &[(&'static str, &str)],
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Self>> {
+        let (fields, columns) = Self::field_columns(fields);
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {columns} FROM {} {} LIMIT ? OFFSET ?",
+                Self::TABLE,
+                where_clause(filters),
+            ))
+            .context("Failed preparing SQL for selecting fields")?;
+
+        let mut params = filters.iter().map(|(_, key)| key as &dyn ToSql).collect::<Vec<_>>();
+        params.push(&limit);
+        params.push(&offset);
+
+        let records = stmt
+            .query_map(params.as_slice(), |row| Self::read_fields(&fields, row))?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        Ok(records)
+    }
+
+    /// Reads the given fields of every object filtered by columns matching
+    /// keys, handing them to `f` one at a time as the rows are stepped through,
+    /// so the whole table is never in memory at once
+    ///
+    /// Stops early, without an error, when `f` returns `false`.
+    fn select_each(
+        conn: &Connection,
+        fields: &[&str],
+        filters: &[(&'static str, &str)],
+        mut f: impl FnMut(Self) -> Result<bool>,
+    ) -> Result<()> {
+        let (fields, columns) = Self::field_columns(fields);
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT {columns} FROM {} {}", Self::TABLE, where_clause(filters)))
+            .context("Failed preparing SQL for reading every object")?;
+
+        let mut rows = stmt.query(params_from_iter(filters.iter().map(|(_, key)| key)))?;
+
+        while let Some(row) = rows.next()? {
+            if !f(Self::read_fields(&fields, row)?)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of objects filtered by columns matching keys
+    fn select_count(conn: &Connection, filters: &[(&'static str, &str)]) -> Result<usize> {
+        let mut stmt = conn
+            .prepare(&format!("SELECT COUNT(*) FROM {} {}", Self::TABLE, where_clause(filters)))
+            .context("Failed preparing SQL for counting selection")?;
+
+        stmt
+            .query_row(params_from_iter(filters.iter().map(|(_, key)| key)), |row| row.get(0))
+            .context("Failed querying selection count")
+    }
+
+    /// Reads a page of the objects related to each of the keys through a
+    /// column, like the states of many countries, in a single query
+    ///
+    /// Each object comes with the key it is related to, and the pages are
+    /// numbered for every key separately.
+    fn select_related(
+        conn: &Connection,
+        fields: &[&str],
+        column: &'static str,
+        keys: &[&str],
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<(String, Self)>> {
+        let (fields, columns) = Self::field_columns(fields);
+        let key_column = Self::columns(Self::KEY).first().copied().unwrap_or(Self::KEY);
+        let related = fields.iter().map(|field| Self::columns(field).len()).sum::<usize>();
+
+        let mut relations = Vec::with_capacity(keys.len());
+
+        for chunk in keys.chunks(KEYS_PER_QUERY) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT * FROM (
+                        SELECT {columns}, {column},
+                            ROW_NUMBER() OVER (PARTITION BY {column} ORDER BY {key_column}) AS row_number
+                        FROM {}
+                        WHERE {column} IN ({placeholders})
+                    )
+                    WHERE row_number > ? AND row_number <= ?",
+                    Self::TABLE,
+                ))
+                .context("Failed preparing SQL for selecting related objects")?;
+
+            let mut params = chunk.iter().map(|key| key as &dyn ToSql).collect::<Vec<_>>();
+            let end = offset + limit;
+            params.push(&offset);
+            params.push(&end);
+
+            let records = stmt
+                .query_map(params.as_slice(), |row| {
+                    let key = match row.get_ref(related)? {
+                        ValueRef::Integer(key) => key.to_string(),
+                        ValueRef::Text(key) => String::from_utf8_lossy(key).into_owned(),
+                        _ => String::new(),
+                    };
+                    Ok((key, Self::read_fields(&fields, row)?))
+                })?
+                .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+            relations.extend(records);
+        }
+
+        Ok(relations)
+    }
+
+    /// Reads the given fields of the object with the key
+    fn select_one(conn: &Connection, fields: &[&str], key: &str) -> Result<Self> {
+        let (fields, columns) = Self::field_columns(fields);
+        let key_column = Self::columns(Self::KEY).first().copied().unwrap_or(Self::KEY);
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT {columns} FROM {} WHERE {key_column} = ?", Self::TABLE))
+            .context("Failed preparing SQL for selecting fields")?;
+
+        stmt
+            .query_row([key], |row| Self::read_fields(&fields, row))
+            .context("Failed querying selected fields")
+    }
+
+    /// Reads the given fields of the objects with the keys, in the order of the
+    /// keys, along with the keys not found
+    ///
+    /// The keys are looked up with `IN` queries of up to `KEYS_PER_QUERY` keys
+    /// each, and a key repeated is only read once.
+    fn select_keys(conn: &Connection, fields: &[&str], keys: &[&str]) -> Result<(Vec<Self>, Vec<String>)>
+    where
+        Self: Keyed,
+        Self::KeyType: ToString,
+    {
+        let (fields, columns) = Self::field_columns(fields);
+        let key_column = Self::columns(Self::KEY).first().copied().unwrap_or(Self::KEY);
+
+        let mut found = HashMap::with_capacity(keys.len());
+
+        for chunk in keys.chunks(KEYS_PER_QUERY) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+
+            let mut stmt = conn
+                .prepare(&format!("SELECT {columns} FROM {} WHERE {key_column} IN ({placeholders})", Self::TABLE))
+                .context("Failed preparing SQL for selecting keys")?;
+
+            let records = stmt
+                .query_map(params_from_iter(chunk), |row| Self::read_fields(&fields, row))?
+                .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+            for record in records {
+                if let Some(key) = record.key().ok().and_then(|key| key.as_ref()).map(ToString::to_string) {
+                    found.insert(key, record);
+                }
+            }
+        }
+
+        let mut objects = Vec::with_capacity(found.len());
+        let mut missing = Vec::new();
+
+        let mut seen = HashSet::with_capacity(keys.len());
+
+        for key in keys.iter().filter(|key| seen.insert(**key)) {
+            match found.remove(*key) {
+                Some(object) => objects.push(object),
+                None => missing.push(key.to_string()),
+            }
+        }
+
+        Ok((objects, missing))
+    }
+
+    /// Known fields with the key first, and the comma separated columns to
+    /// select for them
+    fn field_columns<'a>(fields: &[&'a str]) -> (Vec<&'a str>, String) {
+        let mut selected = vec![Self::KEY];
+        selected.extend(
+            fields
+                .iter()
+                .filter(|field| **field != Self::KEY && !Self::columns(field).is_empty())
+        );
+
+        let columns = selected
+            .iter()
+            .flat_map(|field| Self::columns(field))
+            .copied()
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        (selected, columns)
+    }
+
+    fn read_fields(fields: &[&str], row: &Row<'_>) -> rusqlite::Result<Self> {
+        let mut object = Self::default();
+        let mut index = 0;
+
+        for field in fields {
+            object.read_field(field, row, index)?;
+            index += Self::columns(field).len();
+        }
+
+        Ok(object)
+    }
+}
+
+/// SQL `WHERE` clause matching every filter column to a parameter
+#[cfg(feature = "sqlite")]
+fn where_clause(filters: &[(&str, &str)]) -> String {
+    if filters.is_empty() {
+        return String::new();
+    }
+
+    let conditions = filters
+        .iter()
+        .map(|(column, _)| format!("{column} = ?"))
+        .collect::<Vec<_>>();
+
+    format!("WHERE {}", conditions.join(" AND "))
+}
+
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 //<<>><=========================  COUNTRY  ==========================><<>>//
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
@@ -38,8 +313,11 @@ pub struct Country {
     pub emoji: String,
     pub emoji_u: String,
     pub states: Many<State>,
+    pub cities: Many<City>,
+    pub timezones: Many<Timezone>,
 }
 
+#[cfg(feature = "sqlite")]
 impl Model for Country {
     fn count(conn: &Connection) -> Result<usize> {
         let mut stmt = conn.prepare_cached(
@@ -55,7 +333,7 @@ impl Model for Country {
 
     fn all(conn: &Connection, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
         let mut stmt = conn.prepare_cached(
-                "SELECT iso2, name, world_region_id, world_region, world_subregion_id, world_subregion
+                "SELECT iso2, name, world_region_id, world_region, world_subregion_id, world_subregion, latitude, longitude
                 FROM countries
                 LIMIT ?1
                 OFFSET ?2")
@@ -68,6 +346,8 @@ impl Model for Country {
                         name: row.get(1)?,
                         region: EntityLabel::KeyLabel(row.get(2).unwrap_or_default(), row.get(3).unwrap_or_default()),
                         subregion: EntityLabel::KeyLabel(row.get(4).unwrap_or_default(), row.get(5).unwrap_or_default()),
+                        latitude: row.get(6)?,
+                        longitude: row.get(7)?,
                         ..Default::default()
                     }
                 )
@@ -112,6 +392,7 @@ impl Model for Country {
     }
 }
 
+#[cfg(feature = "sqlite")]
 impl Country {
     pub fn save(&self, conn: &mut Connection) -> Result<()> {
         let Self {
@@ -176,7 +457,7 @@ impl Country {
     pub fn from_region(conn: &Connection, key: &str, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
         let mut stmt = conn
             .prepare_cached(
-                "SELECT iso2, name, world_region_id, world_region, world_subregion_id, world_subregion
+                "SELECT iso2, name, world_region_id, world_region, world_subregion_id, world_subregion, latitude, longitude
                 FROM countries
                 WHERE world_region_id = ?1
                 LIMIT ?2
@@ -191,6 +472,8 @@ impl Country {
                         name: row.get(1)?,
                         region: EntityLabel::KeyLabel(row.get(2).unwrap_or_default(), row.get(3).unwrap_or_default()),
                         subregion: EntityLabel::KeyLabel(row.get(4).unwrap_or_default(), row.get(5).unwrap_or_default()),
+                        latitude: row.get(6)?,
+                        longitude: row.get(7)?,
                         ..Default::default()
                     }
                 )
@@ -203,7 +486,7 @@ impl Country {
     pub fn from_subregion(conn: &Connection, key: &str, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
         let mut stmt = conn
             .prepare_cached(
-                "SELECT iso2, name, world_region_id, world_region, world_subregion_id, world_subregion
+                "SELECT iso2, name, world_region_id, world_region, world_subregion_id, world_subregion, latitude, longitude
                 FROM countries
                 WHERE world_subregion_id = ?1
                 LIMIT ?2
@@ -218,6 +501,8 @@ impl Country {
                         name: row.get(1)?,
                         region: EntityLabel::KeyLabel(row.get(2).unwrap_or_default(), row.get(3).unwrap_or_default()),
                         subregion: EntityLabel::KeyLabel(row.get(4).unwrap_or_default(), row.get(5).unwrap_or_default()),
+                        latitude: row.get(6)?,
+                        longitude: row.get(7)?,
                         ..Default::default()
                     }
                 )
@@ -230,7 +515,7 @@ impl Country {
     pub fn from_currency(conn: &Connection, key: &str, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
         let mut stmt = conn
             .prepare_cached(
-                "SELECT iso2, name, world_region_id, world_region, world_subregion_id, world_subregion, currency_id, currency
+                "SELECT iso2, name, world_region_id, world_region, world_subregion_id, world_subregion, currency_id, currency, latitude, longitude
                 FROM countries
                 WHERE currency_id = ?1
                 LIMIT ?2
@@ -246,6 +531,8 @@ impl Country {
                         region: EntityLabel::KeyLabel(row.get(2).unwrap_or_default(), row.get(3).unwrap_or_default()),
                         subregion: EntityLabel::KeyLabel(row.get(4).unwrap_or_default(), row.get(5).unwrap_or_default()),
                         currency: EntityLabel::KeyLabel(row.get(6).unwrap_or_default(), row.get(7).unwrap_or_default()),
+                        latitude: row.get(8)?,
+                        longitude: row.get(9)?,
                         ..Default::default()
                     }
                 )
@@ -321,6 +608,7 @@ impl std::hash::Hash for Currency {
     }
 }
 
+#[cfg(feature = "sqlite")]
 impl Model for Currency {
     fn count(conn: &Connection) -> Result<usize> {
         let mut stmt = conn.prepare_cached(
@@ -377,6 +665,7 @@ impl Model for Currency {
     }
 }
 
+#[cfg(feature = "sqlite")]
 impl Currency {
     pub fn save(&self, conn: &mut Connection) -> Result<()> {
         let Self {
@@ -405,6 +694,7 @@ pub struct WorldRegion {
     pub countries: Many<Country>,
 }
 
+#[cfg(feature = "sqlite")]
 impl Model for WorldRegion {
     fn count(conn: &Connection) -> Result<usize> {
         let mut stmt = conn.prepare_cached(
@@ -459,6 +749,7 @@ impl Model for WorldRegion {
     }
 }
 
+#[cfg(feature = "sqlite")]
 impl WorldRegion {
     pub fn key_with_name(conn: &Connection, name: &str) -> Result<Key<Int>> {
         let mut stmt = conn.prepare_cached(
@@ -488,6 +779,7 @@ pub struct WorldSubregion {
     pub countries: Many<Country>,
 }
 
+#[cfg(feature = "sqlite")]
 impl Model for WorldSubregion {
     fn count(conn: &Connection) -> Result<usize> {
         let mut stmt = conn.prepare_cached(
@@ -551,6 +843,7 @@ impl Model for WorldSubregion {
     }
 }
 
+#[cfg(feature = "sqlite")]
 impl WorldSubregion {
     pub fn key_with_name(conn: &Connection, name: &str) -> Result<Key<Int>> {
         let mut stmt = conn.prepare_cached(
@@ -623,6 +916,7 @@ pub struct State {
     pub cities: Many<City>,
 }
 
+#[cfg(feature = "sqlite")]
 impl Model for State {
     fn count(conn: &Connection) -> Result<usize> {
         let mut stmt = conn.prepare_cached(
@@ -638,7 +932,7 @@ impl Model for State {
 
     fn all(conn: &Connection, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
         let mut stmt = conn.prepare_cached(
-            "SELECT id, name, country_id, country
+            "SELECT id, name, country_id, country, latitude, longitude
             FROM states
             LIMIT ?1
             OFFSET ?2")
@@ -650,6 +944,8 @@ impl Model for State {
                         id: row.get(0)?,
                         name: row.get(1)?,
                         country: EntityLabel::KeyLabel(row.get(2)?, row.get(3).unwrap_or_default()),
+                        latitude: row.get(4)?,
+                        longitude: row.get(5)?,
                         ..Default::default()
                     }
                 )
@@ -684,6 +980,7 @@ impl Model for State {
     }
 }
 
+#[cfg(feature = "sqlite")]
 impl State {
     pub fn save(&self, conn: &mut Connection) -> Result<()> {
         let Self {
@@ -739,7 +1036,7 @@ impl State {
     pub fn from_country(conn: &Connection, key: &str, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
         let mut stmt = conn
             .prepare_cached(
-                "SELECT id, name, country_id, country FROM states
+                "SELECT id, name, country_id, country, latitude, longitude FROM states
                 WHERE country_id = ?1
                 LIMIT ?2
                 OFFSET ?3")
@@ -752,6 +1049,8 @@ impl State {
                         id: row.get(0)?,
                         name: row.get(1)?,
                         country: EntityLabel::KeyLabel(row.get(2)?, row.get(3).unwrap_or_default()),
+                        latitude: row.get(4)?,
+                        longitude: row.get(5)?,
                         ..Default::default()
                     }
                 )
@@ -789,6 +1088,7 @@ pub struct City {
     pub longitude: Option<f32>,
 }
 
+#[cfg(feature = "sqlite")]
 impl Model for City {
     fn count(conn: &Connection) -> Result<usize> {
         let mut stmt = conn.prepare_cached(
@@ -804,7 +1104,7 @@ impl Model for City {
 
     fn all(conn: &Connection, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
         let mut stmt = conn.prepare_cached(
-            "SELECT id, name, state_id, state, country_id, country FROM cities
+            "SELECT id, name, state_id, state, country_id, country, latitude, longitude FROM cities
             LIMIT ?1
             OFFSET ?2")
             .context("Failed preparing SQL for fetching cities")?;
@@ -816,7 +1116,8 @@ impl Model for City {
                         name: row.get(1)?,
                         state: EntityLabel::KeyLabel(row.get(2)?, row.get(3).unwrap_or_default()),
                         country: EntityLabel::KeyLabel(row.get(4)?, row.get(5).unwrap_or_default()),
-                        ..Default::default()
+                        latitude: row.get(6)?,
+                        longitude: row.get(7)?,
                     }
                 )
             })?
@@ -848,6 +1149,7 @@ impl Model for City {
     }
 }
 
+#[cfg(feature = "sqlite")]
 impl City {
     pub fn save(&self, conn: &mut Connection) -> Result<()> {
         let Self {
@@ -889,7 +1191,7 @@ impl City {
     pub fn from_country(conn: &Connection, key: &str, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
         let mut stmt = conn
             .prepare_cached(
-                "SELECT id, name, state_id, state, country_id, country
+                "SELECT id, name, state_id, state, country_id, country, latitude, longitude
                 FROM cities
                 WHERE country_id = ?1
                 LIMIT ?2
@@ -904,7 +1206,8 @@ impl City {
                         name: row.get(1)?,
                         state: EntityLabel::KeyLabel(row.get(2)?, row.get(3).unwrap_or_default()),
                         country: EntityLabel::KeyLabel(row.get(4)?, row.get(5).unwrap_or_default()),
-                        ..Default::default()
+                        latitude: row.get(6)?,
+                        longitude: row.get(7)?,
                     }
                 )
             })?
@@ -916,7 +1219,7 @@ impl City {
     pub fn from_state(conn: &Connection, key: &str, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
         let mut stmt = conn
             .prepare_cached(
-                "SELECT id, name, state_id, state, country_id, country
+                "SELECT id, name, state_id, state, country_id, country, latitude, longitude
                 FROM cities
                 WHERE state_id = ?1
                 LIMIT ?2
@@ -931,7 +1234,8 @@ impl City {
                         name: row.get(1)?,
                         state: EntityLabel::KeyLabel(row.get(2)?, row.get(3).unwrap_or_default()),
                         country: EntityLabel::KeyLabel(row.get(4)?, row.get(5).unwrap_or_default()),
-                        ..Default::default()
+                        latitude: row.get(6)?,
+                        longitude: row.get(7)?,
                     }
                 )
             })?
@@ -967,6 +1271,638 @@ impl City {
     }
 }
 
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=======================  SELECTION  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(feature = "sqlite")]
+impl Selectable for Country {
+    const TABLE: &'static str = "countries";
+    const KEY: &'static str = "iso2";
+    const FIELDS: &'static [(&'static str, &'static [&'static str])] = &[
+        ("iso2", &["iso2"]),
+        ("iso3", &["iso3"]),
+        ("name", &["name"]),
+        ("code", &["code"]),
+        ("capital", &["capital_id", "capital"]),
+        ("currency", &["currency_id", "currency"]),
+        ("tld", &["tld"]),
+        ("native", &["native"]),
+        ("region", &["world_region_id", "world_region"]),
+        ("subregion", &["world_subregion_id", "world_subregion"]),
+        ("latitude", &["latitude"]),
+        ("longitude", &["longitude"]),
+        ("emoji", &["emoji"]),
+        ("emoji_u", &["emoji_u"]),
+    ];
+
+    fn read_field(&mut self, field: &str, row: &Row<'_>, index: usize) -> rusqlite::Result<()> {
+        match field {
+            "iso2" => self.iso2 = row.get(index)?,
+            "iso3" => self.iso3 = row.get(index)?,
+            "name" => self.name = row.get(index)?,
+            "code" => self.code = row.get(index)?,
+            "capital" => self.capital = EntityLabel::KeyLabel(row.get(index)?, row.get(index + 1).unwrap_or_default()),
+            "currency" => self.currency = EntityLabel::KeyLabel(row.get(index)?, row.get(index + 1).unwrap_or_default()),
+            "tld" => self.tld = row.get(index)?,
+            "native" => self.native = row.get(index)?,
+            "region" => self.region = EntityLabel::KeyLabel(row.get(index).unwrap_or_default(), row.get(index + 1).unwrap_or_default()),
+            "subregion" => self.subregion = EntityLabel::KeyLabel(row.get(index).unwrap_or_default(), row.get(index + 1).unwrap_or_default()),
+            "latitude" => self.latitude = row.get(index)?,
+            "longitude" => self.longitude = row.get(index)?,
+            "emoji" => self.emoji = row.get(index)?,
+            "emoji_u" => self.emoji_u = row.get(index)?,
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Selectable for State {
+    const TABLE: &'static str = "states";
+    const KEY: &'static str = "id";
+    const FIELDS: &'static [(&'static str, &'static [&'static str])] = &[
+        ("id", &["id"]),
+        ("name", &["name"]),
+        ("code", &["code"]),
+        ("country", &["country_id", "country"]),
+        ("latitude", &["latitude"]),
+        ("longitude", &["longitude"]),
+    ];
+
+    fn read_field(&mut self, field: &str, row: &Row<'_>, index: usize) -> rusqlite::Result<()> {
+        match field {
+            "id" => self.id = row.get(index)?,
+            "name" => self.name = row.get(index)?,
+            "code" => self.code = row.get(index)?,
+            "country" => self.country = EntityLabel::KeyLabel(row.get(index)?, row.get(index + 1).unwrap_or_default()),
+            "latitude" => self.latitude = row.get(index)?,
+            "longitude" => self.longitude = row.get(index)?,
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Selectable for City {
+    const TABLE: &'static str = "cities";
+    const KEY: &'static str = "id";
+    const FIELDS: &'static [(&'static str, &'static [&'static str])] = &[
+        ("id", &["id"]),
+        ("name", &["name"]),
+        ("state", &["state_id", "state"]),
+        ("country", &["country_id", "country"]),
+        ("latitude", &["latitude"]),
+        ("longitude", &["longitude"]),
+    ];
+
+    fn read_field(&mut self, field: &str, row: &Row<'_>, index: usize) -> rusqlite::Result<()> {
+        match field {
+            "id" => self.id = row.get(index)?,
+            "name" => self.name = row.get(index)?,
+            "state" => self.state = EntityLabel::KeyLabel(row.get(index)?, row.get(index + 1).unwrap_or_default()),
+            "country" => self.country = EntityLabel::KeyLabel(row.get(index)?, row.get(index + 1).unwrap_or_default()),
+            "latitude" => self.latitude = row.get(index)?,
+            "longitude" => self.longitude = row.get(index)?,
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Selectable for Currency {
+    const TABLE: &'static str = "currencies";
+    const KEY: &'static str = "iso";
+    const FIELDS: &'static [(&'static str, &'static [&'static str])] = &[
+        ("iso", &["iso"]),
+        ("name", &["name"]),
+        ("symbol", &["symbol"]),
+    ];
+
+    fn read_field(&mut self, field: &str, row: &Row<'_>, index: usize) -> rusqlite::Result<()> {
+        match field {
+            "iso" => self.iso = row.get(index)?,
+            "name" => self.name = row.get(index)?,
+            "symbol" => self.symbol = row.get(index)?,
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Selectable for WorldRegion {
+    const TABLE: &'static str = "world_regions";
+    const KEY: &'static str = "id";
+    const FIELDS: &'static [(&'static str, &'static [&'static str])] = &[
+        ("id", &["id"]),
+        ("name", &["name"]),
+    ];
+
+    fn read_field(&mut self, field: &str, row: &Row<'_>, index: usize) -> rusqlite::Result<()> {
+        match field {
+            "id" => self.id = row.get(index)?,
+            "name" => self.name = row.get(index)?,
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Selectable for WorldSubregion {
+    const TABLE: &'static str = "world_subregions AS sub LEFT JOIN world_regions AS reg ON sub.world_region_id = reg.id";
+    const KEY: &'static str = "id";
+    const FIELDS: &'static [(&'static str, &'static [&'static str])] = &[
+        ("id", &["sub.id"]),
+        ("name", &["sub.name"]),
+        ("region", &["sub.world_region_id", "reg.name"]),
+    ];
+
+    fn read_field(&mut self, field: &str, row: &Row<'_>, index: usize) -> rusqlite::Result<()> {
+        match field {
+            "id" => self.id = row.get(index)?,
+            "name" => self.name = row.get(index)?,
+            "region" => self.region = EntityLabel::KeyLabel(row.get(index)?, row.get(index + 1).unwrap_or_default()),
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Selectable for Timezone {
+    const TABLE: &'static str = "timezones";
+    const KEY: &'static str = "id";
+    const FIELDS: &'static [(&'static str, &'static [&'static str])] = &[
+        ("id", &["id"]),
+        ("zone_name", &["zone_name"]),
+        ("gmt_offset", &["gmt_offset"]),
+        ("gmt_offset_name", &["gmt_offset_name"]),
+        ("abbreviation", &["abbreviation"]),
+        ("tz_name", &["tz_name"]),
+        ("country", &["country_id", "country"]),
+    ];
+
+    fn read_field(&mut self, field: &str, row: &Row<'_>, index: usize) -> rusqlite::Result<()> {
+        match field {
+            "id" => self.id = row.get(index)?,
+            "zone_name" => self.zone_name = row.get(index)?,
+            "gmt_offset" => self.gmt_offset = row.get(index)?,
+            "gmt_offset_name" => self.gmt_offset_name = row.get(index)?,
+            "abbreviation" => self.abbreviation = row.get(index)?,
+            "tz_name" => self.tz_name = row.get(index)?,
+            "country" => self.country = EntityLabel::KeyLabel(row.get(index)?, row.get(index + 1).unwrap_or_default()),
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=======================  STATISTICS  =========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Number of objects and the extent of their coordinates
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    pub count: usize,
+    /// Extent of the objects with coordinates, `None` when there are none or
+    /// the objects have no coordinates
+    pub extent: Option<Extent>,
+}
+
+/// Stats of the objects related to the same object, like the cities of a
+/// country
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Group {
+    /// Key of the related object, `None` for the objects related to none
+    pub key: Option<String>,
+    pub name: String,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+/// Bounding box and centroid of a set of coordinates
+///
+/// The box is the minimum and maximum of each coordinate, so it doesn't wrap
+/// around the antimeridian, and the centroid is their mean.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Extent {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Related entity the objects of a `Groupable` model can be grouped by
+#[cfg(feature = "sqlite")]
+pub struct Grouping {
+    /// Name of the field relating the objects, like `country`
+    pub name: &'static str,
+    /// Column with the key of the related object
+    pub key: &'static str,
+    /// Column with the name of the related object
+    pub label: &'static str,
+    /// Join reaching the columns when they aren't in the table of the model
+    pub join: &'static str,
+}
+
+/// Model whose objects can be counted, and their coordinates bounded, as a
+/// whole or grouped by a related entity in a single `GROUP BY` query
+#[cfg(feature = "sqlite")]
+pub trait Groupable: Selectable {
+    /// Related entities the objects can be grouped by
+    const GROUPINGS: &'static [Grouping];
+    /// Latitude and longitude columns of the models with coordinates
+    const COORDINATES: Option<(&'static str, &'static str)>;
+
+    fn grouping(name: &str) -> Option<&'static Grouping> {
+        Self::GROUPINGS.iter().find(|grouping| grouping.name == name)
+    }
+
+    /// Stats of every object
+    fn stats(conn: &Connection) -> Result<Stats> {
+        let mut stmt = conn
+            .prepare(&format!("SELECT {} FROM {}", Self::aggregates(), Self::TABLE))
+            .context("Failed preparing SQL for fetching stats")?;
+
+        stmt
+            .query_row([], |row| read_stats(row, 0))
+            .context("Failed querying stats")
+    }
+
+    /// Stats of the objects grouped by the related entity of `grouping`,
+    /// largest group first
+    fn group_stats(conn: &Connection, grouping: &str) -> Result<Vec<Group>> {
+        let Grouping { key, label, join, .. } = Self::grouping(grouping)
+            .with_context(|| format!("No grouping named {grouping}"))?;
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT CAST({key} AS TEXT), COALESCE(MAX({label}), ''), {}
+                FROM {} {join}
+                GROUP BY {key}
+                ORDER BY COUNT(*) DESC, 1",
+                Self::aggregates(),
+                Self::TABLE,
+            ))
+            .context("Failed preparing SQL for fetching grouped stats")?;
+
+        let groups = stmt
+            .query_map([], |row| {
+                Ok(
+                    Group {
+                        key: row.get(0)?,
+                        name: row.get(1)?,
+                        stats: read_stats(row, 2)?,
+                    }
+                )
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        Ok(groups)
+    }
+
+    /// Aggregate columns read by `read_stats`
+    fn aggregates() -> String {
+        match Self::COORDINATES {
+            Some((lat, lon)) => format!(
+                "COUNT(*), MIN({lat}), MIN({lon}), MAX({lat}), MAX({lon}), AVG({lat}), AVG({lon})"
+            ),
+            None => "COUNT(*), NULL, NULL, NULL, NULL, NULL, NULL".to_string(),
+        }
+    }
+}
+
+/// Reads the aggregate columns of `Groupable::aggregates`, the first being at
+/// `index`
+#[cfg(feature = "sqlite")]
+fn read_stats(row: &Row<'_>, index: usize) -> rusqlite::Result<Stats> {
+    let mut coordinates = [0.0; 6];
+
+    for (offset, coordinate) in coordinates.iter_mut().enumerate() {
+        match row.get::<_, Option<f64>>(index + 1 + offset)? {
+            Some(value) => *coordinate = value,
+            None => return Ok(Stats { count: row.get(index)?, extent: None }),
+        }
+    }
+
+    let [south, west, north, east, latitude, longitude] = coordinates;
+
+    Ok(
+        Stats {
+            count: row.get(index)?,
+            extent: Some(Extent { south, west, north, east, latitude, longitude }),
+        }
+    )
+}
+
+#[cfg(feature = "sqlite")]
+impl Groupable for Country {
+    const GROUPINGS: &'static [Grouping] = &[
+        Grouping { name: "region", key: "countries.world_region_id", label: "countries.world_region", join: "" },
+        Grouping { name: "subregion", key: "countries.world_subregion_id", label: "countries.world_subregion", join: "" },
+        Grouping { name: "currency", key: "countries.currency_id", label: "countries.currency", join: "" },
+    ];
+    const COORDINATES: Option<(&'static str, &'static str)> = Some(("countries.latitude", "countries.longitude"));
+}
+
+#[cfg(feature = "sqlite")]
+impl Groupable for State {
+    const GROUPINGS: &'static [Grouping] = &[
+        Grouping { name: "country", key: "states.country_id", label: "states.country", join: "" },
+        Grouping {
+            name: "region",
+            key: "countries.world_region_id",
+            label: "countries.world_region",
+            join: "LEFT JOIN countries ON countries.iso2 = states.country_id",
+        },
+        Grouping {
+            name: "subregion",
+            key: "countries.world_subregion_id",
+            label: "countries.world_subregion",
+            join: "LEFT JOIN countries ON countries.iso2 = states.country_id",
+        },
+    ];
+    const COORDINATES: Option<(&'static str, &'static str)> = Some(("states.latitude", "states.longitude"));
+}
+
+#[cfg(feature = "sqlite")]
+impl Groupable for City {
+    const GROUPINGS: &'static [Grouping] = &[
+        Grouping { name: "country", key: "cities.country_id", label: "cities.country", join: "" },
+        Grouping { name: "state", key: "cities.state_id", label: "cities.state", join: "" },
+        Grouping {
+            name: "region",
+            key: "countries.world_region_id",
+            label: "countries.world_region",
+            join: "LEFT JOIN countries ON countries.iso2 = cities.country_id",
+        },
+        Grouping {
+            name: "subregion",
+            key: "countries.world_subregion_id",
+            label: "countries.world_subregion",
+            join: "LEFT JOIN countries ON countries.iso2 = cities.country_id",
+        },
+    ];
+    const COORDINATES: Option<(&'static str, &'static str)> = Some(("cities.latitude", "cities.longitude"));
+}
+
+#[cfg(feature = "sqlite")]
+impl Groupable for WorldSubregion {
+    const GROUPINGS: &'static [Grouping] = &[
+        Grouping { name: "region", key: "sub.world_region_id", label: "reg.name", join: "" },
+    ];
+    const COORDINATES: Option<(&'static str, &'static str)> = None;
+}
+
+#[cfg(feature = "sqlite")]
+impl Groupable for WorldRegion {
+    const GROUPINGS: &'static [Grouping] = &[];
+    const COORDINATES: Option<(&'static str, &'static str)> = None;
+}
+
+#[cfg(feature = "sqlite")]
+impl Groupable for Currency {
+    const GROUPINGS: &'static [Grouping] = &[];
+    const COORDINATES: Option<(&'static str, &'static str)> = None;
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  TIMEZONE  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[derive(Clone, Default, Debug, Entity, Label, Serialize, Deserialize)]
+pub struct Timezone {
+    pub id: Key<Int>,
+    #[label] pub zone_name: String,
+    pub gmt_offset: i32,
+    pub gmt_offset_name: String,
+    pub abbreviation: String,
+    pub tz_name: String,
+    pub country: EntityLabelString<Country>,
+}
+
+#[cfg(feature = "sqlite")]
+impl Timezone {
+    pub fn save(&self, conn: &mut Connection) -> Result<()> {
+        let Self {
+            id,
+            zone_name,
+            gmt_offset,
+            gmt_offset_name,
+            abbreviation,
+            tz_name,
+            country,
+        } = self;
+
+        conn.execute(
+            "INSERT INTO timezones (id, zone_name, gmt_offset, gmt_offset_name, abbreviation, tz_name, country_id, country)
+            VALUES (:id, :zone_name, :gmt_offset, :gmt_offset_name, :abbreviation, :tz_name, :country_id, :country)
+            ON CONFLICT(id) DO UPDATE
+            SET
+                zone_name=:zone_name,
+                gmt_offset=:gmt_offset,
+                gmt_offset_name=:gmt_offset_name,
+                abbreviation=:abbreviation,
+                tz_name=:tz_name,
+                country_id=:country_id,
+                country=:country;",
+            named_params![
+                ":id": id,
+                ":zone_name": zone_name,
+                ":gmt_offset": gmt_offset,
+                ":gmt_offset_name": gmt_offset_name,
+                ":abbreviation": abbreviation,
+                ":tz_name": tz_name,
+                ":country_id": country.key().ok(),
+                ":country": country.label().ok(),
+            ]
+        )?;
+
+        Ok(())
+    }
+
+    pub fn from_country(conn: &Connection, key: &str, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT id, zone_name, gmt_offset, gmt_offset_name, abbreviation, tz_name, country_id, country
+                FROM timezones
+                WHERE country_id = ?1
+                LIMIT ?2
+                OFFSET ?3")
+            .context("Failed preparing SQL for fetching timezones")?;
+
+        let records = stmt
+            .query_map(params![key, limit, offset], |row| {
+                Ok(
+                    Self {
+                        id: row.get(0)?,
+                        zone_name: row.get(1)?,
+                        gmt_offset: row.get(2)?,
+                        gmt_offset_name: row.get(3)?,
+                        abbreviation: row.get(4)?,
+                        tz_name: row.get(5)?,
+                        country: EntityLabel::KeyLabel(row.get(6)?, row.get(7).unwrap_or_default()),
+                    }
+                )
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        Ok((Self::from_country_count(conn, key)?, records))
+    }
+
+    pub fn from_country_count(conn: &Connection, key: &str) -> Result<usize> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT count(*) FROM timezones
+            WHERE country_id = ?")
+            .context("Failed preparing SQL for fetching timezones count")?;
+
+        stmt
+            .query_row([key], |row| {
+                row.get(0)
+            })
+            .context("Failed querying timezones count")
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Model for Timezone {
+    fn count(conn: &Connection) -> Result<usize> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT count(*) FROM timezones")
+            .context("Failed preparing SQL for fetching timezones count")?;
+
+        stmt
+            .query_row([], |row| {
+                row.get(0)
+            })
+            .context("Failed querying timezones count")
+    }
+
+    fn all(conn: &Connection, limit: usize, offset: usize) -> Result<(usize, Vec<Self>)> {
+        let fields = Self::FIELDS.iter().map(|(field, _)| *field).collect::<Vec<_>>();
+        let records = Self::select(conn, &fields, &[], limit, offset)?;
+
+        Ok((Self::count(conn)?, records))
+    }
+
+    fn get(conn: &Connection, key: &str) -> Result<Self> {
+        let fields = Self::FIELDS.iter().map(|(field, _)| *field).collect::<Vec<_>>();
+
+        Self::select_one(conn, &fields, key).context("Failed querying timezones data")
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  CHANGE  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+}
+
+/// Write to one of the entity tables, recorded by the triggers on them
+///
+/// The entity is the singular name of the object, like `country` or
+/// `subregion`, and the key is the one of its object routes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Change {
+    pub id: Int,
+    pub entity: String,
+    pub key: String,
+    pub action: ChangeAction,
+}
+
+#[cfg(feature = "sqlite")]
+impl Change {
+    /// Id of the latest change, 0 when there are none
+    pub fn last_id(conn: &Connection) -> Result<Int> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT COALESCE(MAX(id), 0) FROM changes")
+            .context("Failed preparing SQL for fetching last change")?;
+
+        stmt
+            .query_row([], |row| {
+                row.get(0)
+            })
+            .context("Failed querying last change")
+    }
+
+    /// Id of the oldest change still in the log, 0 when there are none
+    pub fn first_id(conn: &Connection) -> Result<Int> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT COALESCE(MIN(id), 0) FROM changes")
+            .context("Failed preparing SQL for fetching first change")?;
+
+        stmt
+            .query_row([], |row| {
+                row.get(0)
+            })
+            .context("Failed querying first change")
+    }
+
+    /// Changes after the one with the id, oldest first
+    pub fn since(conn: &Connection, id: Int, limit: usize) -> Result<Vec<Self>> {
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT id, entity, key, action
+                FROM changes
+                WHERE id > ?1
+                ORDER BY id
+                LIMIT ?2")
+            .context("Failed preparing SQL for fetching changes")?;
+
+        let records = stmt
+            .query_map(params![id, limit], |row| {
+                let action = match row.get_ref(3)?.as_str()? {
+                    "create" => ChangeAction::Create,
+                    "update" => ChangeAction::Update,
+                    _ => ChangeAction::Delete,
+                };
+
+                Ok(
+                    Self {
+                        id: row.get(0)?,
+                        entity: row.get(1)?,
+                        key: row.get(2)?,
+                        action,
+                    }
+                )
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        Ok(records)
+    }
+
+    /// Deletes all but the latest `keep` changes
+    pub fn prune(conn: &Connection, keep: usize) -> Result<usize> {
+        conn
+            .execute(
+                "DELETE FROM changes WHERE id <= (SELECT MAX(id) FROM changes) - ?",
+                [keep],
+            )
+            .context("Failed pruning changes")
+    }
+}
+
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 //<<>><=========================  URL  ==============================><<>>//
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
@@ -993,6 +1929,11 @@ impl UrlBuilder {
         Ok(Self { url: format!("http://{}", &addr).parse()? })
     }
 
+    /// Builder for the server at `url`, like `https://example.com:8443`
+    pub fn with_url(url: &str) -> Result<Self> {
+        Ok(Self { url: url.parse()? })
+    }
+
     pub fn with_base(host: &str) -> Self {
         Self {
             url: host.parse().unwrap(),
@@ -1011,6 +1952,12 @@ impl UrlBuilder {
         builder
     }
 
+    pub fn for_events(&self) -> Self {
+        let mut builder = self.clone();
+        builder.url.set_path("events");
+        builder
+    }
+
     pub fn for_country(&self, key: &str) -> Self {
         let mut builder = self.clone();
         builder
@@ -0,0 +1,8 @@
+CREATE TABLE api_keys (
+	id				INTEGER PRIMARY KEY AUTOINCREMENT,
+	name			TEXT NOT NULL,
+	role			TEXT NOT NULL CHECK(role IN ('read', 'editor', 'admin')),
+	hash			TEXT NOT NULL UNIQUE,
+	created_at		TEXT NOT NULL DEFAULT (datetime('now')),
+	revoked_at		TEXT
+) STRICT;
@@ -0,0 +1,90 @@
+CREATE TABLE changes (
+	id				INTEGER PRIMARY KEY AUTOINCREMENT,
+	entity			TEXT NOT NULL,
+	key				TEXT NOT NULL,
+	action			TEXT NOT NULL CHECK(action IN ('create', 'update', 'delete'))
+) STRICT;
+
+CREATE TRIGGER country_created AFTER INSERT ON countries BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('country', CAST(NEW.iso2 AS TEXT), 'create');
+END;
+
+CREATE TRIGGER country_updated AFTER UPDATE ON countries BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('country', CAST(NEW.iso2 AS TEXT), 'update');
+END;
+
+CREATE TRIGGER country_deleted AFTER DELETE ON countries BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('country', CAST(OLD.iso2 AS TEXT), 'delete');
+END;
+
+CREATE TRIGGER state_created AFTER INSERT ON states BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('state', CAST(NEW.id AS TEXT), 'create');
+END;
+
+CREATE TRIGGER state_updated AFTER UPDATE ON states BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('state', CAST(NEW.id AS TEXT), 'update');
+END;
+
+CREATE TRIGGER state_deleted AFTER DELETE ON states BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('state', CAST(OLD.id AS TEXT), 'delete');
+END;
+
+CREATE TRIGGER city_created AFTER INSERT ON cities BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('city', CAST(NEW.id AS TEXT), 'create');
+END;
+
+CREATE TRIGGER city_updated AFTER UPDATE ON cities BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('city', CAST(NEW.id AS TEXT), 'update');
+END;
+
+CREATE TRIGGER city_deleted AFTER DELETE ON cities BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('city', CAST(OLD.id AS TEXT), 'delete');
+END;
+
+CREATE TRIGGER region_created AFTER INSERT ON world_regions BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('region', CAST(NEW.id AS TEXT), 'create');
+END;
+
+CREATE TRIGGER region_updated AFTER UPDATE ON world_regions BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('region', CAST(NEW.id AS TEXT), 'update');
+END;
+
+CREATE TRIGGER region_deleted AFTER DELETE ON world_regions BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('region', CAST(OLD.id AS TEXT), 'delete');
+END;
+
+CREATE TRIGGER subregion_created AFTER INSERT ON world_subregions BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('subregion', CAST(NEW.id AS TEXT), 'create');
+END;
+
+CREATE TRIGGER subregion_updated AFTER UPDATE ON world_subregions BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('subregion', CAST(NEW.id AS TEXT), 'update');
+END;
+
+CREATE TRIGGER subregion_deleted AFTER DELETE ON world_subregions BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('subregion', CAST(OLD.id AS TEXT), 'delete');
+END;
+
+CREATE TRIGGER currency_created AFTER INSERT ON currencies BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('currency', CAST(NEW.iso AS TEXT), 'create');
+END;
+
+CREATE TRIGGER currency_updated AFTER UPDATE ON currencies BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('currency', CAST(NEW.iso AS TEXT), 'update');
+END;
+
+CREATE TRIGGER currency_deleted AFTER DELETE ON currencies BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('currency', CAST(OLD.iso AS TEXT), 'delete');
+END;
+
+CREATE TRIGGER timezone_created AFTER INSERT ON timezones BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('timezone', CAST(NEW.id AS TEXT), 'create');
+END;
+
+CREATE TRIGGER timezone_updated AFTER UPDATE ON timezones BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('timezone', CAST(NEW.id AS TEXT), 'update');
+END;
+
+CREATE TRIGGER timezone_deleted AFTER DELETE ON timezones BEGIN
+	INSERT INTO changes (entity, key, action) VALUES ('timezone', CAST(OLD.id AS TEXT), 'delete');
+END;
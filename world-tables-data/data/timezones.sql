@@ -0,0 +1,13 @@
+CREATE TABLE timezones (
+	id				INTEGER PRIMARY KEY,
+	zone_name		TEXT NOT NULL CHECK(zone_name <> ''),
+	gmt_offset		INTEGER NOT NULL,
+	gmt_offset_name	TEXT NOT NULL,
+	abbreviation	TEXT NOT NULL,
+	tz_name			TEXT NOT NULL,
+	country_id		TEXT NOT NULL,
+	country			TEXT NOT NULL CHECK(country <> ''),
+	FOREIGN KEY(country_id) REFERENCES countries(iso2)
+) STRICT;
+
+CREATE INDEX timezone_countries ON timezones(country_id);
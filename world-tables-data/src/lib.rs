@@ -1,13 +1,149 @@
 
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OptionalExtension};
 use rusqlite_migration::{M, Migrations};
+use std::collections::HashMap;
+
+use world_tables_base::{Key, EntityLabel, Timezone};
+
+/// Countries the database is seeded with, along with their timezones
+pub const COUNTRIES_CSV: &str = include_str!("../data/countries.csv");
 
 lazy_static::lazy_static! {
     pub static ref MIGRATIONS: Migrations<'static> =
         Migrations::new(vec![
             M::up(include_str!("../data/world.sql")),
+            M::up(include_str!("../data/timezones.sql")),
+            M::up(include_str!("../data/changes.sql")),
+            M::up(include_str!("../data/api_keys.sql")),
         ]);
 }
 
+/// Adds the timezones of the countries in the database when there are none,
+/// as in the databases seeded before the timezones table was added, returning
+/// how many were added
+///
+/// The inserts aren't logged as changes, as the data itself didn't change.
+pub fn fill_timezones(conn: &mut Connection) -> Result<usize> {
+    let empty: bool = conn.query_row("SELECT NOT EXISTS (SELECT 1 FROM timezones)", [], |row| row.get(0))?;
+    if !empty {
+        return Ok(0);
+    }
+
+    conn.execute_batch("SAVEPOINT fill_timezones")?;
+
+    match insert_timezones(conn) {
+        Ok(added) => {
+            conn.execute_batch("RELEASE fill_timezones")?;
+            Ok(added)
+        },
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TO fill_timezones; RELEASE fill_timezones")?;
+            Err(err)
+        },
+    }
+}
+
+fn insert_timezones(conn: &mut Connection) -> Result<usize> {
+    let last_change: i64 = conn.query_row("SELECT coalesce(max(id), 0) FROM changes", [], |row| row.get(0))?;
+    let mut added = 0;
+
+    for record in csv::Reader::from_reader(COUNTRIES_CSV.as_bytes()).deserialize() {
+        let record: HashMap<String, String> = record?;
+
+        let name: Option<String> = conn
+            .query_row("SELECT name FROM countries WHERE iso2 = ?", [&record["iso2"]], |row| row.get(0))
+            .optional()?;
+
+        let Some(name) = name else {
+            continue;
+        };
+
+        for zone in parse_timezones(&record["timezones"])? {
+            let timezone = Timezone {
+                zone_name: zone["zoneName"].to_owned(),
+                gmt_offset: zone["gmtOffset"].parse().context("Failed parsing timezone offset")?,
+                gmt_offset_name: zone["gmtOffsetName"].to_owned(),
+                abbreviation: zone["abbreviation"].to_owned(),
+                tz_name: zone["tzName"].to_owned(),
+                country: EntityLabel::KeyLabel(Key::new(record["iso2"].to_owned()), name.clone()),
+                ..Default::default()
+            };
+
+            timezone.save(conn)?;
+            added += 1;
+        }
+    }
+
+    conn.execute("DELETE FROM changes WHERE id > ?", [last_change])?;
+
+    Ok(added)
+}
+
+/// Parses the timezones column of the countries file, a list of javascript
+/// object literals like `[{zoneName:'Asia\/Kabul',gmtOffset:16200}]`
+fn parse_timezones(text: &str) -> Result<Vec<HashMap<String, String>>> {
+    let mut zones = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            continue;
+        }
+
+        let mut zone = HashMap::new();
+        loop {
+            let name = chars
+                .by_ref()
+                .take_while(|&c| c != ':')
+                .collect::<String>()
+                .trim_matches(|c: char| c == ',' || c.is_whitespace())
+                .to_owned();
+
+            let mut value = String::new();
+            if chars.peek() == Some(&'\'') {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    match c {
+                        // the data has unescaped quotes, like in "Dumont d'Urville"
+                        '\'' if matches!(chars.peek(), Some(',' | '}')) => break,
+                        '\\' => match chars.next() {
+                            Some('u') => {
+                                let code = chars.by_ref().take(4).collect::<String>();
+                                let code = u32::from_str_radix(&code, 16).context("Failed parsing timezone escape")?;
+                                value.push(char::from_u32(code).context("Invalid timezone escape")?);
+                            },
+                            Some(c) => value.push(c),
+                            None => break,
+                        },
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c == ',' || c == '}' {
+                        break;
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+            }
+
+            zone.insert(name, value);
+
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => anyhow::bail!("Unterminated timezone in: {text}"),
+            }
+        }
+
+        zones.push(zone);
+    }
+
+    Ok(zones)
+}
+
 // Test that migrations are working
 #[cfg(test)]
 mod tests {
@@ -17,4 +153,64 @@ mod tests {
     fn migrations_test() {
         assert!(MIGRATIONS.validate().is_ok());
     }
+
+    #[test]
+    fn timezones_are_parsed() {
+        let zones = parse_timezones(
+            "[{zoneName:'Asia\\/Kabul',gmtOffset:16200,tzName:'Afghanistan Time'},{zoneName:'Asia\\/Kolkata',gmtOffset:19800}]"
+        ).unwrap();
+
+        assert_eq!(zones.len(), 2);
+        assert_eq!(zones[0]["zoneName"], "Asia/Kabul");
+        assert_eq!(zones[0]["gmtOffset"], "16200");
+        assert_eq!(zones[0]["tzName"], "Afghanistan Time");
+        assert_eq!(zones[1]["zoneName"], "Asia/Kolkata");
+
+        assert!(parse_timezones("[]").unwrap().is_empty());
+    }
+
+    #[test]
+    fn timezone_quotes_and_escapes() {
+        let zones = parse_timezones("[{zoneName:'Antarctica\\/DumontDUrville',tzName:'Dumont d'Urville Time',abbreviation:'\\u00c9T'}]").unwrap();
+
+        assert_eq!(zones[0]["tzName"], "Dumont d'Urville Time");
+        assert_eq!(zones[0]["abbreviation"], "ÉT");
+    }
+
+    #[test]
+    fn empty_timezone_fields() {
+        let zones = parse_timezones("[{zoneName:'UTC',abbreviation:'',gmtOffset:}]").unwrap();
+
+        assert_eq!(zones[0]["abbreviation"], "");
+        assert_eq!(zones[0]["gmtOffset"], "");
+    }
+
+    #[test]
+    fn malformed_timezones_are_refused() {
+        assert!(parse_timezones("[{zoneName:'UTC'").is_err());
+        assert!(parse_timezones("[{zoneName}]").is_err());
+        assert!(parse_timezones("[{abbreviation:'\\uZZZZ'}]").is_err());
+    }
+
+    #[test]
+    fn timezones_are_filled_once_without_changes() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        MIGRATIONS.to_latest(&mut conn).unwrap();
+
+        conn.execute_batch(
+            "INSERT INTO countries (iso2, iso3, name, code, tld, native, latitude, longitude, emoji, emoji_u)
+            VALUES ('AF', 'AFG', 'Afghanistan', 4, '.af', '', 33, 65, '', '');"
+        ).unwrap();
+
+        assert_eq!(fill_timezones(&mut conn).unwrap(), 1);
+        assert_eq!(fill_timezones(&mut conn).unwrap(), 0);
+
+        let (zone, changes): (String, i64) = conn
+            .query_row("SELECT zone_name, (SELECT count(*) FROM changes WHERE entity = 'timezone') FROM timezones", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(zone, "Asia/Kabul");
+        assert_eq!(changes, 0);
+    }
 }
@@ -8,7 +8,7 @@ use std::{
 };
 
 use world_tables_base::{Key, EntityLabel, Country, State, City, Currency, WorldRegion, WorldSubregion};
-use world_tables_data::MIGRATIONS;
+use world_tables_data::{COUNTRIES_CSV, MIGRATIONS, fill_timezones};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -58,7 +58,11 @@ impl Cli {
 
                 MIGRATIONS.to_latest(&mut conn)?;
 
-                let mut reader = csv::Reader::from_reader(include_str!("../data/countries.csv").as_bytes());
+                // Seed in one transaction, clearing the changes logged by its
+                // inserts at the end, as they aren't events of the data
+                conn.execute_batch("BEGIN")?;
+
+                let mut reader = csv::Reader::from_reader(COUNTRIES_CSV.as_bytes());
 
                 let countries = reader
                     .deserialize()
@@ -116,6 +120,8 @@ impl Cli {
                     country.save(&mut conn).unwrap();
                 }
 
+                fill_timezones(&mut conn)?;
+
                 conn.execute("CREATE UNIQUE INDEX country_names ON countries(name);", []).unwrap();
 
                 let mut reader = csv::Reader::from_reader(include_str!("../data/states.csv").as_bytes());
@@ -167,6 +173,8 @@ impl Cli {
                         [&record["iso2"]]
                     ).unwrap();
                 }
+
+                conn.execute_batch("DELETE FROM changes; COMMIT")?;
             }
             Commands::Server {..} => {
                 todo!();
@@ -5,24 +5,35 @@ use egui_extras::{Column, TableBuilder};
 use enum_map::{enum_map, EnumMap};
 use lazy_static::lazy_static;
 use log::debug;
-use reqwest::blocking::Client;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+#[cfg(target_arch = "wasm32")]
+use reqwest::Client;
 use std::{
     cell::RefCell,
     collections::{hash_map::Entry, HashMap},
-    net::SocketAddr,
-    sync::mpsc::{channel, Receiver, Sender},
+    fmt::Display,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, RwLock,
+    },
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    io::{BufRead, BufReader},
     time::Duration,
     thread,
 };
 
 use world_tables_base::{
     Tag, Tagged, Keyed, Label, Country, State, City,
-    WorldRegion, WorldSubregion, Currency, UrlBuilder, Metadata
+    WorldRegion, WorldSubregion, Currency, UrlBuilder, Metadata,
+    Change, ChangeAction,
 };
 
 use crate::types::*;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::transport::Client;
 
-const RETRY_DELAY: f64 = 10.0;
 const PAGE_LIMIT: usize = 100;
 const NONE: &str = "None";
 
@@ -41,9 +52,13 @@ type ResponseChannels = (Sender<Result<DataResponse>>, Receiver<Result<DataRespo
 pub struct App {
     client: Client,
     url: UrlBuilder,
+    connection: Connection,
+    api_key: Arc<RwLock<Option<String>>>,
+    login: Login,
 
     metadata: ServerData<Metadata>,
     channels: EnumMap<DataKind, ResponseChannels>,
+    events: Receiver<ServerEvent>,
     main_show: EnumMap<MainList, bool>,
 
     countries: Option<TableData<Country>>,
@@ -74,17 +89,19 @@ pub struct App {
 impl Default for App {
     fn default() -> Self {
         Self {
-            client: Client::builder()
-                .timeout(Duration::from_secs(15))
-                .build()
-                .unwrap(),
+            client: App::client(None, &Connection::default()).unwrap(),
             url: UrlBuilder::new(),
+            connection: Connection::default(),
+            api_key: Arc::new(RwLock::new(None)),
+            login: Login::default(),
             metadata: ServerData::Empty,
 
             channels: enum_map! {
                 _ => channel(),
             },
 
+            events: channel().1,
+
             main_show: enum_map! {
                 _ => false,
             },
@@ -118,7 +135,7 @@ impl Default for App {
 
 impl App {
     /// Called once before the first frame.
-    pub fn new(cc: &eframe::CreationContext<'_>, addr: SocketAddr) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>, url: UrlBuilder, connection: Connection) -> Self {
         use catppuccin_egui::FRAPPE as THEME;
         catppuccin_egui::set_theme(&cc.egui_ctx, THEME);
 
@@ -172,12 +189,72 @@ impl App {
 
         cc.egui_ctx.set_style(style);
 
+        let api_key = Arc::new(RwLock::new(None));
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let events = {
+            let (tx, events) = channel();
+            App::subscribe(url.for_events(), connection.clone(), api_key.clone(), tx, cc.egui_ctx.clone());
+            events
+        };
+        #[cfg(target_arch = "wasm32")]
+        let events = channel().1;
+
         Self {
-            url: UrlBuilder::with_addr(addr).unwrap(),
+            client: App::client(None, &connection).expect("failed creating the HTTP client"),
+            url,
+            connection,
+            api_key,
+            events,
             ..Default::default()
         }
     }
 
+    /// Client for the requests, sending the API key when there is one
+    fn client(api_key: Option<&str>, connection: &Connection) -> Result<Client> {
+        let headers = App::auth_headers(api_key)?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        return Client::new(connection, headers, Some(Duration::from_secs(15)));
+
+        // browsers time out the requests themselves, and check the
+        // certificates of the page they loaded the app from
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = connection;
+            Ok(Client::builder().default_headers(headers).build()?)
+        }
+    }
+
+    fn auth_headers(api_key: Option<&str>) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+
+        if let Some(key) = api_key {
+            let mut value = HeaderValue::from_str(&format!("Bearer {key}")).context("Invalid API key")?;
+            value.set_sensitive(true);
+            headers.insert(header::AUTHORIZATION, value);
+        }
+
+        Ok(headers)
+    }
+
+    fn set_api_key(&mut self, api_key: Option<String>, ctx: &egui::Context) {
+        match App::client(api_key.as_deref(), &self.connection) {
+            Ok(client) => self.client = client,
+            Err(e) => return self.errors.push(format!("{e:#}")),
+        }
+
+        *self.api_key.write().unwrap() = api_key;
+        self.login.show = false;
+
+        // what was refused before may be allowed now, and the other way around
+        if self.metadata.is_ok() {
+            self.refresh(None, ctx);
+        } else {
+            self.metadata = ServerData::Empty;
+        }
+    }
+
     fn request(&self, url: &UrlBuilder, data_kind: DataKind, ctx: Option<&egui::Context>) {
         let tx = &self.channels[data_kind].0;
         App::send_request(&self.client, url, data_kind, tx, ctx);
@@ -189,46 +266,313 @@ impl App {
         let client = client.clone();
         let url = url.clone();
 
-        let get_result = move || -> Result<DataResponse> {
-            debug!("{}", url.as_str());
+        #[cfg(not(target_arch = "wasm32"))]
+        thread::spawn(move || {
+            let result = App::fetch(&client, &url, data_kind);
+            tx.send(result).unwrap();
+            if let Some(ctx) = ctx { ctx.request_repaint() }
+        });
+
+        // browsers have no threads to block, so the request is awaited instead
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = App::fetch(&client, &url, data_kind).await;
+            tx.send(result).unwrap();
+            if let Some(ctx) = ctx { ctx.request_repaint() }
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn fetch(client: &Client, url: &UrlBuilder, data_kind: DataKind) -> Result<DataResponse> {
+        debug!("{}", url.as_str());
 
-            let response = client
-                .get(url.as_str())
-                .send()
-                .context("Failed fetching countries from server")?;
+        let response = client
+            .get(url.as_str(), HeaderMap::new())
+            .context("Failed fetching data from server")?;
 
-            let pagination = match data_kind {
-                DataKind::Metadata | DataKind::Country | DataKind::State |
-                DataKind::City | DataKind::Region | DataKind::Subregion | DataKind::Currency => None,
-                _ => Some(Pagination::with_headers(response.headers())?),
-            };
+        let status = response.status;
+        let headers = response.headers.clone();
+        let body = response.text().context("Failed reading response from server")?;
+
+        DataResponse::new(data_kind, status, &headers, body)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn fetch(client: &Client, url: &UrlBuilder, data_kind: DataKind) -> Result<DataResponse> {
+        debug!("{}", url.as_str());
+
+        let response = client
+            .get(url.as_str())
+            .send()
+            .await
+            .context("Failed fetching data from server")?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await.context("Failed reading response from server")?;
 
-            let counts = match data_kind {
-                DataKind::Country => Some(Counts::with_country_headers(response.headers())?),
-                DataKind::State => Some(Counts::with_state_headers(response.headers())?),
-                DataKind::Region => Some(Counts::with_region_headers(response.headers())?),
-                DataKind::Subregion => Some(Counts::with_subregion_headers(response.headers())?),
-                DataKind::Currency => Some(Counts::with_currency_headers(response.headers())?),
-                _ => None,
+        DataResponse::new(data_kind, status, &headers, body)
+    }
+
+    /// Listens to the changes published by the server, reconnecting when the
+    /// stream breaks
+    ///
+    /// Only the native app listens, the web one reloads what it shows by hand.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn subscribe(url: UrlBuilder, connection: Connection, api_key: Arc<RwLock<Option<String>>>, tx: Sender<ServerEvent>, ctx: egui::Context) {
+        thread::spawn(move || {
+            // the stream stays open, so it can't have the timeout of the requests
+            let client = match Client::new(&connection, HeaderMap::new(), None) {
+                Ok(client) => client,
+                Err(e) => {
+                    let _ = tx.send(ServerEvent::Failed(format!("{:#}", e.context("Live updates are off"))));
+                    ctx.request_repaint();
+                    return;
+                },
             };
+            let mut last_event_id = None;
+            let mut reconnecting = false;
+
+            loop {
+                let api_key = api_key.read().unwrap().clone();
+                let delay = match App::read_events(&client, &url, api_key, &mut last_event_id, reconnecting, &tx, &ctx) {
+                    Ok(()) => RETRY_DELAY,
+                    Err(e) => {
+                        debug!("{e:#}");
+                        RateLimited::retry_delay(&e, RETRY_DELAY)
+                    },
+                };
+
+                reconnecting = true;
+                thread::sleep(Duration::from_secs_f64(delay));
+            }
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_events(
+        client: &Client,
+        url: &UrlBuilder,
+        api_key: Option<String>,
+        last_event_id: &mut Option<String>,
+        reconnecting: bool,
+        tx: &Sender<ServerEvent>,
+        ctx: &egui::Context) -> Result<()>
+    {
+        let mut headers = App::auth_headers(api_key.as_deref())?;
+
+        if let Some(id) = last_event_id {
+            headers.insert("Last-Event-ID", HeaderValue::from_str(id).context("Invalid event id")?);
+        }
+
+        let response = client
+            .get(url.as_str(), headers)
+            .context("Failed subscribing to server events")?;
+
+        if let Some(rate_limited) = RateLimited::with_headers(response.status, &response.headers, RETRY_DELAY) {
+            return Err(rate_limited.into());
+        }
+
+        if !response.status.is_success() {
+            let status = response.status;
+            let headers = response.headers.clone();
+            let body = response.text().unwrap_or_default();
 
-            Ok(DataResponse {
-                response,
-                page_text: pagination
-                    .map(|pagination| pagination.page.to_string())
-                    .unwrap_or("1".to_string()),
-                pagination,
-                counts,
-            })
+            let error = ServerError::with_response(status, &headers, &body)
+                .map_or_else(|| anyhow::anyhow!("Server answered {status}"), anyhow::Error::new);
+
+            return Err(error.context("Failed subscribing to server events"));
+        }
+
+        // without the id of a change received there is no telling what was missed
+        if reconnecting && last_event_id.is_none() {
+            tx.send(ServerEvent::Lagged)?;
+            ctx.request_repaint();
+        }
+
+        let mut event = String::new();
+        let mut data = String::new();
+
+        for line in BufReader::new(response.body).lines() {
+            let line = line.context("Failed reading server events")?;
+
+            if line.is_empty() {
+                let server_event = match event.as_str() {
+                    "change" => Some(ServerEvent::Change(
+                        serde_json::from_str(&data).context("Failed parsing change from server")?
+                    )),
+                    "lagged" => Some(ServerEvent::Lagged),
+                    _ => None,
+                };
+
+                if let Some(server_event) = server_event {
+                    tx.send(server_event)?;
+                    ctx.request_repaint();
+                }
+
+                event.clear();
+                data.clear();
+                continue;
+            }
+
+            // lines starting with a colon are comments, like the keep alive ones
+            let (field, value) = line.split_once(':').unwrap_or((&line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+
+            match field {
+                "id" => *last_event_id = Some(value.to_string()),
+                "event" => event = value.to_string(),
+                "data" => {
+                    if !data.is_empty() {
+                        data.push('\n');
+                    }
+                    data.push_str(value);
+                },
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn recv_events(&mut self, ctx: &egui::Context) {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                ServerEvent::Change(change) => self.refresh(Some(&change), ctx),
+                ServerEvent::Lagged => self.refresh(None, ctx),
+                ServerEvent::Failed(error) => self.errors.push(error),
+            }
+        }
+    }
+
+    /// Requests again the windows and lists showing the changed object, or
+    /// all of them without a change
+    fn refresh(&mut self, change: Option<&Change>, ctx: &egui::Context) {
+        let entity = change.map(|change| change.entity.as_str());
+        let affects = |name: &str| entity.is_none_or(|entity| entity == name);
+
+        if affects("country") {
+            App::refresh_windows(ctx, &self.client, &self.url, &self.channels, DataKind::Country, change, &mut self.country_windows);
+            self.refresh_list(ctx, self.url.for_countries(), DataKind::Countries, change, &self.countries);
+            self.refresh_filtered(ctx, DataKind::CountriesByRegion, change, &mut self.countries_by_region_windows.borrow_mut());
+            self.refresh_filtered(ctx, DataKind::CountriesBySubregion, change, &mut self.countries_by_subregion_windows.borrow_mut());
+            self.refresh_filtered(ctx, DataKind::CountriesByCurrency, change, &mut self.countries_by_currency_windows.borrow_mut());
+        }
+
+        if affects("state") {
+            App::refresh_windows(ctx, &self.client, &self.url, &self.channels, DataKind::State, change, &mut self.state_windows);
+            self.refresh_list(ctx, self.url.for_states(), DataKind::States, change, &self.states);
+            self.refresh_filtered(ctx, DataKind::StatesByCountry, change, &mut self.states_by_country_windows.borrow_mut());
+        }
+
+        if affects("city") {
+            App::refresh_windows(ctx, &self.client, &self.url, &self.channels, DataKind::City, change, &mut self.city_windows);
+            self.refresh_list(ctx, self.url.for_cities(), DataKind::Cities, change, &self.cities);
+            self.refresh_filtered(ctx, DataKind::CitiesByCountry, change, &mut self.cities_by_country_windows.borrow_mut());
+            self.refresh_filtered(ctx, DataKind::CitiesByState, change, &mut self.cities_by_state_windows.borrow_mut());
+        }
+
+        if affects("region") {
+            App::refresh_windows(ctx, &self.client, &self.url, &self.channels, DataKind::Region, change, &mut self.region_windows);
+            self.refresh_list(ctx, self.url.for_world_regions(), DataKind::Regions, change, &self.regions);
+        }
+
+        if affects("subregion") {
+            App::refresh_windows(ctx, &self.client, &self.url, &self.channels, DataKind::Subregion, change, &mut self.subregion_windows);
+            self.refresh_list(ctx, self.url.for_world_subregions(), DataKind::Subregions, change, &self.subregions);
+            self.refresh_filtered(ctx, DataKind::SubregionsByRegion, change, &mut self.subregions_by_region_windows.borrow_mut());
+        }
+
+        if affects("currency") {
+            App::refresh_windows(ctx, &self.client, &self.url, &self.channels, DataKind::Currency, change, &mut self.currency_windows);
+            self.refresh_list(ctx, self.url.for_currencies(), DataKind::Currencies, change, &self.currencies);
+        }
+    }
+
+    fn refresh_windows<T>(
+        ctx: &egui::Context,
+        client: &Client,
+        url: &UrlBuilder,
+        channels: &EnumMap<DataKind, ResponseChannels>,
+        data_kind: DataKind,
+        change: Option<&Change>,
+        windows_map: &mut HashMap<String, ObjectData<T>>)
+    {
+        let keys: Vec<String> = match change {
+            Some(change) if change.action == ChangeAction::Delete => {
+                windows_map.remove(&change.key);
+                Vec::new()
+            },
+            Some(change) => windows_map.keys().filter(|key| **key == change.key).cloned().collect(),
+            None => windows_map.keys().cloned().collect(),
         };
 
-        thread::spawn(move || {
-            let result = get_result();
-            tx.send(result).unwrap();
-            if let Some(ctx) = ctx { ctx.request_repaint() }
+        let tx = &channels[data_kind].0;
+        for key in keys {
+            App::send_request(client, &App::object_url(url, data_kind, &key).unwrap(), data_kind, tx, Some(ctx));
+        }
+    }
+
+    fn refresh_list<T: Keyed>(
+        &self,
+        ctx: &egui::Context,
+        url: UrlBuilder,
+        data_kind: DataKind,
+        change: Option<&Change>,
+        table: &Option<TableData<T>>)
+    where
+        T::KeyType: Display,
+    {
+        if let Some(table_data) = table {
+            if App::is_affected(table_data, change) {
+                self.request(&url.with_pagination(table_data.pagination.page, PAGE_LIMIT), data_kind, Some(ctx));
+            }
+        }
+    }
+
+    fn refresh_filtered<T: Keyed>(
+        &self,
+        ctx: &egui::Context,
+        data_kind: DataKind,
+        change: Option<&Change>,
+        windows_map: &mut HashMap<String, FilteredTableData<T>>)
+    where
+        T::KeyType: Display,
+    {
+        windows_map.retain(|key, filtered_table_data| {
+            let Some(table_data) = &filtered_table_data.data else { return true };
+
+            if !App::is_affected(table_data, change) {
+                return true;
+            }
+
+            // an empty list can't tell the window it belongs to, so the window
+            // goes away with its last object
+            let emptied = change.is_some_and(|change| change.action == ChangeAction::Delete) &&
+                table_data.pagination.total_count == 1;
+
+            if !emptied {
+                let url = self.filtered_url(data_kind, key).with_pagination(table_data.pagination.page, PAGE_LIMIT);
+                self.request(&url, data_kind, Some(ctx));
+            }
+
+            !emptied
         });
     }
 
+    /// Whether a list may show different objects after the change
+    fn is_affected<T: Keyed>(table_data: &TableData<T>, change: Option<&Change>) -> bool
+    where
+        T::KeyType: Display,
+    {
+        match change {
+            Some(change) if change.action == ChangeAction::Update => table_data.data
+                .iter()
+                .any(|object| object.key().is_ok_and(|key| key.to_string() == change.key)),
+            _ => true,
+        }
+    }
+
     fn recv_response(&mut self) {
         for (data_kind, (_, rx)) in &self.channels {
             if let Ok(result) = rx.try_recv() {
@@ -638,37 +982,14 @@ impl App {
     {
         if let Some(Tag { key, label }) = selection {
             if let Entry::Vacant(e) = windows_map.entry(key.clone()) {
-                let (title, url) = match data_kind {
-                    DataKind::CountriesByRegion => (
-                        "Countries",
-                        self.url.for_countries_from_region(&key).with_pagination(1, PAGE_LIMIT),
-                    ),
-                    DataKind::CountriesBySubregion => (
-                        "Countries",
-                        self.url.for_countries_from_subregion(&key).with_pagination(1, PAGE_LIMIT),
-                    ),
-                    DataKind::CountriesByCurrency => (
-                        "Countries",
-                        self.url.for_countries_from_currency(&key).with_pagination(1, PAGE_LIMIT),
-                    ),
-                    DataKind::StatesByCountry => (
-                        "States",
-                        self.url.for_states_from_country(&key).with_pagination(1, PAGE_LIMIT),
-                    ),
-                    DataKind::CitiesByCountry => (
-                        "Cities",
-                        self.url.for_cities_from_country(&key).with_pagination(1, PAGE_LIMIT),
-                    ),
-                    DataKind::CitiesByState => (
-                        "Cities",
-                        self.url.for_cities_from_state(&key).with_pagination(1, PAGE_LIMIT),
-                    ),
-                    DataKind::SubregionsByRegion => (
-                        "Subregions",
-                        self.url.for_subregions_from_region(&key).with_pagination(1, PAGE_LIMIT),
-                    ),
+                let title = match data_kind {
+                    DataKind::CountriesByRegion | DataKind::CountriesBySubregion | DataKind::CountriesByCurrency => "Countries",
+                    DataKind::StatesByCountry => "States",
+                    DataKind::CitiesByCountry | DataKind::CitiesByState => "Cities",
+                    DataKind::SubregionsByRegion => "Subregions",
                     _ => panic!("Data kind not supported for filtered listing"),
                 };
+                let url = self.filtered_url(data_kind, &key).with_pagination(1, PAGE_LIMIT);
 
                 e.insert(
                     FilteredTableData {
@@ -682,6 +1003,49 @@ impl App {
         }
     }
 
+    fn filtered_url(&self, data_kind: DataKind, key: &str) -> UrlBuilder {
+        match data_kind {
+            DataKind::CountriesByRegion => self.url.for_countries_from_region(key),
+            DataKind::CountriesBySubregion => self.url.for_countries_from_subregion(key),
+            DataKind::CountriesByCurrency => self.url.for_countries_from_currency(key),
+            DataKind::StatesByCountry => self.url.for_states_from_country(key),
+            DataKind::CitiesByCountry => self.url.for_cities_from_country(key),
+            DataKind::CitiesByState => self.url.for_cities_from_state(key),
+            DataKind::SubregionsByRegion => self.url.for_subregions_from_region(key),
+            _ => panic!("Data kind not supported for filtered listing"),
+        }
+    }
+
+    fn login_window(&mut self, ctx: &egui::Context) {
+        let mut submitted = None;
+        let logged_in = self.api_key.read().unwrap().is_some();
+
+        egui::Window::new("Login")
+            .open(&mut self.login.show)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("API key");
+                let response = ui.add(egui::TextEdit::singleline(&mut self.login.key_text).password(true).desired_width(250.0));
+
+                ui.horizontal(|ui| {
+                    if ui.button("Log in").clicked() || (response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) {
+                        submitted = Some(Some(self.login.key_text.trim().to_string()).filter(|key| !key.is_empty()));
+                    }
+                    if ui.add_enabled(logged_in, egui::Button::new("Log out")).clicked() {
+                        self.login.key_text.clear();
+                        submitted = Some(None);
+                    }
+                });
+
+                ui.small(if logged_in { "Requests send the API key" } else { "Requests are anonymous" });
+            });
+
+        if let Some(api_key) = submitted {
+            self.set_api_key(api_key, ctx);
+        }
+    }
+
     fn errors_window(&mut self, ctx: &egui::Context) {
         if !self.errors.is_empty() {
             egui::Window::new("Errors")
@@ -694,9 +1058,15 @@ impl App {
                     });
                     ui.add_space(10.0);
                     ui.vertical_centered(|ui| {
-                        if ui.button("Clear").clicked() {
-                            self.errors.clear();
-                        }
+                        ui.horizontal(|ui| {
+                            // with the request ids in them, for reporting the errors
+                            if ui.button("Copy").clicked() {
+                                ui.output_mut(|o| o.copied_text = self.errors.join("\n"));
+                            }
+                            if ui.button("Clear").clicked() {
+                                self.errors.clear();
+                            }
+                        });
                     });
                 });
         }
@@ -719,18 +1089,21 @@ impl eframe::App for App {
                 },
                 ServerData::Loading => {
                     if let Ok(result) = self.channels[DataKind::Metadata].1.try_recv() {
-                        let handle_error = |e| -> ServerData<Metadata> {
+                        let login_show = &mut self.login.show;
+                        let handle_error = |e: anyhow::Error| -> ServerData<Metadata> {
                             debug!("{:?}", e);
-                            ServerData::Failed(format!("{e:#}"), ctx.input(|i| i.time))
+                            *login_show |= e.is::<Unauthorized>();
+                            let retry_at = ctx.input(|i| i.time) + RateLimited::retry_delay(&e, RETRY_DELAY);
+                            ServerData::Failed(format!("{e:#}"), retry_at)
                         };
 
                         self.metadata = result
-                            .and_then(|data_response| Ok(data_response.response.json()?))
+                            .and_then(|data_response| data_response.json())
                             .map_or_else(handle_error, ServerData::Ok);
                     }
                 },
                 ServerData::Failed(message, time) => {
-                    if ctx.input(|i| i.time) >= time + RETRY_DELAY {
+                    if ctx.input(|i| i.time) >= *time {
                         self.metadata = ServerData::Empty;
                     } else if !message.is_empty() {
                         self.errors.push(message.clone());
@@ -749,11 +1122,13 @@ impl eframe::App for App {
                 });
 
             self.errors_window(ctx);
+            self.login_window(ctx);
 
             return;
         }
 
         let mut main_show = self.main_show;
+        let mut login_show = self.login.show;
 
         //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
         //<<>><======================  SIDE PANEL  ==========================><<>>//
@@ -814,6 +1189,9 @@ impl eframe::App for App {
                                         self.request(&url, DataKind::Currencies, Some(ctx));
                                     }
                                 });
+
+                                ui.add_space(10.0);
+                                ui.toggle_value(&mut login_show, "Login");
                             });
                         });
                         strip.empty();
@@ -847,6 +1225,7 @@ impl eframe::App for App {
         }
 
         self.recv_response();
+        self.recv_events(ctx);
 
         let mut country_selected: Option<Tag> = None;
         let mut state_selected: Option<Tag> = None;
@@ -991,6 +1370,7 @@ impl eframe::App for App {
 
         // persist the state of shows
         self.main_show = main_show;
+        self.login.show = login_show;
 
         //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
         //<<>><===================  COUNTRY WINDOWS  ========================><<>>//
@@ -1512,6 +1892,7 @@ impl eframe::App for App {
         App::handle_selection(ctx, &self.client, &self.url, &self.channels, DataKind::Currency, currency_selected, &mut self.currency_windows);
 
         self.errors_window(ctx);
+        self.login_window(ctx);
     }
 }
 
@@ -2,4 +2,7 @@
 
 mod types;
 mod app;
+#[cfg(not(target_arch = "wasm32"))]
+mod transport;
 pub use app::App;
+pub use types::Connection;
@@ -2,23 +2,43 @@
 #![warn(clippy::all, rust_2018_idioms)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+use world_tables_base::UrlBuilder;
+use world_tables_gui::{App, Connection};
+
+#[cfg(not(target_arch = "wasm32"))]
 use anyhow::Result;
+#[cfg(not(target_arch = "wasm32"))]
 use clap::Parser;
-use std::net::SocketAddr;
-
-use world_tables_gui::App;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{net::SocketAddr, path::PathBuf};
 
+#[cfg(not(target_arch = "wasm32"))]
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 struct Cli {
+    /// Address of the server, like `127.0.0.1:3000`, or its URL, like `https://example.com:8443`
     #[arg(short, long, default_value_t = String::from("127.0.0.1:3000"))]
     address: String,
+
+    /// Unix domain socket the server listens on, instead of the address
+    #[arg(long, value_name = "PATH", conflicts_with = "address")]
+    socket: Option<PathBuf>,
+
+    /// PEM certificate of a CA to trust for HTTPS, along with the usual ones
+    #[arg(long, value_name = "PATH", conflicts_with = "socket")]
+    ca_cert: Option<PathBuf>,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl Cli {
-    fn execute(self) -> Result<SocketAddr> {
-        Ok(self.address.parse()?)
+    fn execute(self) -> Result<(UrlBuilder, Connection)> {
+        let url = match self.address.contains("://") {
+            true => UrlBuilder::with_url(&self.address)?,
+            false => UrlBuilder::with_addr(self.address.parse::<SocketAddr>()?)?,
+        };
+
+        Ok((url, Connection { socket: self.socket, ca_cert: self.ca_cert }))
     }
 }
 
@@ -28,7 +48,7 @@ fn main() -> eframe::Result<()> {
     // Log to stdout (if you run with `RUST_LOG=debug`).
     tracing_subscriber::fmt::init();
 
-    let addr = Cli::parse().execute().expect("cli: failed execution");
+    let (url, connection) = Cli::parse().execute().expect("cli: failed execution");
 
     let native_options = eframe::NativeOptions {
         min_window_size: Some(egui::vec2(640.0, 480.0)),
@@ -40,7 +60,7 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "World Tables",
         native_options,
-        Box::new(move |cc| Box::new(App::new(cc, addr))),
+        Box::new(move |cc| Box::new(App::new(cc, url, connection))),
     )
 }
 
@@ -55,11 +75,19 @@ fn main() {
 
     let web_options = eframe::WebOptions::default();
 
+    // the app is served by the server it talks to
+    let origin = web_sys::window()
+        .expect("no window")
+        .location()
+        .origin()
+        .expect("no origin for the page");
+    let url = UrlBuilder::with_base(&origin);
+
     wasm_bindgen_futures::spawn_local(async {
         eframe::start_web(
             "the_canvas_id", // hardcode it
             web_options,
-            Box::new(|cc| Box::new(eframe_template::TemplateApp::new(cc))),
+            Box::new(|cc| Box::new(App::new(cc, url, Connection::default()))),
         )
         .await
         .expect("failed to start eframe");
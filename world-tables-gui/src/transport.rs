@@ -0,0 +1,179 @@
+//! Blocking HTTP client of the native app
+//!
+//! Requests go over TCP with `reqwest`, with HTTPS trusting the CA given with
+//! `--ca-cert` besides the usual ones, or over the Unix domain socket of a
+//! server started with `--socket`, through a `hyper` client connecting with
+//! `hyperlocal`. The responses of both are read the same way.
+
+use anyhow::{Context, Result};
+use reqwest::{header::HeaderMap, Certificate, StatusCode};
+use std::{fs, io::Read, time::Duration};
+
+use crate::types::Connection;
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+#[cfg(unix)]
+use hyper::body::{Bytes, HttpBody};
+#[cfg(unix)]
+use std::{io, sync::Arc};
+
+#[derive(Clone, Debug)]
+pub(crate) enum Client {
+    Tcp(reqwest::blocking::Client),
+    #[cfg(unix)]
+    Unix(Box<UnixClient>),
+}
+
+impl Client {
+    /// Client sending `headers` with every request to the server listening on
+    /// the socket of `connection`, or on TCP without one
+    pub fn new(connection: &Connection, headers: HeaderMap, timeout: Option<Duration>) -> Result<Self> {
+        match &connection.socket {
+            None => {
+                let mut builder = reqwest::blocking::Client::builder()
+                    .timeout(timeout)
+                    .default_headers(headers);
+
+                if let Some(path) = &connection.ca_cert {
+                    let pem = fs::read(path).with_context(|| format!("Failed reading CA certificate {path:?}"))?;
+                    builder = builder.add_root_certificate(Certificate::from_pem(&pem).context("Invalid CA certificate")?);
+                }
+
+                Ok(Client::Tcp(builder.build()?))
+            },
+            #[cfg(unix)]
+            Some(socket) => Ok(Client::Unix(Box::new(UnixClient::new(socket.clone(), headers, timeout)?))),
+            #[cfg(not(unix))]
+            Some(_) => anyhow::bail!("Unix domain sockets are not supported on this platform"),
+        }
+    }
+
+    pub fn get(&self, url: &str, headers: HeaderMap) -> Result<Response> {
+        match self {
+            Client::Tcp(client) => {
+                let response = client.get(url).headers(headers).send()?;
+
+                Ok(Response {
+                    status: response.status(),
+                    headers: response.headers().clone(),
+                    body: Box::new(response),
+                })
+            },
+            #[cfg(unix)]
+            Client::Unix(client) => client.get(url, headers),
+        }
+    }
+}
+
+pub(crate) struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Body, read as it arrives
+    pub body: Box<dyn Read + Send>,
+}
+
+impl Response {
+    pub fn text(mut self) -> Result<String> {
+        let mut text = String::new();
+        self.body.read_to_string(&mut text)?;
+        Ok(text)
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><======================  UNIX SOCKET  =========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Client of a server on a Unix domain socket, blocking on its own runtime
+#[cfg(unix)]
+#[derive(Clone)]
+pub(crate) struct UnixClient {
+    socket: PathBuf,
+    headers: HeaderMap,
+    timeout: Option<Duration>,
+    client: hyper::Client<hyperlocal::UnixConnector>,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+#[cfg(unix)]
+impl std::fmt::Debug for UnixClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnixClient").field("socket", &self.socket).finish()
+    }
+}
+
+#[cfg(unix)]
+impl UnixClient {
+    fn new(socket: PathBuf, headers: HeaderMap, timeout: Option<Duration>) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .context("Failed starting runtime for the socket client")?;
+
+        Ok(Self {
+            socket,
+            headers,
+            timeout,
+            client: hyper::Client::builder().build(hyperlocal::UnixConnector),
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    fn get(&self, url: &str, headers: HeaderMap) -> Result<Response> {
+        // the socket takes the place of the host, only the path is sent
+        let url: hyper::Uri = url.parse().context("Invalid request URL")?;
+        let path = url.path_and_query().map_or("/", |path| path.as_str());
+
+        let mut request = hyper::Request::get(hyperlocal::Uri::new(&self.socket, path))
+            .body(hyper::Body::empty())?;
+        request.headers_mut().extend(self.headers.clone());
+        request.headers_mut().extend(headers);
+
+        let response = self.runtime.block_on(async {
+            match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, self.client.request(request))
+                    .await
+                    .context("Request to the server timed out")?
+                    .context("Failed sending request over socket"),
+                None => self.client.request(request).await.context("Failed sending request over socket"),
+            }
+        })?;
+
+        let (parts, body) = response.into_parts();
+
+        Ok(Response {
+            status: parts.status,
+            headers: parts.headers,
+            body: Box::new(BodyReader { body, chunk: Bytes::new(), runtime: self.runtime.clone() }),
+        })
+    }
+}
+
+/// Blocking reader of a streamed `hyper` body
+#[cfg(unix)]
+struct BodyReader {
+    body: hyper::Body,
+    /// Rest of the chunk last received
+    chunk: Bytes,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+#[cfg(unix)]
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.body.data()) {
+                Some(Ok(chunk)) => self.chunk = chunk,
+                Some(Err(e)) => return Err(io::Error::other(e)),
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
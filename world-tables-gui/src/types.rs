@@ -1,8 +1,26 @@
 
 use anyhow::{Context, Result};
 use enum_map::Enum;
-use reqwest::blocking::Response;
 use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use std::{fmt, path::PathBuf};
+
+use world_tables_base::Change;
+
+/// Header with the id of a request in the server logs
+pub(crate) const REQUEST_ID: &str = "X-Request-Id";
+/// Seconds to wait before retrying a failed request, when the server doesn't tell
+pub(crate) const RETRY_DELAY: f64 = 10.0;
+
+/// How the native app reaches the server, besides its URL
+#[derive(Clone, Debug, Default)]
+pub struct Connection {
+    /// Unix domain socket of the server, used instead of the host of the URL
+    pub socket: Option<PathBuf>,
+    /// PEM certificate of a CA trusted for HTTPS, along with the usual ones
+    pub ca_cert: Option<PathBuf>,
+}
 
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 //<<>><========================  DATAKIND  ==========================><<>>//
@@ -79,18 +97,77 @@ pub(crate) enum DataKind {
     SubregionsByRegion,
 }
 
+/// Message received from the server `/events` stream
+#[derive(Debug)]
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))] // the web app doesn't listen to events
+pub(crate) enum ServerEvent {
+    Change(Change),
+    /// Changes were missed, so anything shown may be outdated
+    Lagged,
+    /// The events can't be listened to at all
+    Failed(String),
+}
+
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 //<<>><=======================  DATA TYPES  =========================><<>>//
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 
 #[derive(Debug)]
 pub(crate) struct DataResponse {
-    pub response: Response,
+    pub body: String,
     pub pagination: Option<Pagination>,
     pub counts: Option<Counts>,
     pub page_text: String,
 }
 
+impl DataResponse {
+    /// Data of a response read in full, or the error it answers
+    ///
+    /// Responses are read before being handled, the same way whether they
+    /// were received by a blocking or an async request.
+    pub fn new(data_kind: DataKind, status: StatusCode, headers: &HeaderMap, body: String) -> Result<Self> {
+        if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            return Err(Unauthorized(body).into());
+        }
+
+        if let Some(rate_limited) = RateLimited::with_headers(status, headers, RETRY_DELAY) {
+            return Err(rate_limited.into());
+        }
+
+        if let Some(server_error) = ServerError::with_response(status, headers, &body) {
+            return Err(server_error.into());
+        }
+
+        let pagination = match data_kind {
+            DataKind::Metadata | DataKind::Country | DataKind::State |
+            DataKind::City | DataKind::Region | DataKind::Subregion | DataKind::Currency => None,
+            _ => Some(Pagination::with_headers(headers)?),
+        };
+
+        let counts = match data_kind {
+            DataKind::Country => Some(Counts::with_country_headers(headers)?),
+            DataKind::State => Some(Counts::with_state_headers(headers)?),
+            DataKind::Region => Some(Counts::with_region_headers(headers)?),
+            DataKind::Subregion => Some(Counts::with_subregion_headers(headers)?),
+            DataKind::Currency => Some(Counts::with_currency_headers(headers)?),
+            _ => None,
+        };
+
+        Ok(DataResponse {
+            body,
+            page_text: pagination
+                .map(|pagination| pagination.page.to_string())
+                .unwrap_or("1".to_string()),
+            pagination,
+            counts,
+        })
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_str(&self.body).context("Failed parsing response from server")
+    }
+}
+
 #[derive(Default, Debug)]
 pub(crate) struct TableData<T> {
     pub data: Vec<T>,
@@ -98,9 +175,9 @@ pub(crate) struct TableData<T> {
     pub page_text: String,
 }
 
-impl<T: serde::de::DeserializeOwned> From<DataResponse> for Option<TableData<T>> {
+impl<T: DeserializeOwned> From<DataResponse> for Option<TableData<T>> {
     fn from(data_response: DataResponse) -> Self {
-        let option_data = data_response.response.json().ok();
+        let option_data = data_response.json().ok();
         option_data.map(|data|
             TableData {
                 data,
@@ -123,7 +200,7 @@ pub(crate) struct FilteredTableData<T> {
 pub(crate) enum ServerData<T> {
     Ok(T),
     Loading,
-    Failed(String, f64), // error message with time for delay
+    Failed(String, f64), // error message with time to retry at
     Empty,
 }
 
@@ -159,9 +236,106 @@ impl<T> Default for ObjectData<T> {
     }
 }
 
-impl<T: serde::de::DeserializeOwned> From<DataResponse> for Option<T> {
+#[derive(Default, Debug)]
+pub(crate) struct Login {
+    pub key_text: String,
+    pub show: bool,
+}
+
+/// Refusal of a request for lacking an API key, or one with enough permissions
+#[derive(Debug)]
+pub(crate) struct Unauthorized(pub String);
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, log in with an API key allowing it", self.0)
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// Refusal of a request for making too many of them, with the seconds to wait
+/// before trying again
+#[derive(Debug)]
+pub(crate) struct RateLimited(pub f64);
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Too many requests to the server, retrying in {} seconds", self.0)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+impl RateLimited {
+    /// Refusal in a response, waiting for `default` seconds when the server
+    /// doesn't tell how long
+    pub fn with_headers(status: StatusCode, headers: &HeaderMap, default: f64) -> Option<Self> {
+        if status != StatusCode::TOO_MANY_REQUESTS {
+            return None;
+        }
+
+        let delay = headers
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default);
+
+        Some(RateLimited(delay))
+    }
+
+    /// Seconds to wait before retrying a failed request
+    pub fn retry_delay(error: &anyhow::Error, default: f64) -> f64 {
+        error.downcast_ref::<RateLimited>().map_or(default, |RateLimited(delay)| *delay)
+    }
+}
+
+/// Error answered by the server, with the id of the request in its logs to
+/// mention when reporting it
+#[derive(Debug)]
+pub(crate) struct ServerError {
+    pub status: StatusCode,
+    pub message: String,
+    pub request_id: Option<String>,
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Server answered {}: {}", self.status, self.message)?;
+
+        match &self.request_id {
+            // the server puts the id in the message of its internal errors
+            Some(id) if !self.message.contains(id.as_str()) => write!(f, " (request id {id})"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+impl ServerError {
+    /// Error in a response, if its status isn't a success
+    pub fn with_response(status: StatusCode, headers: &HeaderMap, body: &str) -> Option<Self> {
+        if !status.is_client_error() && !status.is_server_error() {
+            return None;
+        }
+
+        let request_id = headers
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+
+        Some(ServerError {
+            status,
+            message: body.to_string(),
+            request_id,
+        })
+    }
+}
+
+impl<T: DeserializeOwned> From<DataResponse> for Option<T> {
     fn from(data_response: DataResponse) -> Self {
-        data_response.response.json().ok()
+        data_response.json().ok()
     }
 }
 
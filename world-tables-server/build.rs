@@ -0,0 +1,12 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // a protoc of our own unless one is given, so building doesn't need one installed
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/world_tables.proto"], &["proto"])?;
+
+    Ok(())
+}
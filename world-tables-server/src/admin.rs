@@ -0,0 +1,391 @@
+//! Database maintenance
+//!
+//! Online backups with the SQLite backup API, compacted copies with
+//! `VACUUM INTO`, integrity and foreign key checks, and WAL checkpoints, run
+//! while the server keeps serving. They are answered as JSON reports by the
+//! `/admin` routes, for the `admin` role, and printed by the `db` subcommands.
+//!
+//! The copies made through the routes are written to the `backups` directory
+//! next to the database, never over an existing file.
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use clap::ValueEnum;
+use rusqlite::{backup::{Backup, StepResult}, Connection};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{AppError, Database, cache::Uncached};
+
+/// Wait before retrying a backup the database was too busy for
+const BUSY_PAUSE: Duration = Duration::from_millis(50);
+
+/// Copy of the database written to a new file
+#[derive(Debug, Serialize)]
+pub struct CopyReport {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub duration_ms: f64,
+}
+
+/// Problems found in the database, none when `ok`
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub ok: bool,
+    /// Messages of `PRAGMA integrity_check`
+    pub integrity_errors: Vec<String>,
+    /// Rows of `PRAGMA foreign_key_check`
+    pub foreign_key_violations: Vec<ForeignKeyViolation>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ForeignKeyViolation {
+    pub table: String,
+    pub rowid: Option<i64>,
+    pub parent: String,
+    pub fkid: i64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckpointMode {
+    /// Checkpoints what it can without waiting for readers or writers
+    #[default]
+    Passive,
+    /// Waits for writers, then checkpoints the whole log
+    Full,
+    /// Like `full`, then waits for readers so the log starts over
+    Restart,
+    /// Like `restart`, then truncates the log file
+    Truncate,
+}
+
+impl CheckpointMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CheckpointMode::Passive => "PASSIVE",
+            CheckpointMode::Full => "FULL",
+            CheckpointMode::Restart => "RESTART",
+            CheckpointMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+/// Result of `PRAGMA wal_checkpoint`, with `-1` frames when the database
+/// isn't in WAL mode
+#[derive(Debug, Serialize)]
+pub struct CheckpointReport {
+    pub mode: CheckpointMode,
+    /// Whether a reader or writer kept the checkpoint from completing
+    pub busy: bool,
+    pub log_frames: i64,
+    pub checkpointed_frames: i64,
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=======================  OPERATIONS  =========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Copies the database to `path` with the SQLite backup API
+///
+/// In WAL mode reading doesn't block writers, so the whole database is copied
+/// in a single step from one snapshot, instead of in many steps restarted by
+/// every write made in between.
+pub fn backup(conn: &Connection, path: &Path) -> Result<CopyReport> {
+    let start = Instant::now();
+    ensure_new(path)?;
+
+    let mut dest = Connection::open(path).with_context(|| format!("Failed creating backup {path:?}"))?;
+    let backup = Backup::new(conn, &mut dest)?;
+
+    loop {
+        match backup.step(-1).with_context(|| format!("Failed backing up the database to {path:?}"))? {
+            StepResult::Done => break,
+            StepResult::Busy | StepResult::Locked => thread::sleep(BUSY_PAUSE),
+            _ => {},
+        }
+    }
+
+    // finishes the backup before its size is taken
+    drop(backup);
+
+    copy_report(path, start)
+}
+
+/// Writes a compacted copy of the database to `path` with `VACUUM INTO`
+pub fn vacuum_into(conn: &Connection, path: &Path) -> Result<CopyReport> {
+    let start = Instant::now();
+    ensure_new(path)?;
+
+    conn.execute("VACUUM INTO ?", [path.to_str().context("invalid unicode on path")?])
+        .with_context(|| format!("Failed vacuuming the database into {path:?}"))?;
+
+    copy_report(path, start)
+}
+
+/// Runs `PRAGMA integrity_check` and `PRAGMA foreign_key_check`
+pub fn check(conn: &Connection) -> Result<CheckReport> {
+    let integrity_errors = conn
+        .prepare("PRAGMA integrity_check")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .filter(|message| !matches!(message.as_deref(), Ok("ok")))
+        .collect::<Result<Vec<_>, rusqlite::Error>>()
+        .context("Failed checking the database integrity")?;
+
+    let foreign_key_violations = conn
+        .prepare("PRAGMA foreign_key_check")?
+        .query_map([], |row| {
+            Ok(
+                ForeignKeyViolation {
+                    table: row.get(0)?,
+                    rowid: row.get(1)?,
+                    parent: row.get(2)?,
+                    fkid: row.get(3)?,
+                }
+            )
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()
+        .context("Failed checking the database foreign keys")?;
+
+    Ok(
+        CheckReport {
+            ok: integrity_errors.is_empty() && foreign_key_violations.is_empty(),
+            integrity_errors,
+            foreign_key_violations,
+        }
+    )
+}
+
+/// Runs `PRAGMA wal_checkpoint` in `mode`
+pub fn checkpoint(conn: &Connection, mode: CheckpointMode) -> Result<CheckpointReport> {
+    conn
+        .query_row(&format!("PRAGMA wal_checkpoint({})", mode.as_str()), [], |row| {
+            Ok(
+                CheckpointReport {
+                    mode,
+                    busy: row.get::<_, i64>(0)? != 0,
+                    log_frames: row.get(1)?,
+                    checkpointed_frames: row.get(2)?,
+                }
+            )
+        })
+        .context("Failed checkpointing the database")
+}
+
+fn ensure_new(path: &Path) -> Result<()> {
+    if path.try_exists()? {
+        bail!("{path:?} already exists");
+    }
+
+    Ok(())
+}
+
+fn copy_report(path: &Path, start: Instant) -> Result<CopyReport> {
+    Ok(
+        CopyReport {
+            path: path.canonicalize()?,
+            bytes: path.metadata()?.len(),
+            duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+        }
+    )
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  ROUTES  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[derive(Deserialize)]
+pub struct CopyQuery {
+    /// File name of the copy in the backups directory
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CheckpointQuery {
+    #[serde(default)]
+    mode: CheckpointMode,
+}
+
+pub async fn backup_route(
+    Query(query): Query<CopyQuery>,
+    Extension(db): Extension<Database>,
+) -> Result<Response, AppError> {
+    copy_route(query, db, "backup", backup).await
+}
+
+pub async fn vacuum_route(
+    Query(query): Query<CopyQuery>,
+    Extension(db): Extension<Database>,
+) -> Result<Response, AppError> {
+    copy_route(query, db, "vacuum", vacuum_into).await
+}
+
+pub async fn check_route(Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError> {
+    let report = blocking(db, check).await?;
+
+    // a check is only worth anything when it's run
+    Ok((Extension(Uncached), Json(report)))
+}
+
+pub async fn checkpoint_route(
+    Query(query): Query<CheckpointQuery>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, AppError> {
+    let mode = query.mode;
+
+    Ok(Json(blocking(db, move |conn| checkpoint(conn, mode)).await?))
+}
+
+/// Writes a copy named by the query, or after the time and `kind`, to the
+/// backups directory
+async fn copy_route(
+    query: CopyQuery,
+    db: Database,
+    kind: &'static str,
+    copy: fn(&Connection, &Path) -> Result<CopyReport>,
+) -> Result<Response, AppError> {
+    let dir = db.backup_dir();
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed creating {dir:?}"))?;
+
+    let name = match query.name {
+        Some(name) if is_file_name(&name) => name,
+        Some(name) => return Ok((StatusCode::BAD_REQUEST, format!("{name:?} is not a plain file name")).into_response()),
+        None => {
+            let conn = db.connection()?;
+            let time: String = conn.query_row("SELECT strftime('%Y%m%dT%H%M%SZ', 'now')", [], |row| row.get(0))?;
+            format!("world-{time}-{kind}.db3")
+        },
+    };
+
+    let path = dir.join(&name);
+    if path.try_exists()? {
+        return Ok((StatusCode::CONFLICT, format!("A backup named {name} already exists")).into_response());
+    }
+
+    Ok(Json(blocking(db, move |conn| copy(conn, &path)).await?).into_response())
+}
+
+/// Whether `name` names a file in the directory it's joined to, and nothing
+/// outside of it
+fn is_file_name(name: &str) -> bool {
+    Path::new(name).file_name().and_then(|file_name| file_name.to_str()) == Some(name)
+        && !name.starts_with('.')
+}
+
+/// Runs `operation` on a pooled connection off the async runtime, as copies
+/// and checks of a large database take a while
+async fn blocking<R: Send + 'static>(
+    db: Database,
+    operation: impl FnOnce(&Connection) -> Result<R> + Send + 'static,
+) -> Result<R> {
+    let span = tracing::Span::current();
+
+    tokio::task::spawn_blocking(move || {
+        let _span = span.enter();
+        operation(&*db.connection()?)
+    })
+    .await?
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::post, Router};
+    use tower::ServiceExt;
+
+    fn copy_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("world-tables-{name}-{}.db3", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn copies_are_never_written_over_files() {
+        let db = Database::seeded("admin-copies");
+        let conn = db.connection().unwrap();
+
+        type Operation = fn(&Connection, &Path) -> Result<CopyReport>;
+        let copies: [(&str, Operation); 2] = [
+            ("admin-backup", backup),
+            ("admin-vacuum", vacuum_into),
+        ];
+
+        for (name, copy) in copies {
+            let path = copy_path(name);
+
+            let report = copy(&conn, &path).unwrap();
+            assert!(report.bytes > 0);
+
+            let symbol: String = Connection::open(&path)
+                .unwrap()
+                .query_row("SELECT symbol FROM currencies WHERE iso = 'EUR'", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(symbol, "€");
+
+            assert!(copy(&conn, &path).is_err());
+            assert_eq!(path.metadata().unwrap().len(), report.bytes);
+
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn check_and_checkpoint_report_on_the_database() {
+        let db = Database::seeded("admin-check");
+        let conn = db.connection().unwrap();
+
+        let report = check(&conn).unwrap();
+        assert!(report.ok);
+        assert!(report.integrity_errors.is_empty() && report.foreign_key_violations.is_empty());
+
+        let report = checkpoint(&conn, CheckpointMode::Truncate).unwrap();
+        assert!(!report.busy);
+        assert_eq!(report.log_frames, 0);
+    }
+
+    #[test]
+    fn copy_names_stay_in_the_backups_directory() {
+        assert!(is_file_name("world.db3"));
+
+        for name in ["", ".", "..", ".hidden", "../world.db3", "backups/world.db3", "/tmp/world.db3"] {
+            assert!(!is_file_name(name), "{name:?} is not a plain file name");
+        }
+    }
+
+    #[tokio::test]
+    async fn copy_routes_refuse_other_paths_and_existing_files() {
+        let db = Database::seeded("admin-routes");
+        let app = Router::new()
+            .route("/admin/backup", post(backup_route))
+            .layer(Extension(db.clone()));
+
+        let send = |uri: String| {
+            let app = app.clone();
+            async move { app.oneshot(Request::post(uri).body(Body::empty()).unwrap()).await.unwrap().status() }
+        };
+
+        assert_eq!(send("/admin/backup?name=..%2Fworld.db3".into()).await, StatusCode::BAD_REQUEST);
+
+        let name = format!("world-tables-admin-route-{}.db3", std::process::id());
+        let path = db.backup_dir().join(&name);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(send(format!("/admin/backup?name={name}")).await, StatusCode::OK);
+        assert_eq!(send(format!("/admin/backup?name={name}")).await, StatusCode::CONFLICT);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+//! API key authentication
+//!
+//! Keys are created with the `keys` command and sent as a bearer token in the
+//! `Authorization` header. Only a SHA-256 hash of each key is stored, in the
+//! `api_keys` table, along with its role. Requests without a key get the role
+//! given with `--anonymous-role`, if any.
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use clap::ValueEnum;
+use rand::RngCore;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+
+use world_tables_base::Int;
+
+use crate::{AppError, Database, cache::READ_ONLY_POSTS, limit::{Client, RateLimiter, too_many_requests}};
+
+/// Prefix of the generated keys, telling them apart from other secrets
+const KEY_PREFIX: &str = "wt_";
+/// Random bytes in a generated key
+const KEY_BYTES: usize = 32;
+/// Invalid keys an address may send at once, whatever the rate limit
+const ATTEMPTS_BURST: f64 = 10.0;
+/// Invalid keys an address may send per second once the burst is spent
+const ATTEMPTS_RATE: f64 = 0.1;
+/// Routes about the server rather than the data
+const ADMIN_PATHS: &[&str] = &["/cache", "/metrics"];
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  ROLE  =============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Permissions of a key, each role allowing everything the previous ones do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Role {
+    /// Reading the data
+    Read,
+    /// Writing the data
+    Editor,
+    /// Managing the server
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Read => "read",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Role needed for a request
+    fn required(method: &Method, path: &str) -> Self {
+        if ADMIN_PATHS.contains(&path) || path.starts_with("/admin") {
+            Role::Admin
+        } else if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) || READ_ONLY_POSTS.contains(&path) {
+            Role::Read
+        } else {
+            Role::Editor
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        <Role as ValueEnum>::from_str(s, false).map_err(anyhow::Error::msg)
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  API KEY  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[derive(Debug)]
+pub struct ApiKey {
+    pub id: Int,
+    pub name: String,
+    pub role: Role,
+    pub created_at: String,
+    pub revoked_at: Option<String>,
+}
+
+impl ApiKey {
+    /// Stores a new random key, returning it along with the only copy of the
+    /// key itself
+    pub fn create(conn: &Connection, name: &str, role: Role) -> Result<(Self, String)> {
+        let mut bytes = [0u8; KEY_BYTES];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let key = format!("{KEY_PREFIX}{}", hex(&bytes));
+
+        let id = conn
+            .query_row(
+                "INSERT INTO api_keys (name, role, hash) VALUES (?, ?, ?) RETURNING id",
+                params![name, role.as_str(), hash(&key)],
+                |row| row.get(0),
+            )
+            .context("Failed inserting API key")?;
+
+        Ok((ApiKey::get(conn, id)?.context("API key not found after insert")?, key))
+    }
+
+    pub fn get(conn: &Connection, id: Int) -> Result<Option<Self>> {
+        conn.query_row(
+            "SELECT id, name, role, created_at, revoked_at FROM api_keys WHERE id = ?",
+            [id],
+            ApiKey::read_row,
+        )
+        .optional()
+        .context("Failed querying API key")
+    }
+
+    pub fn all(conn: &Connection) -> Result<Vec<Self>> {
+        let mut stmt = conn
+            .prepare("SELECT id, name, role, created_at, revoked_at FROM api_keys ORDER BY id")
+            .context("Failed preparing SQL for API keys")?;
+
+        let keys = stmt
+            .query_map([], ApiKey::read_row)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(keys)
+    }
+
+    /// Revokes a key, returning false when there is no such key or it was
+    /// already revoked
+    pub fn revoke(conn: &Connection, id: Int) -> Result<bool> {
+        let updated = conn
+            .execute(
+                "UPDATE api_keys SET revoked_at = datetime('now') WHERE id = ? AND revoked_at IS NULL",
+                [id],
+            )
+            .context("Failed revoking API key")?;
+
+        Ok(updated == 1)
+    }
+
+    /// Id and role of a key, `None` when it's unknown or revoked
+    pub fn authenticate(conn: &Connection, key: &str) -> Result<Option<(Int, Role)>> {
+        let found: Option<(Int, String)> = conn
+            .query_row(
+                "SELECT id, role FROM api_keys WHERE hash = ? AND revoked_at IS NULL",
+                [hash(key)],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .context("Failed querying API key role")?;
+
+        found.map(|(id, role)| Ok((id, role.parse::<Role>()?))).transpose()
+    }
+
+    fn read_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        let role: String = row.get(2)?;
+
+        Ok(ApiKey {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            role: role.parse().map_err(|_| rusqlite::Error::InvalidColumnType(
+                2, "role".into(), rusqlite::types::Type::Text
+            ))?,
+            created_at: row.get(3)?,
+            revoked_at: row.get(4)?,
+        })
+    }
+}
+
+fn hash(key: &str) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><======================  MIDDLEWARE  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Id of the API key a request was authenticated with
+#[derive(Clone, Copy, Debug)]
+pub struct KeyId(pub Int);
+
+/// Requests with invalid keys of each address, which are refused before the
+/// key is looked up once there are too many of them
+#[derive(Clone)]
+pub struct FailedAttempts(pub RateLimiter);
+
+impl Default for FailedAttempts {
+    /// Always limited, even with `--rate-limit 0`, as guessing keys is never fine
+    fn default() -> Self {
+        Self(RateLimiter::new(ATTEMPTS_RATE, ATTEMPTS_BURST))
+    }
+}
+
+/// Role of the requests without a key, which are refused when it's `None`
+#[derive(Clone, Copy, Debug)]
+pub struct Anonymous(pub Option<Role>);
+
+pub async fn authorize<B>(
+    State(Anonymous(anonymous)): State<Anonymous>,
+    Extension(db): Extension<Database>,
+    Extension(FailedAttempts(attempts)): Extension<FailedAttempts>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let required = Role::required(request.method(), request.uri().path());
+
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .map(|value| value.to_str().ok().and_then(|value| value.strip_prefix("Bearer ")).map(|key| key.trim().to_string()));
+
+    let role = match bearer {
+        None => anonymous,
+        Some(Some(key)) => {
+            // keys aren't known to be valid yet, so guessing them is limited
+            // by address
+            let client = Client::of(&request);
+            if let Err(wait) = attempts.check(client.clone()) {
+                return too_many_requests(wait);
+            }
+
+            let found = db
+                .connection()
+                .and_then(|conn| ApiKey::authenticate(&conn, &key));
+
+            match found {
+                Ok(Some((id, role))) => {
+                    request.extensions_mut().insert(KeyId(id));
+                    Some(role)
+                },
+                Ok(None) => {
+                    let _ = attempts.take(client, 1.0);
+                    return unauthorized("Invalid or revoked API key");
+                },
+                Err(err) => return AppError(err).into_response(),
+            }
+        },
+        Some(None) => return unauthorized("Authorization must be a bearer API key"),
+    };
+
+    match role {
+        Some(role) if role >= required => next.run(request).await,
+        Some(role) => (
+            StatusCode::FORBIDDEN,
+            format!("The {role} role can't access this route, it needs the {required} role"),
+        )
+            .into_response(),
+        None => unauthorized("An API key is required"),
+    }
+}
+
+fn unauthorized(message: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"))],
+        message.to_string(),
+    )
+        .into_response()
+}
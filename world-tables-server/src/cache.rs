@@ -0,0 +1,374 @@
+//! In-process cache of GET responses
+//!
+//! Responses are kept in a bounded LRU map keyed by route and normalized
+//! query, so repeated reads like the metadata and the first pages of the lists
+//! are answered without touching SQLite. The whole cache is dropped when the
+//! database files change or a request that may have written to it succeeds.
+//!
+//! Besides the number of responses, the cache holds at most `max_bytes` of
+//! them, dropping the least recently used ones to make room, and responses
+//! over a sixteenth of that are never kept.
+
+use axum::{
+    body::{Bytes, Full, HttpBody},
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use lru::LruCache;
+use serde::Serialize;
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{AppError, Database, conditional::DataVersion};
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  CACHE  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Marks a response that must not be cached, like the ones reporting the
+/// state of the server itself
+#[derive(Clone, Copy, Debug)]
+pub struct Uncached;
+
+#[derive(Clone)]
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl CachedResponse {
+    /// Approximate bytes taken by the response
+    fn size(&self) -> usize {
+        let headers = self.headers.iter().map(|(name, value)| name.as_str().len() + value.len()).sum::<usize>();
+        self.body.len() + headers
+    }
+}
+
+struct Entries {
+    version: Option<DataVersion>,
+    responses: LruCache<String, CachedResponse>,
+    /// Bytes taken by the keys and responses
+    bytes: usize,
+}
+
+impl Entries {
+    fn clear(&mut self) {
+        self.responses.clear();
+        self.bytes = 0;
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseCache {
+    entries: Option<Arc<Mutex<Entries>>>,
+    max_bytes: usize,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheStats {
+    pub capacity: usize,
+    pub entries: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl ResponseCache {
+    /// A cache holding up to `capacity` responses and `max_bytes` of them,
+    /// disabled when either is 0
+    pub fn new(capacity: usize, max_bytes: usize) -> Self {
+        let entries = NonZeroUsize::new(capacity).filter(|_| max_bytes > 0).map(|capacity| {
+            Arc::new(Mutex::new(Entries { version: None, responses: LruCache::new(capacity), bytes: 0 }))
+        });
+
+        Self {
+            entries,
+            max_bytes,
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+
+    /// Largest response kept
+    fn max_response_bytes(&self) -> usize {
+        self.max_bytes / 16
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (capacity, entries, bytes) = self
+            .entries
+            .as_ref()
+            .map(|entries| {
+                let entries = entries.lock().unwrap();
+                (entries.responses.cap().get(), entries.responses.len(), entries.bytes)
+            })
+            .unwrap_or_default();
+
+        CacheStats {
+            capacity,
+            entries,
+            bytes,
+            max_bytes: if self.entries.is_some() { self.max_bytes } else { 0 },
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().clear();
+        }
+    }
+
+    fn get(&self, key: &str, version: DataVersion) -> Option<CachedResponse> {
+        let mut entries = self.entries.as_ref()?.lock().unwrap();
+
+        if entries.version != Some(version) {
+            entries.version = Some(version);
+            entries.clear();
+            return None;
+        }
+
+        entries.responses.get(key).cloned()
+    }
+
+    fn put(&self, key: String, version: DataVersion, response: CachedResponse) {
+        let size = key.len() + response.size();
+
+        let Some(entries) = &self.entries else { return };
+        if size > self.max_response_bytes() {
+            return;
+        }
+
+        let mut entries = entries.lock().unwrap();
+
+        // the data changed while the response was being built
+        if entries.version != Some(version) {
+            return;
+        }
+
+        if let Some(old) = entries.responses.pop(&key) {
+            entries.bytes -= key.len() + old.size();
+        }
+
+        while entries.bytes + size > self.max_bytes {
+            match entries.responses.pop_lru() {
+                Some((key, old)) => entries.bytes -= key.len() + old.size(),
+                None => break,
+            }
+        }
+
+        // a full cache drops its least recently used response
+        if let Some((key, old)) = entries.responses.push(key, response) {
+            entries.bytes -= key.len() + old.size();
+        }
+        entries.bytes += size;
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><======================  MIDDLEWARE  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Routes taking queries in the body of a POST, which don't write to the
+/// database
+pub const READ_ONLY_POSTS: &[&str] = &["/graphql"];
+
+pub async fn cached<B>(
+    Extension(cache): Extension<ResponseCache>,
+    Extension(db): Extension<Database>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        let writes = writes(request.method(), request.uri().path());
+        let response = next.run(request).await;
+        // refused requests wrote nothing, so anyone could clear the cache
+        // with them
+        if writes && response.status().is_success() {
+            cache.clear();
+        }
+        return response;
+    }
+
+    // HEAD responses lose their body on the way out, so they are not shared
+    // with the GET ones
+    if cache.entries.is_none() || request.method() == Method::HEAD {
+        return next.run(request).await;
+    }
+
+    let version = match db.data_version() {
+        Ok(version) => version,
+        Err(err) => return AppError(err).into_response(),
+    };
+
+    let key = cache_key(&request);
+
+    if let Some(CachedResponse { status, headers, body }) = cache.get(&key, version) {
+        cache.hits.fetch_add(1, Ordering::Relaxed);
+        let mut response = (status, headers, body).into_response();
+        response.headers_mut().insert("X-Cache", HeaderValue::from_static("HIT"));
+        return response;
+    }
+
+    let mut response = next.run(request).await;
+    if response.status() != StatusCode::OK || response.extensions().get::<Uncached>().is_some() {
+        return response;
+    }
+
+    cache.misses.fetch_add(1, Ordering::Relaxed);
+
+    // too large to keep, so not worth reading into memory
+    if response.body().size_hint().lower() as usize > cache.max_response_bytes() {
+        response.headers_mut().insert("X-Cache", HeaderValue::from_static("MISS"));
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => return AppError(err.into()).into_response(),
+    };
+
+    cache.put(key, version, CachedResponse { status: parts.status, headers: parts.headers.clone(), body: body.clone() });
+
+    parts.headers.insert("X-Cache", HeaderValue::from_static("MISS"));
+    Response::from_parts(parts, axum::body::boxed(Full::from(body)))
+}
+
+/// Whether a request that isn't a GET may write to the database, which the
+/// `/admin` operations never do
+fn writes(method: &Method, path: &str) -> bool {
+    *method != Method::OPTIONS && !READ_ONLY_POSTS.contains(&path) && !path.starts_with("/admin")
+}
+
+/// Route with the query parameters sorted, so their order doesn't matter, and
+/// the `Accept` header the representation was negotiated from
+fn cache_key<B>(request: &Request<B>) -> String {
+    let mut params = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .collect::<Vec<_>>();
+    params.sort_unstable();
+
+    let accept = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .unwrap_or_default();
+
+    format!("{}?{}#{accept}", request.uri().path(), params.join("&"))
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::{get, post}, Router};
+    use std::time::SystemTime;
+    use tower::ServiceExt;
+
+    const VERSION: DataVersion = DataVersion { tag: 1, modified: SystemTime::UNIX_EPOCH };
+
+    fn response(bytes: usize) -> CachedResponse {
+        CachedResponse { status: StatusCode::OK, headers: HeaderMap::new(), body: Bytes::from(vec![b'x'; bytes]) }
+    }
+
+    fn cache(capacity: usize, max_bytes: usize) -> ResponseCache {
+        let cache = ResponseCache::new(capacity, max_bytes);
+        assert!(cache.get("", VERSION).is_none());
+        cache
+    }
+
+    #[test]
+    fn responses_are_kept_within_the_byte_budget() {
+        let cache = cache(100, 1600);
+
+        for n in 0..20 {
+            cache.put(format!("/{n:02}"), VERSION, response(97));
+        }
+
+        let stats = cache.stats();
+        assert_eq!(stats.bytes, 16 * 100);
+        assert_eq!(stats.entries, 16);
+        assert!(cache.get("/03", VERSION).is_none());
+        assert!(cache.get("/04", VERSION).is_some());
+    }
+
+    #[test]
+    fn large_responses_are_not_kept() {
+        let cache = cache(100, 1600);
+
+        cache.put("/large".into(), VERSION, response(101));
+        assert!(cache.get("/large", VERSION).is_none());
+
+        cache.put("/small".into(), VERSION, response(94));
+        assert!(cache.get("/small", VERSION).is_some());
+        assert_eq!(cache.stats().bytes, 100);
+    }
+
+    #[test]
+    fn bytes_follow_replaced_and_evicted_responses() {
+        let cache = cache(2, 1600);
+
+        cache.put("/a".into(), VERSION, response(10));
+        cache.put("/a".into(), VERSION, response(20));
+        assert_eq!(cache.stats().bytes, 22);
+
+        cache.put("/b".into(), VERSION, response(30));
+        cache.put("/c".into(), VERSION, response(40));
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.stats().bytes, 32 + 42);
+
+        cache.clear();
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn only_successful_writes_clear_the_cache() {
+        let cache = ResponseCache::new(100, 1600);
+        let app = Router::new()
+            .route("/data", get(|| async { "data" }))
+            .route("/edit", post(|| async { "edited" }))
+            .route("/refused", post(|| async { StatusCode::UNAUTHORIZED }))
+            .route("/admin/backup", post(|| async { "copied" }))
+            .layer(middleware::from_fn(cached))
+            .layer(Extension(cache.clone()))
+            .layer(Extension(Database::temporary("cache-writes")));
+
+        let send = |method: Method, path: &'static str| {
+            let app = app.clone();
+            async move { app.oneshot(Request::builder().method(method).uri(path).body(Body::empty()).unwrap()).await.unwrap() }
+        };
+
+        send(Method::GET, "/data").await;
+        assert_eq!(cache.stats().entries, 1);
+
+        for path in ["/refused", "/admin/backup"] {
+            send(Method::POST, path).await;
+            assert_eq!(cache.stats().entries, 1, "cleared by {path}");
+        }
+        send(Method::OPTIONS, "/edit").await;
+        assert_eq!(cache.stats().entries, 1);
+
+        send(Method::POST, "/edit").await;
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
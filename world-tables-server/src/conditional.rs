@@ -0,0 +1,297 @@
+//! Conditional GET support
+//!
+//! Every successful GET response is tagged with a strong `ETag`, derived from
+//! the data version of the database and the request, and with the
+//! `Last-Modified` time of the database files. Requests revalidating with
+//! an `If-None-Match` naming the current tag get a `304 Not Modified` without
+//! running the handler, as the tag was only ever given to a `200 OK` of the
+//! same request. With `If-None-Match: *` or `If-Modified-Since` the handler
+//! runs, and only a `200 OK` it answers is turned into a `304`.
+//!
+//! Routes registered as uncached, which report the state of the server rather
+//! than data, are never answered `304`.
+
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{AppError, Database, cache::Uncached, metrics::Routes};
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><======================  DATA VERSION  ========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Version of the data in the database files
+///
+/// Any write to the database touches the main file or its write-ahead log, so
+/// the size and modification time of both identify the current data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DataVersion {
+    pub tag: u64,
+    pub modified: SystemTime,
+}
+
+impl DataVersion {
+    pub fn of(path: &Path) -> Result<Self> {
+        let mut hasher = DefaultHasher::new();
+        let mut modified = SystemTime::UNIX_EPOCH;
+
+        let mut wal = path.as_os_str().to_owned();
+        wal.push("-wal");
+
+        for file in [path, Path::new(&wal)] {
+            // the write-ahead log only exists while there are connections
+            let Ok(metadata) = std::fs::metadata(file) else { continue };
+            let file_modified = metadata.modified()?;
+
+            metadata.len().hash(&mut hasher);
+            file_modified.hash(&mut hasher);
+            modified = modified.max(file_modified);
+        }
+
+        Ok(Self { tag: hasher.finish(), modified })
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><======================  MIDDLEWARE  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// How long clients may reuse a response before revalidating it, and the
+/// routes it doesn't apply to
+#[derive(Clone)]
+pub struct CachePolicy {
+    pub max_age: Duration,
+    pub uncached: Routes,
+}
+
+/// What the request headers say of the current representation
+#[derive(Debug, PartialEq, Eq)]
+enum Freshness {
+    /// Nothing, or the client has an outdated one
+    Stale,
+    /// The client has the current one, the tag being that of a `200 OK`
+    Current,
+    /// The client has the current one if there is one, as for `*` or a date
+    CurrentIfOk,
+}
+
+pub async fn conditional<B>(
+    State(policy): State<CachePolicy>,
+    Extension(db): Extension<Database>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) || policy.uncached.contains(request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let version = match db.data_version() {
+        Ok(version) => version,
+        Err(err) => return AppError(err).into_response(),
+    };
+
+    let etag = entity_tag(&version, &request);
+    let freshness = freshness(request.headers(), &etag, version.modified);
+
+    if freshness == Freshness::Current {
+        return not_modified(&etag, &version, &policy);
+    }
+
+    let mut response = next.run(request).await;
+    if response.status() == StatusCode::OK && response.extensions().get::<Uncached>().is_none() {
+        if freshness == Freshness::CurrentIfOk {
+            return not_modified(&etag, &version, &policy);
+        }
+
+        insert_validators(response.headers_mut(), &etag, &version, &policy);
+    }
+
+    response
+}
+
+fn not_modified(etag: &str, version: &DataVersion, policy: &CachePolicy) -> Response {
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
+    insert_validators(response.headers_mut(), etag, version, policy);
+    response
+}
+
+/// Strong entity tag of the response to a request, for the given data version
+///
+/// The representation depends on the path, the query and the negotiated media
+/// type and encoding, so all of them go into the tag.
+fn entity_tag<B>(version: &DataVersion, request: &Request<B>) -> String {
+    let mut hasher = DefaultHasher::new();
+
+    version.tag.hash(&mut hasher);
+    request.uri().path().hash(&mut hasher);
+    request.uri().query().hash(&mut hasher);
+
+    for name in [header::ACCEPT, header::ACCEPT_ENCODING] {
+        request.headers().get(name).map(HeaderValue::as_bytes).hash(&mut hasher);
+    }
+
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+fn freshness(headers: &HeaderMap, etag: &str, modified: SystemTime) -> Freshness {
+    // If-Modified-Since is ignored when If-None-Match is present
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        let tags = if_none_match.to_str().unwrap_or_default().split(',').map(str::trim).collect::<Vec<_>>();
+
+        return if tags.iter().any(|tag| tag.trim_start_matches("W/") == etag) {
+            Freshness::Current
+        } else if tags.contains(&"*") {
+            Freshness::CurrentIfOk
+        } else {
+            Freshness::Stale
+        };
+    }
+
+    let unmodified = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| httpdate::parse_http_date(since).ok())
+        .map(|since| truncate_to_seconds(modified) <= since)
+        .unwrap_or(false);
+
+    match unmodified {
+        true => Freshness::CurrentIfOk,
+        false => Freshness::Stale,
+    }
+}
+
+fn insert_validators(headers: &mut HeaderMap, etag: &str, version: &DataVersion, policy: &CachePolicy) {
+    let cache_control = format!("max-age={}", policy.max_age.as_secs());
+
+    if let Ok(etag) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, etag);
+    }
+    if let Ok(modified) = HeaderValue::from_str(&httpdate::fmt_http_date(version.modified)) {
+        headers.insert(header::LAST_MODIFIED, modified);
+    }
+    if let Ok(cache_control) = HeaderValue::from_str(&cache_control) {
+        headers.insert(header::CACHE_CONTROL, cache_control);
+    }
+    headers.insert(header::VARY, HeaderValue::from_static("Accept"));
+}
+
+/// HTTP dates have a resolution of seconds
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or_default();
+
+    SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::HeaderName, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn app(name: &str) -> Router {
+        let policy = CachePolicy {
+            max_age: Duration::from_secs(60),
+            uncached: Routes::new(&["/state".to_string(), "/state/:key".to_string()]),
+        };
+
+        Router::new()
+            .route("/data", get(|| async { "data" }))
+            .route("/missing", get(|| async { StatusCode::NOT_FOUND }))
+            .route("/state", get(|| async { "state" }))
+            .route("/state/:key", get(|| async { "state" }))
+            .route("/marked", get(|| async { (Extension(Uncached), "marked") }))
+            .layer(middleware::from_fn_with_state(policy, conditional))
+            .layer(Extension(Database::temporary(name)))
+    }
+
+    async fn send(app: &Router, path: &str, headers: &[(HeaderName, &str)]) -> Response {
+        let mut request = Request::get(path);
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+
+        app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    fn future_date() -> String {
+        httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(3600))
+    }
+
+    #[tokio::test]
+    async fn current_tag_is_not_modified() {
+        let app = app("conditional-tag");
+
+        let response = send(&app, "/data", &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+
+        let response = send(&app, "/data", &[(header::IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+
+        let response = send(&app, "/data", &[(header::IF_NONE_MATCH, &format!("W/{etag}"))]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn other_tag_or_query_is_modified() {
+        let app = app("conditional-other");
+
+        let etag = send(&app, "/data", &[]).await.headers()[header::ETAG].to_str().unwrap().to_string();
+
+        let response = send(&app, "/data", &[(header::IF_NONE_MATCH, "\"0000000000000000\"")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(&app, "/data?page=2", &[(header::IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn wildcard_and_date_need_a_representation() {
+        let app = app("conditional-wildcard");
+
+        for header in [(header::IF_NONE_MATCH, "*".to_string()), (header::IF_MODIFIED_SINCE, future_date())] {
+            let response = send(&app, "/data", &[(header.0.clone(), &header.1)]).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{header:?}");
+
+            let response = send(&app, "/missing", &[(header.0.clone(), &header.1)]).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{header:?}");
+        }
+
+        let past = httpdate::fmt_http_date(SystemTime::UNIX_EPOCH);
+        let response = send(&app, "/data", &[(header::IF_MODIFIED_SINCE, &past)]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn uncached_routes_always_run() {
+        let app = app("conditional-uncached");
+
+        for path in ["/state", "/state/key", "/marked"] {
+            for header in [(header::IF_NONE_MATCH, "*".to_string()), (header::IF_MODIFIED_SINCE, future_date())] {
+                let response = send(&app, path, &[(header.0.clone(), &header.1)]).await;
+                assert_eq!(response.status(), StatusCode::OK, "{path} {header:?}");
+                assert!(response.headers().get(header::ETAG).is_none(), "{path} {header:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Cross-origin requests
+//!
+//! Browser frontends on other origins can only call the API from the origins
+//! given with `--cors-origin`, and their scripts only see the response headers
+//! exposed here, which include all the custom ones the routes send.
+
+use anyhow::{Context, Result};
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::trace::REQUEST_ID;
+
+/// Headers scripts may read besides the CORS-safelisted ones
+pub const EXPOSED_HEADERS: &[&str] = &[
+    "Pagination-Count",
+    "Pagination-Total-Count",
+    "Pagination-Page",
+    "Pagination-Limit",
+    "Pagination-Total-Pages",
+    "Countries-Count",
+    "States-Count",
+    "Cities-Count",
+    "Subregions-Count",
+    "Keys-Count",
+    "Missing-Count",
+    "Missing-Keys",
+    "ETag",
+    "Retry-After",
+    "WWW-Authenticate",
+    "Deprecation",
+    "Link",
+    "X-Cache",
+    "X-Request-Id",
+];
+
+/// How long browsers may reuse the answer to a preflight request
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(600);
+
+/// Layer answering the cross-origin requests from `origins`, or `None` when
+/// there are none to allow
+///
+/// A `*` origin allows any of them.
+pub fn layer(origins: &[String], methods: &[String], exposed_headers: &[String]) -> Result<Option<CorsLayer>> {
+    if origins.is_empty() {
+        return Ok(None);
+    }
+
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin).with_context(|| format!("invalid CORS origin: {origin}")))
+                .collect::<Result<Vec<_>>>()?,
+        )
+    };
+
+    let methods = methods
+        .iter()
+        .map(|method| Method::from_bytes(method.to_uppercase().as_bytes()).with_context(|| format!("invalid CORS method: {method}")))
+        .collect::<Result<Vec<_>>>()?;
+
+    let exposed_headers = EXPOSED_HEADERS
+        .iter()
+        .copied()
+        .chain(exposed_headers.iter().map(String::as_str))
+        .map(|name| HeaderName::from_bytes(name.as_bytes()).with_context(|| format!("invalid CORS exposed header: {name}")))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(methods)
+            // request headers besides the CORS-safelisted ones
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_NONE_MATCH,
+                header::IF_MODIFIED_SINCE,
+                HeaderName::from_static("last-event-id"),
+                REQUEST_ID,
+            ])
+            .expose_headers(exposed_headers)
+            .max_age(PREFLIGHT_MAX_AGE),
+    ))
+}
@@ -0,0 +1,252 @@
+//! Change notifications
+//!
+//! Triggers on the entity tables record every create, update and delete in the
+//! `changes` table, whichever process writes to the database. A task polls that
+//! log and publishes the new changes to the `/events` Server-Sent Events
+//! stream. Each event carries the id of its change, so clients reconnecting
+//! with `Last-Event-ID` get the changes they missed, or a `lagged` event when
+//! the log no longer has all of them.
+
+use anyhow::Result;
+use axum::{
+    http::HeaderMap,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension,
+};
+use log::warn;
+use rusqlite::Connection;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    StreamExt,
+};
+
+use world_tables_base::{Change, Int};
+
+use crate::{AppError, Database, cache::Uncached};
+
+/// Changes buffered for each subscriber before it lags behind
+const CHANNEL_CAPACITY: usize = 1024;
+/// Most changes read from the log at once
+const POLL_BATCH: usize = 1000;
+/// Changes kept in the log for clients catching up
+const CHANGES_KEPT: usize = 10_000;
+
+/// Publisher of the changes to the database
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Change>,
+}
+
+impl Events {
+    /// Starts polling the change log every `interval`, publishing the changes
+    /// made from now on
+    pub fn spawn(db: Database, interval: Duration) -> Result<Self> {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let mut last = Change::last_id(&*db.connection()?)?;
+
+        let publisher = sender.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                match publish(&db, &publisher, last) {
+                    Ok(id) => last = id,
+                    Err(err) => warn!("Failed publishing changes: {err:#}"),
+                }
+            }
+        });
+
+        Ok(Self { sender })
+    }
+}
+
+/// Publishes the changes after `last`, returning the id of the latest one
+fn publish(db: &Database, sender: &broadcast::Sender<Change>, mut last: Int) -> Result<Int> {
+    let conn = db.connection()?;
+    let first = last;
+
+    loop {
+        let changes = Change::since(&conn, last, POLL_BATCH)?;
+        let count = changes.len();
+
+        for change in changes {
+            last = change.id;
+            // there may be no subscribers at the moment
+            let _ = sender.send(change);
+        }
+
+        if count < POLL_BATCH {
+            break;
+        }
+    }
+
+    // only write to the database after someone else did, so the data version
+    // doesn't change on every poll
+    if last != first {
+        Change::prune(&conn, CHANGES_KEPT)?;
+    }
+
+    Ok(last)
+}
+
+pub async fn events(
+    Extension(events): Extension<Events>,
+    Extension(db): Extension<Database>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    // subscribe before reading the missed changes, so none falls in between
+    let receiver = events.sender.subscribe();
+
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse::<Int>().ok());
+
+    let replay = match last_event_id {
+        Some(id) => replay(&*db.connection()?, id)?,
+        None => Replay::Missed(Vec::new()),
+    };
+
+    // a client that lost changes starts over from what is published next
+    let (first, caught_up) = match replay {
+        Replay::Missed(missed) => {
+            let caught_up = missed.last().map(|change| change.id).or(last_event_id).unwrap_or_default();
+            (missed.iter().map(change_event).collect::<Vec<_>>(), caught_up)
+        },
+        Replay::Lost(count) => (vec![Ok(lagged_event(count))], 0),
+    };
+
+    let live = BroadcastStream::new(receiver).filter_map(move |change| match change {
+        Ok(change) if change.id > caught_up => Some(change_event(&change)),
+        Ok(_) => None,
+        // the client has to reload what it shows, as some changes were dropped
+        Err(BroadcastStreamRecvError::Lagged(count)) => Some(Ok(lagged_event(count))),
+    });
+
+    let stream = tokio_stream::iter(first).chain(live);
+
+    // the stream never ends, so it must not be buffered for the cache
+    Ok((Extension(Uncached), Sse::new(stream).keep_alive(KeepAlive::default())))
+}
+
+/// Changes made after the one with `id`, as far as the log still has them
+#[derive(Debug)]
+enum Replay {
+    Missed(Vec<Change>),
+    /// Some changes were pruned from the log, or are too many to replay
+    Lost(u64),
+}
+
+fn replay(conn: &Connection, id: Int) -> Result<Replay> {
+    let first = Change::first_id(conn)?;
+    let last = Change::last_id(conn)?;
+    let missed = Change::since(conn, id, CHANGES_KEPT + 1)?;
+
+    // an id past the last change is from before the log was emptied
+    if first > id.saturating_add(1) || id > last || missed.len() > CHANGES_KEPT {
+        return Ok(Replay::Lost(last.saturating_sub(id) as u64));
+    }
+
+    Ok(Replay::Missed(missed))
+}
+
+fn lagged_event(count: u64) -> Event {
+    Event::default().event("lagged").data(count.to_string())
+}
+
+fn change_event(change: &Change) -> Result<Event, serde_json::Error> {
+    Event::default()
+        .id(change.id.to_string())
+        .event("change")
+        .json_data(change)
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::{Body, HttpBody}, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    /// Database with a change logged for each of `count` new currencies
+    fn database(name: &str, count: usize) -> Database {
+        let db = Database::temporary(name);
+
+        db.connection().unwrap().execute(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?)
+            INSERT INTO currencies (iso, name, symbol) SELECT 'C' || i, 'Currency ' || i, '$' FROM n",
+            [count],
+        ).unwrap();
+
+        db
+    }
+
+    fn ids(replay: Replay) -> Vec<Int> {
+        match replay {
+            Replay::Missed(missed) => missed.iter().map(|change| change.id).collect(),
+            Replay::Lost(count) => panic!("{count} changes lost"),
+        }
+    }
+
+    #[test]
+    fn missed_changes_are_replayed() {
+        let db = database("events-missed", 3);
+        let conn = db.connection().unwrap();
+        let first = Change::first_id(&conn).unwrap();
+
+        assert_eq!(ids(replay(&conn, first).unwrap()), [first + 1, first + 2]);
+        assert!(ids(replay(&conn, first + 2).unwrap()).is_empty());
+        assert_eq!(ids(replay(&conn, first - 1).unwrap()).len(), 3);
+    }
+
+    #[test]
+    fn lost_changes_are_not_replayed() {
+        let db = database("events-lost", 3);
+        let conn = db.connection().unwrap();
+        let first = Change::first_id(&conn).unwrap();
+        Change::prune(&conn, 1).unwrap();
+
+        // pruned from the log
+        assert!(matches!(replay(&conn, first).unwrap(), Replay::Lost(2)));
+        assert_eq!(ids(replay(&conn, first + 1).unwrap()), [first + 2]);
+
+        // from a log that was emptied since
+        assert!(matches!(replay(&conn, first + 10).unwrap(), Replay::Lost(0)));
+
+        // more than are kept
+        let db = database("events-many", CHANGES_KEPT + 2);
+        let conn = db.connection().unwrap();
+        let first = Change::first_id(&conn).unwrap();
+        assert!(matches!(replay(&conn, first).unwrap(), Replay::Lost(count) if count == CHANGES_KEPT as u64 + 1));
+    }
+
+    #[tokio::test]
+    async fn clients_that_lost_changes_are_told_first() {
+        let db = database("events-stream", 3);
+        let conn = db.connection().unwrap();
+        let first = Change::first_id(&conn).unwrap();
+        Change::prune(&conn, 1).unwrap();
+        drop(conn);
+
+        let app = Router::new()
+            .route("/events", get(events))
+            .layer(Extension(Events::spawn(db.clone(), Duration::from_secs(60)).unwrap()))
+            .layer(Extension(db));
+
+        let request = Request::get("/events").header("Last-Event-ID", first.to_string()).body(Body::empty()).unwrap();
+        let mut body = app.oneshot(request).await.unwrap().into_body();
+        let event = body.data().await.unwrap().unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&event), "event:lagged\ndata:2\n\n");
+    }
+}
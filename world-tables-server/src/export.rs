@@ -0,0 +1,286 @@
+//! Full table exports
+//!
+//! `/export/:entity` streams every row of a table as NDJSON, or as CSV when
+//! asked for with `format=csv` or `Accept: text/csv`. The rows are read with a
+//! single cursor and serialized as they are stepped through, and the body is
+//! sent in chunks through a small channel, so an export takes the same memory
+//! whatever the size of the table, waiting for slow clients instead of
+//! buffering for them. The chunks are compressed on the fly like any other
+//! response.
+//!
+//! An export holds a pooled connection and a read snapshot until it's done, so
+//! fewer of them than the pool has connections run at once, and the clients
+//! that stop reading for `SEND_TIMEOUT` are cut off.
+
+use anyhow::Result;
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::Path,
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    runtime::Handle,
+    sync::{mpsc::{self, error::SendTimeoutError}, OwnedSemaphorePermit, Semaphore},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, error};
+
+use world_tables_base::{Selectable, Country, State, City, WorldRegion, WorldSubregion, Currency};
+
+use crate::{AppError, Database, cache::Uncached, format::{CsvRecord, Format}, metrics::timed, v1};
+
+/// Entities that can be exported, by the name in the route
+pub const ENTITIES: &[&str] = &["countries", "states", "cities", "regions", "subregions", "currencies"];
+
+/// Bytes serialized before they are sent as a chunk of the body
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks waiting for the client before the export pauses
+const BUFFERED_CHUNKS: usize = 4;
+
+/// Wait for a paused client before the export is stopped
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Chunks = ReceiverStream<io::Result<Bytes>>;
+
+/// Exports allowed to run at once, shared with the gRPC streams
+#[derive(Clone)]
+pub struct Exports(Arc<Semaphore>);
+
+impl Exports {
+    /// Allows half the connections of a pool of `pool_size` to exports
+    pub fn new(pool_size: u32) -> Self {
+        Self(Arc::new(Semaphore::new((pool_size as usize / 2).max(1))))
+    }
+
+    /// Permit to run an export until it's dropped, `None` when too many of
+    /// them are running
+    pub fn try_start(&self) -> Option<OwnedSemaphorePermit> {
+        self.0.clone().try_acquire_owned().ok()
+    }
+}
+
+pub async fn export(
+    Path(entity): Path<String>,
+    format: Format,
+    Extension(db): Extension<Database>,
+    Extension(exports): Extension<Exports>,
+) -> Result<Response, AppError> {
+    // JSON is what clients get when they don't ask for a format, and a table
+    // is exported as one JSON object per line
+    let format = match format {
+        Format::Csv => Format::Csv,
+        Format::Json | Format::Ndjson => Format::Ndjson,
+        _ => return Ok((StatusCode::NOT_ACCEPTABLE, "Exports are only available as NDJSON or CSV").into_response()),
+    };
+
+    if !ENTITIES.contains(&entity.as_str()) {
+        return Ok((StatusCode::NOT_FOUND, format!("No entity to export named {entity}")).into_response());
+    }
+
+    let Some(permit) = exports.try_start() else {
+        return Ok((
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, HeaderValue::from(SEND_TIMEOUT.as_secs()))],
+            "Too many exports running, try again later",
+        )
+            .into_response());
+    };
+
+    let body = match entity.as_str() {
+        "countries" => stream::<Country, v1::Country>(&db, format, permit)?,
+        "states" => stream::<State, v1::State>(&db, format, permit)?,
+        "cities" => stream::<City, v1::City>(&db, format, permit)?,
+        "regions" => stream::<WorldRegion, v1::WorldRegion>(&db, format, permit)?,
+        "subregions" => stream::<WorldSubregion, v1::WorldSubregion>(&db, format, permit)?,
+        _ => stream::<Currency, v1::Currency>(&db, format, permit)?,
+    };
+
+    let extension = if format == Format::Csv { "csv" } else { "ndjson" };
+    let disposition = HeaderValue::from_str(&format!("attachment; filename=\"{entity}.{extension}\""))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(format.content_type())),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        // a whole table is no response to keep in memory
+        Extension(Uncached),
+        StreamBody::new(body),
+    )
+        .into_response())
+}
+
+/// Starts writing every object of `T`, as `D`, to the returned stream of
+/// chunks, keeping `permit` until it's done
+fn stream<T, D>(db: &Database, format: Format, permit: OwnedSemaphorePermit) -> Result<Chunks>
+where
+    T: Selectable + Send + 'static,
+    D: From<T> + Serialize + CsvRecord,
+{
+    let conn = db.connection()?;
+    let (tx, rx) = mpsc::channel(BUFFERED_CHUNKS + 1);
+    // a slot for the error cutting the body short, which can't wait for a
+    // client that stopped reading
+    let error_slot = tx.clone().try_reserve_owned()?;
+    let span = tracing::Span::current();
+    let runtime = Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        let _span = span.enter();
+        let _permit = permit;
+        let mut sender = ChunkSender { chunk: Vec::with_capacity(CHUNK_SIZE), tx, runtime, timed_out: false };
+
+        if let Err(err) = write::<T, D>(&conn, format, &mut sender) {
+            if sender.tx.is_closed() {
+                debug!("Export stopped, the client went away");
+            } else if sender.timed_out {
+                debug!("Export stopped, the client stopped reading");
+                error_slot.send(Err(io::Error::new(io::ErrorKind::TimedOut, "the client stopped reading")));
+            } else {
+                // the status was already sent, so the body is cut short
+                error!("Export failed: {err:#}");
+                error_slot.send(Err(io::Error::other(err.to_string())));
+            }
+        }
+    });
+
+    Ok(ReceiverStream::new(rx))
+}
+
+fn write<T, D>(conn: &Connection, format: Format, sender: &mut ChunkSender) -> Result<()>
+where
+    T: Selectable,
+    D: From<T> + Serialize + CsvRecord,
+{
+    let fields = T::FIELDS.iter().map(|(field, _)| *field).collect::<Vec<_>>();
+
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut *sender);
+            writer.write_record(D::csv_headers())?;
+
+            timed!(T::select_each(conn, &fields, &[], |object| {
+                writer.write_record(D::from(object).csv_record())?;
+                Ok(true)
+            }))?;
+
+            writer.flush()?;
+        },
+        _ => {
+            timed!(T::select_each(conn, &fields, &[], |object| {
+                serde_json::to_writer(&mut *sender, &D::from(object))?;
+                sender.write_all(b"\n")?;
+                Ok(true)
+            }))?;
+        },
+    }
+
+    sender.flush()?;
+    Ok(())
+}
+
+/// Writer sending what is written to it through the channel in chunks of
+/// about `CHUNK_SIZE` bytes, blocking while the channel is full for at most
+/// `SEND_TIMEOUT`
+struct ChunkSender {
+    chunk: Vec<u8>,
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    runtime: Handle,
+    timed_out: bool,
+}
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.chunk.extend_from_slice(buf);
+
+        if self.chunk.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = std::mem::replace(&mut self.chunk, Vec::with_capacity(CHUNK_SIZE));
+
+        self.runtime
+            .block_on(self.tx.send_timeout(Ok(Bytes::from(chunk)), SEND_TIMEOUT))
+            .map_err(|err| match err {
+                SendTimeoutError::Timeout(_) => {
+                    self.timed_out = true;
+                    io::Error::new(io::ErrorKind::TimedOut, "the client stopped reading")
+                },
+                SendTimeoutError::Closed(_) => io::Error::new(io::ErrorKind::BrokenPipe, "the client went away"),
+            })
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn app(name: &str, exports: &Exports) -> Router {
+        Router::new()
+            .route("/export/:entity", get(export))
+            .layer(Extension(exports.clone()))
+            .layer(Extension(Database::seeded(name)))
+    }
+
+    async fn send(app: &Router, path: &str) -> (StatusCode, String) {
+        let response = app.clone().oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn every_row_is_streamed() {
+        let app = app("export-rows", &Exports::new(2));
+
+        let (status, body) = send(&app, "/export/currencies").await;
+        assert_eq!(status, StatusCode::OK);
+        let rows = body
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["symbol"], "€");
+
+        let (status, body) = send(&app, "/export/currencies?format=csv").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "iso,name,symbol\nBRL,Brazilian real,R$\nEUR,Euro,€\n");
+    }
+
+    #[tokio::test]
+    async fn exports_over_the_limit_are_refused() {
+        let exports = Exports::new(2);
+        let app = app("export-limit", &exports);
+
+        let running = exports.try_start().unwrap();
+        assert_eq!(send(&app, "/export/currencies").await.0, StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(running);
+        assert_eq!(send(&app, "/export/currencies").await.0, StatusCode::OK);
+    }
+}
@@ -0,0 +1,182 @@
+//! Sparse fieldsets
+//!
+//! The `fields` query parameter names the fields of the objects to respond
+//! with, like `?fields=iso2,name,latitude`. Only the columns of those fields are
+//! selected and the other fields are left out of the response, instead of
+//! showing up with default values.
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+};
+use serde::Deserialize;
+use std::marker::PhantomData;
+
+use world_tables_base::Selectable;
+
+use crate::format::Format;
+
+/// Fields asked for objects of type `T`, checked against the ones it has
+#[derive(Debug)]
+pub struct Fields<T> {
+    /// Fields read from the database, `None` for all of them
+    selection: Option<Vec<&'static str>>,
+    /// Fields kept in the response, included relations among them
+    output: Option<Vec<String>>,
+    object: PhantomData<T>,
+}
+
+impl<T> Fields<T> {
+    pub fn selection(&self) -> Option<&[&'static str]> {
+        self.selection.as_deref()
+    }
+
+    pub fn output(&self) -> Option<&[String]> {
+        self.output.as_deref()
+    }
+}
+
+impl<T: Selectable> Fields<T> {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut selection = Vec::new();
+
+        for name in text.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let field = Self::field(name).ok_or(format!(
+                "Unknown field: {name}, expected some of: {}",
+                T::FIELDS.iter().map(|(field, _)| *field).collect::<Vec<_>>().join(", "),
+            ))?;
+
+            if !selection.contains(&field) {
+                selection.push(field);
+            }
+        }
+
+        let selection = (!selection.is_empty()).then_some(selection);
+
+        let output = selection
+            .as_ref()
+            .map(|selection| selection.iter().map(ToString::to_string).collect());
+
+        Ok(Self { selection, output, object: PhantomData })
+    }
+
+    fn field(name: &str) -> Option<&'static str> {
+        T::FIELDS.iter().map(|(field, _)| *field).find(|field| *field == name)
+    }
+
+    /// Keeps the relations of the `include` query parameter in the response,
+    /// also reading the key of the single object ones
+    fn include(&mut self, includes: &str) {
+        let (Some(selection), Some(output)) = (&mut self.selection, &mut self.output) else { return };
+
+        for relation in includes.split(',').filter_map(|include| include.split(':').next()) {
+            let relation = relation.trim();
+
+            if let Some(field) = Self::field(relation) {
+                if !selection.contains(&field) {
+                    selection.push(field);
+                }
+            }
+
+            // unknown relations are rejected by the `Includes` extractor
+            if !output.iter().any(|field| field == relation) {
+                output.push(relation.to_string());
+            }
+        }
+    }
+
+    /// Reads the fields GeoJSON geometries are made of, without responding
+    /// with them as properties
+    fn locate(&mut self) {
+        let Some(selection) = &mut self.selection else { return };
+
+        for name in ["latitude", "longitude"] {
+            if let Some(field) = Self::field(name) {
+                if !selection.contains(&field) {
+                    selection.push(field);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FieldsQuery {
+    fields: Option<String>,
+    include: Option<String>,
+}
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for Fields<T>
+where
+    S: Send + Sync,
+    T: Selectable + Send,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<FieldsQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+        let mut fields = Self::parse(query.fields.as_deref().unwrap_or_default())
+            .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+        if let Some(includes) = &query.include {
+            fields.include(includes);
+        }
+
+        if let Ok(Format::GeoJson(_)) = Format::from_request_parts(parts, state).await {
+            fields.locate();
+        }
+
+        Ok(fields)
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use world_tables_base::Country;
+
+    async fn extract(uri: &str) -> Result<Fields<Country>, (StatusCode, String)> {
+        let (mut parts, _) = Request::get(uri).body(()).unwrap().into_parts();
+
+        Fields::<Country>::from_request_parts(&mut parts, &()).await
+    }
+
+    #[test]
+    fn only_known_fields_are_selected() {
+        let fields = Fields::<Country>::parse(" name, iso2,,name ").unwrap();
+        assert_eq!(fields.selection(), Some(&["name", "iso2"][..]));
+        assert_eq!(fields.output(), Some(&["name".to_string(), "iso2".to_string()][..]));
+
+        let err = Fields::<Country>::parse("name,iso2 FROM api_keys --").unwrap_err();
+        assert!(err.starts_with("Unknown field: iso2 FROM api_keys --"));
+
+        assert!(Fields::<Country>::parse("").unwrap().selection().is_none());
+    }
+
+    #[tokio::test]
+    async fn included_and_located_fields_are_selected() {
+        let fields = extract("/countries?fields=name&include=currency,states:name").await.unwrap();
+        assert_eq!(fields.selection(), Some(&["name", "currency"][..]));
+        assert_eq!(
+            fields.output(),
+            Some(&["name".to_string(), "currency".to_string(), "states".to_string()][..]),
+        );
+
+        let fields = extract("/countries?fields=name&format=geojson").await.unwrap();
+        assert_eq!(fields.selection(), Some(&["name", "latitude", "longitude"][..]));
+        assert_eq!(fields.output(), Some(&["name".to_string()][..]));
+
+        let (status, _) = extract("/countries?fields=name,password").await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
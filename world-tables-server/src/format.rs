@@ -0,0 +1,527 @@
+use anyhow::Result;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::Value;
+use std::fmt::{self, Display};
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  FORMAT  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Representation of a response body, negotiated from the `Accept` header or
+/// forced with the `format` query parameter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+    Ndjson,
+    MsgPack,
+    GeoJson(MissingGeometry),
+}
+
+/// What to do with objects without coordinates in GeoJSON responses, chosen
+/// with the `geometry` query parameter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingGeometry {
+    /// Leave the object out of the feature collection
+    #[default]
+    Skip,
+    /// Keep the object as a feature with a `null` geometry
+    Null,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "ndjson" => Some(Self::Ndjson),
+            "msgpack" => Some(Self::MsgPack),
+            "geojson" => Some(Self::GeoJson(Default::default())),
+            _ => None,
+        }
+    }
+
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "*/*" | "application/*" | "application/json" => Some(Self::Json),
+            "text/csv" => Some(Self::Csv),
+            "application/x-ndjson" => Some(Self::Ndjson),
+            "application/msgpack" | "application/x-msgpack" => Some(Self::MsgPack),
+            "application/geo+json" => Some(Self::GeoJson(Default::default())),
+            _ => None,
+        }
+    }
+
+    /// Picks the first supported media type of an `Accept` header value, in
+    /// order of preference
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let mut ranges = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let media_type = params.next()?.trim().to_ascii_lowercase();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+
+                (quality > 0.0).then_some((media_type, quality))
+            })
+            .collect::<Vec<_>>();
+
+        // stable sort keeps the header order for equal qualities
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges.iter().find_map(|(media_type, _)| Self::from_media_type(media_type))
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+            Self::MsgPack => "application/msgpack",
+            Self::GeoJson(_) => "application/geo+json",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+    geometry: Option<MissingGeometry>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Format
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<FormatQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+        let format = match (query.format, parts.headers.get(header::ACCEPT)) {
+            (Some(name), _) => Self::from_name(&name)
+                .ok_or((StatusCode::BAD_REQUEST, format!("Unknown format: {name}")))?,
+            (None, None) => Self::Json,
+            (None, Some(accept)) => {
+                let accept = accept
+                    .to_str()
+                    .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Accept header".to_string()))?;
+
+                Self::from_accept(accept)
+                    .ok_or((StatusCode::NOT_ACCEPTABLE, format!("No supported media type in: {accept}")))?
+            },
+        };
+
+        match (format, query.geometry) {
+            (Self::GeoJson(_), Some(missing)) => Ok(Self::GeoJson(missing)),
+            _ => Ok(format),
+        }
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  RESPONSE  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Builds the response for a list of objects in the given format, keeping the
+/// headers like the pagination ones
+///
+/// With `fields`, only those fields of each object are kept.
+pub fn list_response<D>(format: Format, mut headers: HeaderMap, objects: Vec<D>, fields: Option<&[String]>) -> Result<Response>
+where
+    D: Serialize + CsvRecord + Geometry,
+{
+    let body = match format {
+        Format::Json | Format::Ndjson | Format::MsgPack => match fields {
+            Some(fields) => {
+                let objects = objects
+                    .iter()
+                    .map(|object| sparse(object, fields))
+                    .collect::<Result<Vec<_>>>()?;
+                serialized_list(format, &objects)?
+            },
+            None => serialized_list(format, &objects)?,
+        },
+        Format::Csv => {
+            let columns = csv_columns::<D>(fields);
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(select(D::csv_headers(), &columns))?;
+            for object in &objects {
+                writer.write_record(select(&object.csv_record(), &columns))?;
+            }
+            writer.into_inner()?
+        },
+        Format::GeoJson(_) if !D::LOCATED => return Ok(not_located()),
+        Format::GeoJson(missing) => {
+            let features = objects
+                .iter()
+                .filter(|object| missing == MissingGeometry::Null || object.coordinates().is_some())
+                .map(|object| feature(object, fields))
+                .collect::<Result<Vec<_>>>()?;
+
+            serde_json::to_vec(&GeoJson::FeatureCollection { features })?
+        },
+    };
+
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+
+    Ok((headers, body).into_response())
+}
+
+/// Builds the response for a single object in the given format, keeping the
+/// headers like the counts ones
+///
+/// A GeoJSON object is always a feature, with a `null` geometry when it has no
+/// coordinates. With `fields`, only those fields of the object are kept.
+pub fn object_response<D>(format: Format, mut headers: HeaderMap, object: D, fields: Option<&[String]>) -> Result<Response>
+where
+    D: Serialize + CsvRecord + Geometry,
+{
+    let body = match format {
+        Format::Json | Format::Ndjson | Format::MsgPack => match fields {
+            Some(fields) => serialized_object(format, &sparse(&object, fields)?)?,
+            None => serialized_object(format, &object)?,
+        },
+        Format::Csv => {
+            let columns = csv_columns::<D>(fields);
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(select(D::csv_headers(), &columns))?;
+            writer.write_record(select(&object.csv_record(), &columns))?;
+            writer.into_inner()?
+        },
+        Format::GeoJson(_) if !D::LOCATED => return Ok(not_located()),
+        Format::GeoJson(_) => serde_json::to_vec(&feature(&object, fields)?)?,
+    };
+
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+
+    Ok((headers, body).into_response())
+}
+
+/// Body of a list in one of the serde formats
+fn serialized_list<D: Serialize>(format: Format, objects: &[D]) -> Result<Vec<u8>> {
+    match format {
+        Format::Ndjson => {
+            let mut body = Vec::new();
+            for object in objects {
+                serde_json::to_writer(&mut body, object)?;
+                body.push(b'\n');
+            }
+            Ok(body)
+        },
+        Format::MsgPack => Ok(rmp_serde::to_vec_named(objects)?),
+        _ => Ok(serde_json::to_vec(objects)?),
+    }
+}
+
+/// Body of a single object in one of the serde formats
+fn serialized_object<D: Serialize>(format: Format, object: &D) -> Result<Vec<u8>> {
+    match format {
+        Format::Ndjson => {
+            let mut body = serde_json::to_vec(object)?;
+            body.push(b'\n');
+            Ok(body)
+        },
+        Format::MsgPack => Ok(rmp_serde::to_vec_named(object)?),
+        _ => Ok(serde_json::to_vec(object)?),
+    }
+}
+
+/// Object with only the given fields
+fn sparse<D: Serialize>(object: &D, fields: &[String]) -> Result<Object> {
+    Ok(Object::of(object)?.retain(|name| fields.iter().any(|field| field == name)))
+}
+
+/// Fields of a serialized object, in the order they are declared whatever map
+/// `serde_json` is built with
+struct Object(Vec<(String, Value)>);
+
+impl Object {
+    fn of<D: Serialize>(object: &D) -> Result<Self> {
+        // going through the JSON text keeps floats as they are printed, instead
+        // of widening them to noisy doubles
+        Ok(serde_json::from_slice(&serde_json::to_vec(object)?)?)
+    }
+
+    fn retain(mut self, keep: impl Fn(&str) -> bool) -> Self {
+        self.0.retain(|(name, _)| keep(name));
+        self
+    }
+}
+
+impl Serialize for Object {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(name, value)| (name, value)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Object {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        struct ObjectVisitor;
+
+        impl<'de> Visitor<'de> for ObjectVisitor {
+            type Value = Object;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Object, A::Error> {
+                let mut fields = Vec::with_capacity(map.size_hint().unwrap_or_default());
+                while let Some(field) = map.next_entry()? {
+                    fields.push(field);
+                }
+
+                Ok(Object(fields))
+            }
+        }
+
+        deserializer.deserialize_map(ObjectVisitor)
+    }
+}
+
+/// Indexes of the CSV columns of the given fields, all of them without fields
+fn csv_columns<D: CsvRecord>(fields: Option<&[String]>) -> Vec<usize> {
+    D::csv_headers()
+        .iter()
+        .enumerate()
+        .filter(|(_, header)| {
+            fields.is_none_or(|fields| {
+                fields.iter().any(|field| {
+                    // references are split into key and name columns
+                    let column = header.strip_suffix("_key").or_else(|| header.strip_suffix("_name"));
+                    *header == field || column == Some(field)
+                })
+            })
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+fn select<T: AsRef<str>>(record: &[T], columns: &[usize]) -> Vec<String> {
+    columns.iter().map(|index| record[*index].as_ref().to_string()).collect()
+}
+
+fn not_located() -> Response {
+    (StatusCode::NOT_ACCEPTABLE, "GeoJSON is only available for countries, states and cities").into_response()
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  GEOJSON  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Location of an object as a GeoJSON point
+///
+/// Types without coordinates keep the defaults and are not acceptable as
+/// GeoJSON.
+pub trait Geometry {
+    const LOCATED: bool = false;
+
+    /// Longitude and latitude of the object, in GeoJSON position order
+    fn coordinates(&self) -> Option<[f32; 2]> {
+        None
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum GeoJson {
+    FeatureCollection { features: Vec<GeoJson> },
+    Feature { geometry: Option<Point>, properties: Object },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum Point {
+    Point { coordinates: [f32; 2] },
+}
+
+/// Point feature of an object, with every other field of it as properties,
+/// references to other objects included, or only the given fields
+fn feature<D: Serialize + Geometry>(object: &D, fields: Option<&[String]>) -> Result<GeoJson> {
+    let properties = Object::of(object)?.retain(|name| {
+        name != "latitude"
+            && name != "longitude"
+            && fields.is_none_or(|fields| fields.iter().any(|field| field == name))
+    });
+
+    Ok(
+        GeoJson::Feature {
+            geometry: object.coordinates().map(|coordinates| Point::Point { coordinates }),
+            properties,
+        }
+    )
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><==========================  CSV  =============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Flat representation of an object as a CSV row
+///
+/// References to other objects are split into `<field>_key` and `<field>_name`
+/// columns.
+pub trait CsvRecord {
+    fn csv_headers() -> &'static [&'static str];
+    fn csv_record(&self) -> Vec<String>;
+}
+
+pub fn optional<T: Display>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    #[derive(Serialize)]
+    struct Place {
+        name: &'static str,
+        code: &'static str,
+        latitude: f32,
+        longitude: f32,
+    }
+
+    impl Geometry for Place {
+        const LOCATED: bool = true;
+
+        fn coordinates(&self) -> Option<[f32; 2]> {
+            Some([self.longitude, self.latitude])
+        }
+    }
+
+    impl CsvRecord for Place {
+        fn csv_headers() -> &'static [&'static str] {
+            &["name", "code", "latitude", "longitude"]
+        }
+
+        fn csv_record(&self) -> Vec<String> {
+            vec![self.name.into(), self.code.into(), self.latitude.to_string(), self.longitude.to_string()]
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Note {
+        text: &'static str,
+    }
+
+    impl Geometry for Note {}
+
+    impl CsvRecord for Note {
+        fn csv_headers() -> &'static [&'static str] {
+            &["text"]
+        }
+
+        fn csv_record(&self) -> Vec<String> {
+            vec![self.text.into()]
+        }
+    }
+
+    const PLACE: Place = Place { name: "Lisbon", code: "LIS", latitude: 38.7, longitude: -9.1 };
+
+    fn fields(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn sparse_fields_keep_their_declared_order() {
+        let object = sparse(&PLACE, &fields(&["latitude", "code", "name"])).unwrap();
+
+        assert_eq!(serde_json::to_string(&object).unwrap(), r#"{"name":"Lisbon","code":"LIS","latitude":38.7}"#);
+    }
+
+    #[test]
+    fn feature_properties_keep_their_declared_order() {
+        let feature = feature(&PLACE, None).unwrap();
+        assert_eq!(
+            serde_json::to_string(&feature).unwrap(),
+            r#"{"type":"Feature","geometry":{"type":"Point","coordinates":[-9.1,38.7]},"properties":{"name":"Lisbon","code":"LIS"}}"#,
+        );
+
+        let feature = super::feature(&PLACE, Some(&fields(&["code", "latitude"]))).unwrap();
+        assert_eq!(
+            serde_json::to_string(&feature).unwrap(),
+            r#"{"type":"Feature","geometry":{"type":"Point","coordinates":[-9.1,38.7]},"properties":{"code":"LIS"}}"#,
+        );
+    }
+
+    async fn negotiate(uri: &str, accept: Option<&str>) -> Result<Format, (StatusCode, String)> {
+        let mut request = Request::builder().uri(uri);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+
+        Format::from_request_parts(&mut parts, &()).await
+    }
+
+    #[test]
+    fn media_types_are_picked_by_quality() {
+        assert_eq!(Format::from_accept("text/csv;q=0.5, application/json"), Some(Format::Json));
+        assert_eq!(Format::from_accept("application/json;q=0.2, text/csv;q=0.8"), Some(Format::Csv));
+        assert_eq!(Format::from_accept("text/csv, application/x-ndjson"), Some(Format::Csv));
+        assert_eq!(Format::from_accept("text/csv;q=0, application/msgpack"), Some(Format::MsgPack));
+        assert_eq!(Format::from_accept("text/html, */*;q=0.1"), Some(Format::Json));
+        assert_eq!(Format::from_accept("TEXT/CSV"), Some(Format::Csv));
+    }
+
+    #[test]
+    fn unsupported_media_types_are_not_picked() {
+        assert_eq!(Format::from_accept("text/html"), None);
+        assert_eq!(Format::from_accept("text/*"), None);
+        assert_eq!(Format::from_accept("application/json;q=0"), None);
+    }
+
+    #[tokio::test]
+    async fn format_is_negotiated() {
+        assert_eq!(negotiate("/", None).await, Ok(Format::Json));
+        assert_eq!(negotiate("/", Some("*/*")).await, Ok(Format::Json));
+        assert_eq!(negotiate("/", Some("application/geo+json")).await, Ok(Format::GeoJson(MissingGeometry::Skip)));
+        assert_eq!(negotiate("/?geometry=null", Some("application/geo+json")).await, Ok(Format::GeoJson(MissingGeometry::Null)));
+        // the query parameter wins over the header
+        assert_eq!(negotiate("/?format=csv", Some("application/json")).await, Ok(Format::Csv));
+
+        assert_eq!(negotiate("/", Some("text/html")).await.unwrap_err().0, StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(negotiate("/?format=xml", None).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn geojson_needs_located_objects() {
+        let geojson = Format::GeoJson(Default::default());
+        let note = || Note { text: "Hello" };
+
+        assert_eq!(list_response(geojson, HeaderMap::new(), vec![note()], None).unwrap().status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(object_response(geojson, HeaderMap::new(), note(), None).unwrap().status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(list_response(geojson, HeaderMap::new(), vec![PLACE], None).unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn csv_keeps_only_the_given_fields() {
+        let response = list_response(Format::Csv, HeaderMap::new(), vec![PLACE], Some(&fields(&["code", "name"]))).unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "name,code\nLisbon,LIS\n");
+    }
+}
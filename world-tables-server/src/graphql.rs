@@ -0,0 +1,779 @@
+//! GraphQL endpoint
+//!
+//! `/graphql` exposes the six entities as a graph, following their relations
+//! from countries to states to cities, regions to subregions to countries and
+//! currencies to countries. Lists take the same `page` and `limit` arguments as
+//! the REST routes, and the root lists filter by their relations.
+//!
+//! Queries are refused when their complexity, which grows with the `limit` of
+//! every list and multiplies with nested lists, is over `MAX_COMPLEXITY`, and
+//! the lists of related objects take a `limit` of at most `MAX_RELATED_LIMIT`.
+//!
+//! Relations are resolved through data loaders, so the objects at the same
+//! depth of a query are fetched together with a few `IN` queries instead of one
+//! query per parent object.
+
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    extensions::{self, ExtensionContext, ExtensionFactory, NextValidation},
+    http::GraphiQLSource,
+    Context, EmptyMutation, EmptySubscription, Error, Object, Schema, ServerError, ValidationResult,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{
+    async_trait,
+    response::{Html, IntoResponse},
+    Extension,
+};
+use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+
+use world_tables_base::{
+    EntityLabel, Keyed, Selectable, Country, State, City, WorldRegion, WorldSubregion, Currency, Timezone
+};
+
+use crate::{Database, Pagination, include::MAX_LIMIT, limit::{Charge, COMPLEXITY_PER_TOKEN}, metrics::timed};
+
+/// Deepest nesting of relations accepted in a query
+const MAX_DEPTH: usize = 12;
+
+/// Highest complexity accepted in a query, about the fields of a full page of
+/// an entity
+///
+/// Every field counts 1, and a list counts its `limit` times the fields asked
+/// of each object, so nested lists multiply.
+pub const MAX_COMPLEXITY: usize = 20_000;
+
+/// Largest page of the lists of related objects, nested in other objects
+const MAX_RELATED_LIMIT: usize = 100;
+
+pub type WorldSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+pub fn schema() -> WorldSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .extension(ComplexityCharge)
+        .finish()
+}
+
+/// Takes tokens from the rate limit of a request for the complexity of its
+/// query, on top of the one every request takes
+struct ComplexityCharge;
+
+impl ExtensionFactory for ComplexityCharge {
+    fn create(&self) -> Arc<dyn extensions::Extension> {
+        Arc::new(ComplexityCharge)
+    }
+}
+
+#[async_trait]
+impl extensions::Extension for ComplexityCharge {
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await?;
+
+        if let Some(charge) = ctx.data_opt::<Charge>() {
+            let cost = result.complexity / COMPLEXITY_PER_TOKEN;
+
+            if cost > 0 {
+                if let Err(wait) = charge.take(cost as f64) {
+                    let message = format!("Too many requests, try again in {} seconds", wait.ceil());
+                    return Err(vec![ServerError::new(message, None)]);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  HANDLERS  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+pub async fn graphql(
+    Extension(schema): Extension<WorldSchema>,
+    Extension(db): Extension<Database>,
+    charge: Option<Extension<Charge>>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let mut request = with_loaders(request.into_inner(), db);
+
+    if let Some(Extension(charge)) = charge {
+        request = request.data(charge);
+    }
+
+    schema.execute(request).await.into()
+}
+
+/// Adds the data loaders of a request, which only cache objects for its
+/// duration
+fn with_loaders(request: async_graphql::Request, db: Database) -> async_graphql::Request {
+    request
+        .data(DataLoader::new(ObjectLoader::<Country>::new(&db), tokio::spawn))
+        .data(DataLoader::new(ObjectLoader::<State>::new(&db), tokio::spawn))
+        .data(DataLoader::new(ObjectLoader::<City>::new(&db), tokio::spawn))
+        .data(DataLoader::new(ObjectLoader::<WorldRegion>::new(&db), tokio::spawn))
+        .data(DataLoader::new(ObjectLoader::<WorldSubregion>::new(&db), tokio::spawn))
+        .data(DataLoader::new(ObjectLoader::<Currency>::new(&db), tokio::spawn))
+        .data(DataLoader::new(RelatedLoader::<Country>::new(&db), tokio::spawn))
+        .data(DataLoader::new(RelatedLoader::<State>::new(&db), tokio::spawn))
+        .data(DataLoader::new(RelatedLoader::<City>::new(&db), tokio::spawn))
+        .data(DataLoader::new(RelatedLoader::<WorldSubregion>::new(&db), tokio::spawn))
+        .data(DataLoader::new(RelatedLoader::<Timezone>::new(&db), tokio::spawn))
+        .data(db)
+}
+
+/// GraphiQL page to explore the schema and run queries from the browser
+pub async fn graphiql() -> impl IntoResponse {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  LOADERS  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Loads objects by their keys
+pub struct ObjectLoader<T> {
+    db: Database,
+    object: PhantomData<T>,
+}
+
+impl<T> ObjectLoader<T> {
+    fn new(db: &Database) -> Self {
+        Self { db: db.clone(), object: PhantomData }
+    }
+}
+
+#[async_trait]
+impl<T> Loader<String> for ObjectLoader<T>
+where
+    T: Selectable + Keyed + Clone + Send + Sync + 'static,
+    T::KeyType: ToString,
+{
+    type Value = T;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, T>, Self::Error> {
+        let conn = self.db.connection()?;
+        let keys = keys.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let (objects, _) = timed!(T::select_keys(&conn, &all_fields::<T>(), &keys))?;
+
+        Ok(
+            objects
+                .into_iter()
+                .filter_map(|object| Some((key_of(&object)?, object)))
+                .collect()
+        )
+    }
+}
+
+/// Page of the objects related to another one through a column
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Related {
+    column: &'static str,
+    key: String,
+    limit: usize,
+    offset: usize,
+}
+
+/// Loads pages of related objects, one query for all the keys asking for the
+/// same page of the same relation
+pub struct RelatedLoader<T> {
+    db: Database,
+    object: PhantomData<T>,
+}
+
+impl<T> RelatedLoader<T> {
+    fn new(db: &Database) -> Self {
+        Self { db: db.clone(), object: PhantomData }
+    }
+}
+
+#[async_trait]
+impl<T> Loader<Related> for RelatedLoader<T>
+where
+    T: Selectable + Clone + Send + Sync + 'static,
+{
+    type Value = Vec<T>;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, keys: &[Related]) -> Result<HashMap<Related, Vec<T>>, Self::Error> {
+        let conn = self.db.connection()?;
+        let fields = all_fields::<T>();
+
+        let mut windows = HashMap::<_, Vec<&str>>::new();
+        for related in keys {
+            windows
+                .entry((related.column, related.limit, related.offset))
+                .or_default()
+                .push(&related.key);
+        }
+
+        // keys without related objects still get their empty page
+        let mut pages = keys
+            .iter()
+            .map(|related| (related.clone(), Vec::new()))
+            .collect::<HashMap<_, _>>();
+
+        for ((column, limit, offset), keys) in windows {
+            for (key, object) in timed!(T::select_related(&conn, &fields, column, &keys, limit, offset))? {
+                if let Some(page) = pages.get_mut(&Related { column, key, limit, offset }) {
+                    page.push(object);
+                }
+            }
+        }
+
+        Ok(pages)
+    }
+}
+
+fn all_fields<T: Selectable>() -> Vec<&'static str> {
+    T::FIELDS.iter().map(|(field, _)| *field).collect()
+}
+
+fn key_of<T>(object: &T) -> Option<String>
+where
+    T: Keyed,
+    T::KeyType: ToString,
+{
+    object.key().ok().and_then(|key| key.as_ref()).map(ToString::to_string)
+}
+
+fn label_key<K: ToString, T, L>(label: &EntityLabel<K, T, L>) -> Option<String> {
+    match label {
+        EntityLabel::KeyLabel(key, _) => key.as_ref().map(ToString::to_string),
+        _ => None,
+    }
+}
+
+/// Object of type `T` with the key, if there is one
+async fn load_one<T>(ctx: &Context<'_>, key: Option<String>) -> Result<Option<T>, Error>
+where
+    ObjectLoader<T>: Loader<String, Value = T, Error = Arc<anyhow::Error>>,
+{
+    let Some(key) = key else { return Ok(None) };
+
+    Ok(ctx.data_unchecked::<DataLoader<ObjectLoader<T>>>().load_one(key).await?)
+}
+
+/// Page of the objects of type `T` related to the key through the column
+async fn load_related<T>(ctx: &Context<'_>, column: &'static str, key: Option<String>, page: usize, limit: usize) -> Result<Vec<T>, Error>
+where
+    RelatedLoader<T>: Loader<Related, Value = Vec<T>, Error = Arc<anyhow::Error>>,
+{
+    let Some(key) = key else { return Ok(Vec::new()) };
+    let (limit, offset) = page_window(page, limit, MAX_RELATED_LIMIT)?;
+
+    let related = Related { column, key, limit, offset };
+    let objects = ctx.data_unchecked::<DataLoader<RelatedLoader<T>>>().load_one(related).await?;
+
+    Ok(objects.unwrap_or_default())
+}
+
+/// Page of the objects of type `T` matching every filter
+fn select_page<T: Selectable>(ctx: &Context<'_>, filters: &[(&'static str, Option<String>)], page: usize, limit: usize) -> Result<Vec<T>, Error> {
+    let (limit, offset) = page_window(page, limit, MAX_LIMIT)?;
+    let filters = filters
+        .iter()
+        .filter_map(|(column, key)| Some((*column, key.as_deref()?)))
+        .collect::<Vec<_>>();
+
+    let conn = ctx.data_unchecked::<Database>().connection()?;
+
+    Ok(timed!(T::select(&conn, &all_fields::<T>(), &filters, limit, offset))?)
+}
+
+fn page_window(page: usize, limit: usize, max_limit: usize) -> Result<(usize, usize), Error> {
+    if page == 0 || !(1..=max_limit).contains(&limit) {
+        return Err(Error::new(format!("page must be at least 1 and limit from 1 to {max_limit}")));
+    }
+
+    Ok(Pagination { page, limit }.to_limit_offset())
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  QUERY  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn country(&self, ctx: &Context<'_>, iso2: String) -> Result<Option<CountryNode>, Error> {
+        Ok(load_one(ctx, Some(iso2)).await?.map(CountryNode))
+    }
+
+    async fn state(&self, ctx: &Context<'_>, id: usize) -> Result<Option<StateNode>, Error> {
+        Ok(load_one(ctx, Some(id.to_string())).await?.map(StateNode))
+    }
+
+    async fn city(&self, ctx: &Context<'_>, id: usize) -> Result<Option<CityNode>, Error> {
+        Ok(load_one(ctx, Some(id.to_string())).await?.map(CityNode))
+    }
+
+    async fn region(&self, ctx: &Context<'_>, id: usize) -> Result<Option<WorldRegionNode>, Error> {
+        Ok(load_one(ctx, Some(id.to_string())).await?.map(WorldRegionNode))
+    }
+
+    async fn subregion(&self, ctx: &Context<'_>, id: usize) -> Result<Option<WorldSubregionNode>, Error> {
+        Ok(load_one(ctx, Some(id.to_string())).await?.map(WorldSubregionNode))
+    }
+
+    async fn currency(&self, ctx: &Context<'_>, iso: String) -> Result<Option<CurrencyNode>, Error> {
+        Ok(load_one(ctx, Some(iso)).await?.map(CurrencyNode))
+    }
+
+    /// Countries, optionally of a region, a subregion or using a currency
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn countries(
+        &self,
+        ctx: &Context<'_>,
+        region: Option<usize>,
+        subregion: Option<usize>,
+        currency: Option<String>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<CountryNode>, Error> {
+        let filters = [
+            ("world_region_id", region.map(|id| id.to_string())),
+            ("world_subregion_id", subregion.map(|id| id.to_string())),
+            ("currency_id", currency),
+        ];
+
+        Ok(select_page(ctx, &filters, page, limit)?.into_iter().map(CountryNode).collect())
+    }
+
+    /// States, optionally of a country
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn states(
+        &self,
+        ctx: &Context<'_>,
+        country: Option<String>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<StateNode>, Error> {
+        let filters = [("country_id", country)];
+
+        Ok(select_page(ctx, &filters, page, limit)?.into_iter().map(StateNode).collect())
+    }
+
+    /// Cities, optionally of a country or a state
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn cities(
+        &self,
+        ctx: &Context<'_>,
+        country: Option<String>,
+        state: Option<usize>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<CityNode>, Error> {
+        let filters = [("country_id", country), ("state_id", state.map(|id| id.to_string()))];
+
+        Ok(select_page(ctx, &filters, page, limit)?.into_iter().map(CityNode).collect())
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn regions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<WorldRegionNode>, Error> {
+        Ok(select_page(ctx, &[], page, limit)?.into_iter().map(WorldRegionNode).collect())
+    }
+
+    /// Subregions, optionally of a region
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn subregions(
+        &self,
+        ctx: &Context<'_>,
+        region: Option<usize>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<WorldSubregionNode>, Error> {
+        let filters = [("sub.world_region_id", region.map(|id| id.to_string()))];
+
+        Ok(select_page(ctx, &filters, page, limit)?.into_iter().map(WorldSubregionNode).collect())
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn currencies(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<CurrencyNode>, Error> {
+        Ok(select_page(ctx, &[], page, limit)?.into_iter().map(CurrencyNode).collect())
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  NODES  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+pub struct CountryNode(Country);
+
+#[Object(name = "Country")]
+impl CountryNode {
+    async fn iso2(&self) -> Option<&str> {
+        self.0.iso2.as_deref()
+    }
+
+    async fn iso3(&self) -> &str {
+        &self.0.iso3
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn code(&self) -> u32 {
+        self.0.code
+    }
+
+    async fn capital(&self, ctx: &Context<'_>) -> Result<Option<CityNode>, Error> {
+        Ok(load_one(ctx, label_key(&self.0.capital)).await?.map(CityNode))
+    }
+
+    async fn currency(&self, ctx: &Context<'_>) -> Result<Option<CurrencyNode>, Error> {
+        Ok(load_one(ctx, label_key(&self.0.currency)).await?.map(CurrencyNode))
+    }
+
+    async fn tld(&self) -> &str {
+        &self.0.tld
+    }
+
+    async fn native(&self) -> &str {
+        &self.0.native
+    }
+
+    async fn region(&self, ctx: &Context<'_>) -> Result<Option<WorldRegionNode>, Error> {
+        Ok(load_one(ctx, label_key(&self.0.region)).await?.map(WorldRegionNode))
+    }
+
+    async fn subregion(&self, ctx: &Context<'_>) -> Result<Option<WorldSubregionNode>, Error> {
+        Ok(load_one(ctx, label_key(&self.0.subregion)).await?.map(WorldSubregionNode))
+    }
+
+    async fn latitude(&self) -> f32 {
+        self.0.latitude
+    }
+
+    async fn longitude(&self) -> f32 {
+        self.0.longitude
+    }
+
+    async fn emoji(&self) -> &str {
+        &self.0.emoji
+    }
+
+    async fn emoji_u(&self) -> &str {
+        &self.0.emoji_u
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn states(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<StateNode>, Error> {
+        let states = load_related(ctx, "country_id", self.0.iso2.0.clone(), page, limit).await?;
+
+        Ok(states.into_iter().map(StateNode).collect())
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn cities(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<CityNode>, Error> {
+        let cities = load_related(ctx, "country_id", self.0.iso2.0.clone(), page, limit).await?;
+
+        Ok(cities.into_iter().map(CityNode).collect())
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn timezones(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<TimezoneNode>, Error> {
+        let timezones = load_related(ctx, "country_id", self.0.iso2.0.clone(), page, limit).await?;
+
+        Ok(timezones.into_iter().map(TimezoneNode).collect())
+    }
+}
+
+pub struct StateNode(State);
+
+#[Object(name = "State")]
+impl StateNode {
+    async fn id(&self) -> Option<usize> {
+        self.0.id.0
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn code(&self) -> &str {
+        &self.0.code
+    }
+
+    async fn country(&self, ctx: &Context<'_>) -> Result<Option<CountryNode>, Error> {
+        Ok(load_one(ctx, label_key(&self.0.country)).await?.map(CountryNode))
+    }
+
+    async fn latitude(&self) -> Option<f32> {
+        self.0.latitude
+    }
+
+    async fn longitude(&self) -> Option<f32> {
+        self.0.longitude
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn cities(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<CityNode>, Error> {
+        let cities = load_related(ctx, "state_id", self.0.id.map(|id| id.to_string()), page, limit).await?;
+
+        Ok(cities.into_iter().map(CityNode).collect())
+    }
+}
+
+pub struct CityNode(City);
+
+#[Object(name = "City")]
+impl CityNode {
+    async fn id(&self) -> Option<usize> {
+        self.0.id.0
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn state(&self, ctx: &Context<'_>) -> Result<Option<StateNode>, Error> {
+        Ok(load_one(ctx, label_key(&self.0.state)).await?.map(StateNode))
+    }
+
+    async fn country(&self, ctx: &Context<'_>) -> Result<Option<CountryNode>, Error> {
+        Ok(load_one(ctx, label_key(&self.0.country)).await?.map(CountryNode))
+    }
+
+    async fn latitude(&self) -> Option<f32> {
+        self.0.latitude
+    }
+
+    async fn longitude(&self) -> Option<f32> {
+        self.0.longitude
+    }
+}
+
+pub struct WorldRegionNode(WorldRegion);
+
+#[Object(name = "WorldRegion")]
+impl WorldRegionNode {
+    async fn id(&self) -> Option<usize> {
+        self.0.id.0
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn subregions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<WorldSubregionNode>, Error> {
+        let key = self.0.id.map(|id| id.to_string());
+        let subregions = load_related(ctx, "sub.world_region_id", key, page, limit).await?;
+
+        Ok(subregions.into_iter().map(WorldSubregionNode).collect())
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn countries(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<CountryNode>, Error> {
+        let key = self.0.id.map(|id| id.to_string());
+        let countries = load_related(ctx, "world_region_id", key, page, limit).await?;
+
+        Ok(countries.into_iter().map(CountryNode).collect())
+    }
+}
+
+pub struct WorldSubregionNode(WorldSubregion);
+
+#[Object(name = "WorldSubregion")]
+impl WorldSubregionNode {
+    async fn id(&self) -> Option<usize> {
+        self.0.id.0
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn region(&self, ctx: &Context<'_>) -> Result<Option<WorldRegionNode>, Error> {
+        Ok(load_one(ctx, label_key(&self.0.region)).await?.map(WorldRegionNode))
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn countries(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<CountryNode>, Error> {
+        let key = self.0.id.map(|id| id.to_string());
+        let countries = load_related(ctx, "world_subregion_id", key, page, limit).await?;
+
+        Ok(countries.into_iter().map(CountryNode).collect())
+    }
+}
+
+pub struct CurrencyNode(Currency);
+
+#[Object(name = "Currency")]
+impl CurrencyNode {
+    async fn iso(&self) -> Option<&str> {
+        self.0.iso.as_deref()
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn symbol(&self) -> &str {
+        &self.0.symbol
+    }
+
+    #[graphql(complexity = "limit.saturating_mul(child_complexity)")]
+    async fn countries(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<CountryNode>, Error> {
+        let countries = load_related(ctx, "currency_id", self.0.iso.0.clone(), page, limit).await?;
+
+        Ok(countries.into_iter().map(CountryNode).collect())
+    }
+}
+
+pub struct TimezoneNode(Timezone);
+
+#[Object(name = "Timezone")]
+impl TimezoneNode {
+    async fn id(&self) -> Option<usize> {
+        self.0.id.0
+    }
+
+    async fn zone_name(&self) -> &str {
+        &self.0.zone_name
+    }
+
+    async fn gmt_offset(&self) -> i32 {
+        self.0.gmt_offset
+    }
+
+    async fn gmt_offset_name(&self) -> &str {
+        &self.0.gmt_offset_name
+    }
+
+    async fn abbreviation(&self) -> &str {
+        &self.0.abbreviation
+    }
+
+    async fn tz_name(&self) -> &str {
+        &self.0.tz_name
+    }
+
+    async fn country(&self, ctx: &Context<'_>) -> Result<Option<CountryNode>, Error> {
+        Ok(load_one(ctx, label_key(&self.0.country)).await?.map(CountryNode))
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limit::{Client, RateLimiter};
+
+    async fn execute(db: &Database, query: &str) -> async_graphql::Response {
+        schema().execute(with_loaders(async_graphql::Request::new(query), db.clone())).await
+    }
+
+    #[tokio::test]
+    async fn cyclic_query_over_complexity_is_refused() {
+        let db = Database::seeded("graphql-cyclic");
+        let response = execute(&db, "{ countries(limit: 1000) { states(limit: 100) { country { states(limit: 100) { name } } } } }").await;
+
+        assert!(response.data == async_graphql::Value::Null);
+        assert!(response.errors.iter().any(|err| err.message.contains("complex")), "{:?}", response.errors);
+    }
+
+    #[tokio::test]
+    async fn default_limits_stay_under_complexity() {
+        let db = Database::seeded("graphql-defaults");
+        let response = execute(&db, "{ countries { name states { name country { name states { name code } } } } }").await;
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap()["countries"][0]["states"][0]["country"]["states"][0]["code"],
+            "SP",
+        );
+    }
+
+    #[tokio::test]
+    async fn related_lists_take_a_smaller_limit() {
+        let db = Database::seeded("graphql-related");
+
+        let response = execute(&db, &format!("{{ countries(limit: 1) {{ states(limit: {}) {{ name }} }} }}", MAX_RELATED_LIMIT + 1)).await;
+        assert!(response.errors.iter().any(|err| err.message.contains("limit")), "{:?}", response.errors);
+
+        let response = execute(&db, &format!("{{ countries(limit: 1) {{ states(limit: {MAX_RELATED_LIMIT}) {{ name }} }} }}")).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    }
+
+    #[tokio::test]
+    async fn complex_queries_take_more_tokens() {
+        let db = Database::seeded("graphql-charge");
+        let charge = RateLimiter::new(0.001, 10.0).charge(Client::Unknown);
+        let query = "{ countries(limit: 1000) { name iso2 iso3 } }";
+
+        for _ in 0..3 {
+            let response = schema().execute(with_loaders(async_graphql::Request::new(query), db.clone()).data(charge.clone())).await;
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+        }
+
+        let response = schema().execute(with_loaders(async_graphql::Request::new(query), db.clone()).data(charge.clone())).await;
+        assert!(response.errors.iter().any(|err| err.message.contains("Too many requests")), "{:?}", response.errors);
+
+        let response = schema().execute(with_loaders(async_graphql::Request::new("{ countries { name } }"), db).data(charge)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    }
+}
@@ -0,0 +1,636 @@
+//! gRPC service
+//!
+//! With `--grpc-address` the server also answers gRPC on a second port, with
+//! the services of `proto/world_tables.proto`. Each entity has a unary `Get`,
+//! a `List` streaming the whole table, read from the database with a single
+//! cursor as the client takes the objects, and the filtered lists of the
+//! `from_*` queries, streamed the same way. The streams share the limit of the
+//! REST exports on how many run at once, and are stopped when the client stops
+//! reading for a while.
+//!
+//! Calls are authorized with the same API keys as the REST routes, sent as a
+//! bearer token in the `authorization` metadata, take a token each from the
+//! same rate limit, are counted and timed in the metrics by method and status
+//! code, and served over TLS when the server has a certificate.
+
+// the calls answer with tonic's `Status`, large as it is
+#![allow(clippy::result_large_err)]
+
+use anyhow::Result;
+use std::{
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::{
+    net::TcpListener,
+    runtime::Handle,
+    sync::mpsc::{self, error::SendTimeoutError},
+};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{
+    codec::CompressionEncoding,
+    codegen::http,
+    service::{interceptor::InterceptedService, Interceptor},
+    transport::{Identity, Server, ServerTlsConfig},
+    Code, Request, Response, Status,
+};
+
+use world_tables_base::{
+    EntityLabel, Keyed, Label, Model, Selectable, Country, State, City, WorldRegion, WorldSubregion, Currency,
+};
+
+use crate::{
+    Database,
+    auth::{Anonymous, ApiKey, FailedAttempts},
+    export::{Exports, SEND_TIMEOUT},
+    limit::{Client, RateLimiter},
+    metrics::{self, timed},
+};
+
+use pb::{
+    cities_server::{Cities, CitiesServer},
+    countries_server::{Countries, CountriesServer},
+    currencies_server::{Currencies, CurrenciesServer},
+    states_server::{States, StatesServer},
+    world_regions_server::{WorldRegions, WorldRegionsServer},
+    world_subregions_server::{WorldSubregions, WorldSubregionsServer},
+    KeyRequest, ListRequest,
+};
+
+pub mod pb {
+    tonic::include_proto!("world_tables.v1");
+}
+
+/// Objects a stream holds while the client is slower than the database
+const STREAM_BUFFER: usize = 64;
+
+/// Stream of the objects of a list call
+type ObjectStream<P> = ReceiverStream<Result<P, Status>>;
+
+/// Shared with the REST routes, so clients have the same limits over both
+pub struct Limits {
+    pub anonymous: Anonymous,
+    pub rate: RateLimiter,
+    pub attempts: FailedAttempts,
+    pub exports: Exports,
+}
+
+/// Serves the gRPC services on `listener` until `shutdown` completes
+pub async fn serve(
+    listener: TcpListener,
+    tls: Option<(PathBuf, PathBuf)>,
+    db: Database,
+    limits: Limits,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let mut server = Server::builder().layer(tower::layer::layer_fn(Track));
+
+    if let Some((cert, key)) = tls {
+        let identity = Identity::from_pem(tokio::fs::read(cert).await?, tokio::fs::read(key).await?);
+        server = server.tls_config(ServerTlsConfig::new().identity(identity))?;
+    }
+
+    let service = WorldTables { db: db.clone(), exports: limits.exports };
+    let authorize = Authorize { db, anonymous: limits.anonymous, rate: limits.rate, attempts: limits.attempts };
+
+    // whole tables compress well
+    macro_rules! service {
+        ($server:ident) => {
+            InterceptedService::new(
+                $server::new(service.clone())
+                    .send_compressed(CompressionEncoding::Gzip)
+                    .accept_compressed(CompressionEncoding::Gzip),
+                authorize.clone(),
+            )
+        };
+    }
+
+    server
+        .add_service(service!(CountriesServer))
+        .add_service(service!(StatesServer))
+        .add_service(service!(CitiesServer))
+        .add_service(service!(CurrenciesServer))
+        .add_service(service!(WorldRegionsServer))
+        .add_service(service!(WorldSubregionsServer))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+        .await?;
+
+    Ok(())
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=======================  SERVICES  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[derive(Clone)]
+struct WorldTables {
+    db: Database,
+    exports: Exports,
+}
+
+#[tonic::async_trait]
+impl Countries for WorldTables {
+    type ListStream = ObjectStream<pb::Country>;
+    type ListByRegionStream = ObjectStream<pb::Country>;
+    type ListBySubregionStream = ObjectStream<pb::Country>;
+    type ListByCurrencyStream = ObjectStream<pb::Country>;
+
+    async fn get(&self, request: Request<KeyRequest>) -> Result<Response<pb::Country>, Status> {
+        get::<Country, _>(&self.db, &request.into_inner().key)
+    }
+
+    async fn list(&self, _: Request<ListRequest>) -> Result<Response<Self::ListStream>, Status> {
+        self.stream::<Country, _>(vec![])
+    }
+
+    async fn list_by_region(&self, request: Request<KeyRequest>) -> Result<Response<Self::ListByRegionStream>, Status> {
+        let key = request.into_inner().key;
+        self.stream::<Country, _>(vec![("world_region_id", key)])
+    }
+
+    async fn list_by_subregion(&self, request: Request<KeyRequest>) -> Result<Response<Self::ListBySubregionStream>, Status> {
+        let key = request.into_inner().key;
+        self.stream::<Country, _>(vec![("world_subregion_id", key)])
+    }
+
+    async fn list_by_currency(&self, request: Request<KeyRequest>) -> Result<Response<Self::ListByCurrencyStream>, Status> {
+        let key = request.into_inner().key;
+        self.stream::<Country, _>(vec![("currency_id", key)])
+    }
+}
+
+#[tonic::async_trait]
+impl States for WorldTables {
+    type ListStream = ObjectStream<pb::State>;
+    type ListByCountryStream = ObjectStream<pb::State>;
+
+    async fn get(&self, request: Request<KeyRequest>) -> Result<Response<pb::State>, Status> {
+        get::<State, _>(&self.db, &request.into_inner().key)
+    }
+
+    async fn list(&self, _: Request<ListRequest>) -> Result<Response<Self::ListStream>, Status> {
+        self.stream::<State, _>(vec![])
+    }
+
+    async fn list_by_country(&self, request: Request<KeyRequest>) -> Result<Response<Self::ListByCountryStream>, Status> {
+        let key = request.into_inner().key;
+        self.stream::<State, _>(vec![("country_id", key)])
+    }
+}
+
+#[tonic::async_trait]
+impl Cities for WorldTables {
+    type ListStream = ObjectStream<pb::City>;
+    type ListByCountryStream = ObjectStream<pb::City>;
+    type ListByStateStream = ObjectStream<pb::City>;
+
+    async fn get(&self, request: Request<KeyRequest>) -> Result<Response<pb::City>, Status> {
+        get::<City, _>(&self.db, &request.into_inner().key)
+    }
+
+    async fn list(&self, _: Request<ListRequest>) -> Result<Response<Self::ListStream>, Status> {
+        self.stream::<City, _>(vec![])
+    }
+
+    async fn list_by_country(&self, request: Request<KeyRequest>) -> Result<Response<Self::ListByCountryStream>, Status> {
+        let key = request.into_inner().key;
+        self.stream::<City, _>(vec![("country_id", key)])
+    }
+
+    async fn list_by_state(&self, request: Request<KeyRequest>) -> Result<Response<Self::ListByStateStream>, Status> {
+        let key = request.into_inner().key;
+        self.stream::<City, _>(vec![("state_id", key)])
+    }
+}
+
+#[tonic::async_trait]
+impl Currencies for WorldTables {
+    type ListStream = ObjectStream<pb::Currency>;
+
+    async fn get(&self, request: Request<KeyRequest>) -> Result<Response<pb::Currency>, Status> {
+        get::<Currency, _>(&self.db, &request.into_inner().key)
+    }
+
+    async fn list(&self, _: Request<ListRequest>) -> Result<Response<Self::ListStream>, Status> {
+        self.stream::<Currency, _>(vec![])
+    }
+}
+
+#[tonic::async_trait]
+impl WorldRegions for WorldTables {
+    type ListStream = ObjectStream<pb::WorldRegion>;
+
+    async fn get(&self, request: Request<KeyRequest>) -> Result<Response<pb::WorldRegion>, Status> {
+        get::<WorldRegion, _>(&self.db, &request.into_inner().key)
+    }
+
+    async fn list(&self, _: Request<ListRequest>) -> Result<Response<Self::ListStream>, Status> {
+        self.stream::<WorldRegion, _>(vec![])
+    }
+}
+
+#[tonic::async_trait]
+impl WorldSubregions for WorldTables {
+    type ListStream = ObjectStream<pb::WorldSubregion>;
+    type ListByRegionStream = ObjectStream<pb::WorldSubregion>;
+
+    async fn get(&self, request: Request<KeyRequest>) -> Result<Response<pb::WorldSubregion>, Status> {
+        get::<WorldSubregion, _>(&self.db, &request.into_inner().key)
+    }
+
+    async fn list(&self, _: Request<ListRequest>) -> Result<Response<Self::ListStream>, Status> {
+        self.stream::<WorldSubregion, _>(vec![])
+    }
+
+    async fn list_by_region(&self, request: Request<KeyRequest>) -> Result<Response<Self::ListByRegionStream>, Status> {
+        let key = request.into_inner().key;
+        self.stream::<WorldSubregion, _>(vec![("sub.world_region_id", key)])
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  QUERIES  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+fn get<T: Model, P: From<T>>(db: &Database, key: &str) -> Result<Response<P>, Status> {
+    let conn = db.connection().map_err(status)?;
+    let object = timed!(T::get(&conn, key)).map_err(status)?;
+
+    Ok(Response::new(P::from(object)))
+}
+
+impl WorldTables {
+    /// Streams the objects of `T` filtered by columns matching keys, read with
+    /// a single cursor as the client takes them
+    fn stream<T, P>(&self, filters: Vec<(&'static str, String)>) -> Result<Response<ObjectStream<P>>, Status>
+    where
+        T: Selectable + Send + 'static,
+        P: From<T> + Send + 'static,
+    {
+        let permit = self
+            .exports
+            .try_start()
+            .ok_or_else(|| Status::resource_exhausted("Too many streams running, try again later"))?;
+
+        let conn = self.db.connection().map_err(status)?;
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER + 1);
+        // a slot for the error ending the stream, which can't wait for a
+        // client that stopped reading
+        let error_slot = tx.clone().try_reserve_owned().map_err(|err| status(err.into()))?;
+        let runtime = Handle::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let fields = T::FIELDS.iter().map(|(field, _)| *field).collect::<Vec<_>>();
+            let filters = filters.iter().map(|(column, key)| (*column, key.as_str())).collect::<Vec<_>>();
+            let mut timed_out = false;
+
+            let result = timed!(T::select_each(&conn, &fields, &filters, |object| {
+                match runtime.block_on(tx.send_timeout(Ok(P::from(object)), SEND_TIMEOUT)) {
+                    Ok(()) => Ok(true),
+                    // the client went away
+                    Err(SendTimeoutError::Closed(_)) => Ok(false),
+                    Err(SendTimeoutError::Timeout(_)) => {
+                        timed_out = true;
+                        Ok(false)
+                    },
+                }
+            }));
+
+            match result {
+                Err(err) => {
+                    error_slot.send(Err(status(err)));
+                },
+                Ok(()) if timed_out => {
+                    error_slot.send(Err(Status::deadline_exceeded("The client stopped reading")));
+                },
+                Ok(()) => {},
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+fn status(err: anyhow::Error) -> Status {
+    match err.downcast_ref::<rusqlite::Error>() {
+        Some(rusqlite::Error::QueryReturnedNoRows) => Status::not_found("No object with this key"),
+        _ => {
+            // the details are for the logs, not for the clients
+            tracing::error!("{err:#}");
+            Status::internal("Something went wrong")
+        },
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=====================  AUTHORIZATION  ========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Checks the API key of the calls, which only read and so are allowed to
+/// every role, and takes a token for each from the rate limit
+#[derive(Clone)]
+struct Authorize {
+    db: Database,
+    anonymous: Anonymous,
+    rate: RateLimiter,
+    attempts: FailedAttempts,
+}
+
+impl Interceptor for Authorize {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let address = request.remote_addr().map_or(Client::Unknown, |addr| Client::address(addr.ip()));
+        let FailedAttempts(attempts) = &self.attempts;
+
+        let bearer = request
+            .metadata()
+            .get("authorization")
+            .map(|value| value.to_str().ok().and_then(|value| value.strip_prefix("Bearer ")));
+
+        let (client, role) = match bearer {
+            None => (address, self.anonymous.0),
+            Some(Some(key)) => {
+                attempts.check(address.clone()).map_err(too_many_requests)?;
+
+                // interceptors can't wait, so the checkout and the query move
+                // the other tasks off this worker instead of holding them up
+                let found = tokio::task::block_in_place(|| {
+                    self.db.connection().and_then(|conn| ApiKey::authenticate(&conn, key.trim()))
+                })
+                .map_err(status)?;
+
+                match found {
+                    Some((id, role)) => (Client::Key(id), Some(role)),
+                    None => {
+                        let _ = attempts.take(address, 1.0);
+                        return Err(Status::unauthenticated("Invalid or revoked API key"));
+                    },
+                }
+            },
+            Some(None) => return Err(Status::unauthenticated("Authorization must be a bearer API key")),
+        };
+
+        if role.is_none() {
+            return Err(Status::unauthenticated("An API key is required"));
+        }
+
+        self.rate.take(client, 1.0).map_err(too_many_requests)?;
+
+        Ok(request)
+    }
+}
+
+fn too_many_requests(wait: f64) -> Status {
+    Status::resource_exhausted(format!("Too many requests, try again in {} seconds", wait.ceil()))
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  METRICS  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Counts and times the calls by method and status code
+#[derive(Clone)]
+struct Track<S>(S);
+
+impl<S, B, R> tower::Service<http::Request<B>> for Track<S>
+where
+    S: tower::Service<http::Request<B>, Response = http::Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let method = request.uri().path().to_string();
+        let start = Instant::now();
+        let response = self.0.call(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+
+            // calls failing before they answer have their status in the
+            // headers, the others have it in the trailers and count as ok
+            let code = response
+                .headers()
+                .get("grpc-status")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<i32>().ok())
+                .map_or(Code::Ok, Code::from);
+
+            metrics::record_grpc(&method, code, start.elapsed());
+
+            Ok(response)
+        })
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><======================  CONVERSIONS  =========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Reference to a related object, when there is one
+fn reference<K, T>(label: &EntityLabel<K, T, String>) -> Option<pb::Ref>
+where
+    K: ToString,
+    T: Keyed<KeyType = K> + Label<LabelType = String>,
+{
+    let key = label.key().ok()?.as_ref()?;
+
+    Some(pb::Ref {
+        key: key.to_string(),
+        name: label.label().cloned().unwrap_or_default(),
+    })
+}
+
+impl From<Country> for pb::Country {
+    fn from(country: Country) -> Self {
+        Self {
+            capital: reference(&country.capital),
+            currency: reference(&country.currency),
+            region: reference(&country.region),
+            subregion: reference(&country.subregion),
+            iso2: country.iso2.0.unwrap_or_default(),
+            iso3: country.iso3,
+            name: country.name,
+            code: country.code,
+            tld: country.tld,
+            native: country.native,
+            latitude: country.latitude,
+            longitude: country.longitude,
+            emoji: country.emoji,
+            emoji_u: country.emoji_u,
+        }
+    }
+}
+
+impl From<State> for pb::State {
+    fn from(state: State) -> Self {
+        Self {
+            country: reference(&state.country),
+            id: state.id.0.unwrap_or_default() as u64,
+            name: state.name,
+            code: state.code,
+            latitude: state.latitude,
+            longitude: state.longitude,
+        }
+    }
+}
+
+impl From<City> for pb::City {
+    fn from(city: City) -> Self {
+        Self {
+            state: reference(&city.state),
+            country: reference(&city.country),
+            id: city.id.0.unwrap_or_default() as u64,
+            name: city.name,
+            latitude: city.latitude,
+            longitude: city.longitude,
+        }
+    }
+}
+
+impl From<Currency> for pb::Currency {
+    fn from(currency: Currency) -> Self {
+        Self {
+            iso: currency.iso.0.unwrap_or_default(),
+            name: currency.name,
+            symbol: currency.symbol,
+        }
+    }
+}
+
+impl From<WorldRegion> for pb::WorldRegion {
+    fn from(region: WorldRegion) -> Self {
+        Self {
+            id: region.id.0.unwrap_or_default() as u64,
+            name: region.name,
+        }
+    }
+}
+
+impl From<WorldSubregion> for pb::WorldSubregion {
+    fn from(subregion: WorldSubregion) -> Self {
+        Self {
+            region: reference(&subregion.region),
+            id: subregion.id.0.unwrap_or_default() as u64,
+            name: subregion.name,
+        }
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+    use world_tables_base::Key;
+
+    use crate::auth::Role;
+
+    fn authorize(db: Database, anonymous: Option<Role>) -> Authorize {
+        Authorize {
+            db,
+            anonymous: Anonymous(anonymous),
+            rate: RateLimiter::new(0.001, 2.0),
+            attempts: FailedAttempts(RateLimiter::new(0.001, 2.0)),
+        }
+    }
+
+    fn call(key: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(key) = key {
+            request.metadata_mut().insert("authorization", format!("Bearer {key}").parse().unwrap());
+        }
+
+        request
+    }
+
+    #[tokio::test]
+    async fn lists_stream_the_filtered_objects() {
+        let service = WorldTables { db: Database::seeded("grpc-lists"), exports: Exports::new(2) };
+
+        let states = service.stream::<State, pb::State>(vec![]).unwrap().into_inner().collect::<Vec<_>>().await;
+        assert_eq!(states.len(), 3);
+
+        let states = service
+            .stream::<State, pb::State>(vec![("country_id", "BR".to_string())])
+            .unwrap()
+            .into_inner()
+            .map(|state| state.unwrap().name)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(states, ["Sao Paulo", "Bahia"]);
+    }
+
+    #[tokio::test]
+    async fn streams_over_the_limit_are_refused() {
+        let exports = Exports::new(2);
+        let service = WorldTables { db: Database::seeded("grpc-streams"), exports: exports.clone() };
+
+        let running = exports.try_start().unwrap();
+        let refused = service.stream::<State, pb::State>(vec![]).unwrap_err();
+        assert_eq!(refused.code(), Code::ResourceExhausted);
+
+        drop(running);
+        assert!(service.stream::<State, pb::State>(vec![]).is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn calls_are_authorized_and_limited() {
+        let db = Database::seeded("grpc-authorize");
+        let (_, key) = ApiKey::create(&db.connection().unwrap(), "test", Role::Read).unwrap();
+
+        let mut closed = authorize(db.clone(), None);
+        assert_eq!(closed.call(call(None)).unwrap_err().code(), Code::Unauthenticated);
+        assert!(closed.call(call(Some(&key))).is_ok());
+
+        let mut open = authorize(db, Some(Role::Read));
+        assert!(open.call(call(None)).is_ok());
+        assert!(open.call(call(None)).is_ok());
+        assert_eq!(open.call(call(None)).unwrap_err().code(), Code::ResourceExhausted);
+        assert!(open.call(call(Some(&key))).is_ok());
+
+        for _ in 0..2 {
+            assert_eq!(open.call(call(Some("wt_guess"))).unwrap_err().code(), Code::Unauthenticated);
+        }
+        assert_eq!(open.call(call(Some("wt_guess"))).unwrap_err().code(), Code::ResourceExhausted);
+    }
+
+    #[test]
+    fn internal_errors_are_only_logged() {
+        let not_found = status(rusqlite::Error::QueryReturnedNoRows.into());
+        assert_eq!(not_found.code(), Code::NotFound);
+
+        let internal = status(anyhow::anyhow!("no such table: /var/lib/secret"));
+        assert_eq!(internal.code(), Code::Internal);
+        assert!(!internal.message().contains("secret"));
+    }
+
+    #[test]
+    fn related_objects_become_references() {
+        let state = State {
+            id: Key::new(1),
+            name: "Sao Paulo".into(),
+            country: EntityLabel::KeyLabel(Key::new("BR".into()), "Brazil".into()),
+            ..Default::default()
+        };
+
+        let state = pb::State::from(state);
+        assert_eq!(state.id, 1);
+        assert_eq!(state.country, Some(pb::Ref { key: "BR".into(), name: "Brazil".into() }));
+
+        let region = pb::WorldSubregion::from(WorldSubregion { name: "Caribbean".into(), ..Default::default() });
+        assert_eq!(region.region, None);
+    }
+}
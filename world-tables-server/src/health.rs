@@ -0,0 +1,234 @@
+//! Health probes
+//!
+//! `/healthz` answers as long as the process is serving requests, while
+//! `/readyz` also checks the database is usable: a pooled connection can be
+//! obtained, its schema is at the latest migration and the core tables have
+//! been loaded. Orchestrators, and the GUI launching the server, only send
+//! traffic once it answers `200 OK`.
+
+use anyhow::{Context, Result};
+use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
+use lazy_static::lazy_static;
+use rusqlite::Connection;
+use rusqlite_migration::SchemaVersion;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use world_tables_data::MIGRATIONS;
+
+use crate::{Database, cache::Uncached};
+
+lazy_static! {
+    /// Schema version after running all the migrations, found by running them
+    /// on an empty database as they only create the schema
+    static ref LATEST_VERSION: Option<usize> = {
+        let mut conn = Connection::open_in_memory().ok()?;
+        MIGRATIONS.to_latest(&mut conn).ok()?;
+        MIGRATIONS.current_version(&conn).ok().map(version_number)
+    };
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    pub status: &'static str,
+    pub version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub connection: Check,
+    pub migrations: MigrationsCheck,
+    pub tables: TablesCheck,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MigrationsCheck {
+    pub ok: bool,
+    pub version: Option<usize>,
+    pub latest: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TablesCheck {
+    pub ok: bool,
+    /// Whether each core table has any rows
+    pub loaded: BTreeMap<&'static str, bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn failed(error: String) -> Self {
+        Self { ok: false, error: Some(error) }
+    }
+}
+
+/// Tables that must have been loaded, by the name of their entity in the API
+const CORE_TABLES: &[(&str, &str)] = &[
+    ("countries", "countries"),
+    ("states", "states"),
+    ("cities", "cities"),
+    ("regions", "world_regions"),
+    ("subregions", "world_subregions"),
+    ("currencies", "currencies"),
+];
+
+/// Error of the checks that need the connection that couldn't be obtained
+const NO_CONNECTION: &str = "skipped without a database connection";
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=======================  HANDLERS  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+pub async fn healthz() -> impl IntoResponse {
+    (
+        Extension(Uncached),
+        Json(Health { status: "alive", version: env!("CARGO_PKG_VERSION") }),
+    )
+}
+
+pub async fn readyz(Extension(db): Extension<Database>) -> impl IntoResponse {
+    let readiness = match db.connection() {
+        Ok(conn) => Readiness {
+            ready: false,
+            connection: Check { ok: true, error: None },
+            migrations: check_migrations(&conn),
+            tables: check_tables(&conn),
+        },
+        Err(err) => Readiness {
+            ready: false,
+            connection: Check::failed(format!("{err:#}")),
+            migrations: MigrationsCheck {
+                latest: *LATEST_VERSION,
+                error: Some(NO_CONNECTION.into()),
+                ..Default::default()
+            },
+            tables: TablesCheck { error: Some(NO_CONNECTION.into()), ..Default::default() },
+        },
+    };
+
+    let ready = readiness.connection.ok && readiness.migrations.ok && readiness.tables.ok;
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (status, Extension(Uncached), Json(Readiness { ready, ..readiness }))
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  CHECKS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+fn check_migrations(conn: &Connection) -> MigrationsCheck {
+    let latest = *LATEST_VERSION;
+
+    match MIGRATIONS.current_version(conn) {
+        Ok(current) => {
+            let version = version_number(current);
+
+            MigrationsCheck {
+                ok: matches!(current, SchemaVersion::Inside(_)) && Some(version) == latest,
+                version: Some(version),
+                latest,
+                error: None,
+            }
+        },
+        Err(err) => MigrationsCheck {
+            latest,
+            error: Some(err.to_string()),
+            ..Default::default()
+        },
+    }
+}
+
+fn check_tables(conn: &Connection) -> TablesCheck {
+    match loaded_tables(conn) {
+        Ok(loaded) => {
+            let empty: Vec<_> = loaded.iter().filter(|(_, loaded)| !**loaded).map(|(table, _)| *table).collect();
+
+            TablesCheck {
+                ok: empty.is_empty(),
+                error: (!empty.is_empty()).then(|| format!("empty tables: {}", empty.join(", "))),
+                loaded,
+            }
+        },
+        Err(err) => TablesCheck {
+            error: Some(format!("{err:#}")),
+            ..Default::default()
+        },
+    }
+}
+
+/// Whether each core table has a row, without counting all of them as the
+/// probes are frequent
+fn loaded_tables(conn: &Connection) -> Result<BTreeMap<&'static str, bool>> {
+    CORE_TABLES
+        .iter()
+        .map(|(name, table)| {
+            let loaded = conn
+                .query_row(&format!("SELECT EXISTS(SELECT 1 FROM {table})"), [], |row| row.get(0))
+                .with_context(|| format!("Failed checking the {table} table"))?;
+
+            Ok((*name, loaded))
+        })
+        .collect()
+}
+
+fn version_number(version: SchemaVersion) -> usize {
+    match version {
+        SchemaVersion::NoneSet => 0,
+        SchemaVersion::Inside(version) | SchemaVersion::Outside(version) => version.get(),
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn probe(db: Database) -> (StatusCode, Value) {
+        let app = Router::new().route("/readyz", get(readyz)).layer(Extension(db));
+        let response = app.oneshot(Request::get("/readyz").body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn loaded_database_is_ready() {
+        let (status, readiness) = probe(Database::seeded("health-ready")).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(readiness["ready"], true);
+        assert_eq!(readiness["migrations"]["version"], readiness["migrations"]["latest"]);
+        assert!(readiness["tables"]["loaded"].as_object().unwrap().values().all(|loaded| loaded == true));
+    }
+
+    #[tokio::test]
+    async fn empty_database_is_not_ready() {
+        let (status, readiness) = probe(Database::temporary("health-empty")).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(readiness["ready"], false);
+        assert_eq!(readiness["migrations"]["ok"], true);
+        assert_eq!(readiness["tables"]["ok"], false);
+        assert_eq!(readiness["tables"]["loaded"]["regions"], true);
+        assert_eq!(readiness["tables"]["loaded"]["cities"], false);
+        assert_eq!(readiness["tables"]["error"], "empty tables: cities, countries, currencies, states");
+    }
+}
@@ -0,0 +1,301 @@
+//! Related objects embedded in object responses
+//!
+//! The `include` query parameter names the relations to fetch along with an
+//! object, each with an optional limit of objects after a colon, like
+//! `?include=states:20,cities,currency`. Lists fill the `Many` fields and
+//! single objects replace the key and label of their `EntityLabel` field.
+
+use anyhow::Result;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+};
+use rusqlite::Connection;
+use serde::Deserialize;
+use std::marker::PhantomData;
+
+use world_tables_base::{
+    EntityLabel, Keyed, Model, Country, State, City, WorldRegion, WorldSubregion, Currency, Timezone
+};
+
+use crate::metrics::timed;
+
+/// Objects fetched for a list relation when the include has no limit
+pub const DEFAULT_LIMIT: usize = 100;
+/// Highest limit accepted for a list relation
+pub const MAX_LIMIT: usize = 1000;
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  INCLUDES  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Include {
+    pub relation: &'static str,
+    pub limit: usize,
+}
+
+/// Relations asked for an object of type `T`, checked against the ones it has
+#[derive(Debug)]
+pub struct Includes<T> {
+    includes: Vec<Include>,
+    object: PhantomData<T>,
+}
+
+impl<T: Includable> Includes<T> {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut includes = Vec::new();
+
+        for include in text.split(',').map(str::trim).filter(|include| !include.is_empty()) {
+            let (name, limit) = match include.split_once(':') {
+                Some((name, limit)) => {
+                    let limit = limit
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                        .ok_or(format!("Invalid limit for include {name}, it must be from 1 to {MAX_LIMIT}"))?;
+                    (name, limit)
+                },
+                None => (include, DEFAULT_LIMIT),
+            };
+
+            let relation = T::RELATIONS
+                .iter()
+                .find(|relation| **relation == name)
+                .ok_or(format!("Unknown include: {name}, expected one of: {}", T::RELATIONS.join(", ")))?;
+
+            includes.push(Include { relation, limit });
+        }
+
+        Ok(Self { includes, object: PhantomData })
+    }
+
+    /// Fetches every included relation into the object
+    pub fn fetch(&self, conn: &Connection, object: &mut T) -> Result<()> {
+        for include in &self.includes {
+            object.include(conn, include)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct IncludeQuery {
+    include: Option<String>,
+}
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for Includes<T>
+where
+    S: Send + Sync,
+    T: Includable,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<IncludeQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+        Self::parse(query.include.as_deref().unwrap_or_default())
+            .map_err(|err| (StatusCode::BAD_REQUEST, err))
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><======================  RELATIONS  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Object with relations that can be fetched along with it
+pub trait Includable: Send + 'static {
+    const RELATIONS: &'static [&'static str];
+
+    fn include(&mut self, conn: &Connection, include: &Include) -> Result<()>;
+}
+
+/// Replaces the key and label of a relation with the object it points to
+///
+/// A key pointing to no object keeps its key and label, so a dangling
+/// reference in the data doesn't fail the whole response.
+fn fetch_label<K, T, L>(conn: &Connection, label: &mut EntityLabel<K, T, L>) -> Result<()>
+where
+    K: ToString,
+    T: Model + Keyed<KeyType = K>,
+{
+    let key = match label {
+        EntityLabel::KeyLabel(key, _) => key.as_ref().map(ToString::to_string),
+        _ => None,
+    };
+
+    if let Some(key) = key {
+        match timed!(T::get(conn, &key)) {
+            Ok(object) => *label = EntityLabel::Data(Box::new(object)),
+            Err(err) if matches!(err.downcast_ref(), Some(rusqlite::Error::QueryReturnedNoRows)) => {},
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+impl Includable for Country {
+    const RELATIONS: &'static [&'static str] = &["states", "cities", "currency", "timezones"];
+
+    fn include(&mut self, conn: &Connection, include: &Include) -> Result<()> {
+        let key = self.iso2.0.clone().unwrap_or_default();
+
+        match include.relation {
+            "states" => self.states = timed!(State::from_country(conn, &key, include.limit, 0))?.1.into(),
+            "cities" => self.cities = timed!(City::from_country(conn, &key, include.limit, 0))?.1.into(),
+            "currency" => fetch_label(conn, &mut self.currency)?,
+            "timezones" => self.timezones = timed!(Timezone::from_country(conn, &key, include.limit, 0))?.1.into(),
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+impl Includable for State {
+    const RELATIONS: &'static [&'static str] = &["cities", "country"];
+
+    fn include(&mut self, conn: &Connection, include: &Include) -> Result<()> {
+        let key = self.id.map(|id| id.to_string()).unwrap_or_default();
+
+        match include.relation {
+            "cities" => self.cities = timed!(City::from_state(conn, &key, include.limit, 0))?.1.into(),
+            "country" => fetch_label(conn, &mut self.country)?,
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+impl Includable for City {
+    const RELATIONS: &'static [&'static str] = &["state", "country"];
+
+    fn include(&mut self, conn: &Connection, include: &Include) -> Result<()> {
+        match include.relation {
+            "state" => fetch_label(conn, &mut self.state)?,
+            "country" => fetch_label(conn, &mut self.country)?,
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+impl Includable for WorldRegion {
+    const RELATIONS: &'static [&'static str] = &["subregions", "countries"];
+
+    fn include(&mut self, conn: &Connection, include: &Include) -> Result<()> {
+        let key = self.id.map(|id| id.to_string()).unwrap_or_default();
+
+        match include.relation {
+            "subregions" => self.subregions = timed!(WorldSubregion::from_region(conn, &key, include.limit, 0))?.1.into(),
+            "countries" => self.countries = timed!(Country::from_region(conn, &key, include.limit, 0))?.1.into(),
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+impl Includable for WorldSubregion {
+    const RELATIONS: &'static [&'static str] = &["countries", "region"];
+
+    fn include(&mut self, conn: &Connection, include: &Include) -> Result<()> {
+        let key = self.id.map(|id| id.to_string()).unwrap_or_default();
+
+        match include.relation {
+            "countries" => self.countries = timed!(Country::from_subregion(conn, &key, include.limit, 0))?.1.into(),
+            "region" => fetch_label(conn, &mut self.region)?,
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+impl Includable for Currency {
+    const RELATIONS: &'static [&'static str] = &["countries"];
+
+    fn include(&mut self, conn: &Connection, include: &Include) -> Result<()> {
+        let key = self.iso.0.clone().unwrap_or_default();
+
+        if include.relation == "countries" {
+            self.countries = timed!(Country::from_currency(conn, &key, include.limit, 0))?.1.into();
+        }
+
+        Ok(())
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><==========================  TESTS  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world_tables_base::Many;
+
+    use crate::Database;
+
+    #[test]
+    fn limits_follow_the_relation() {
+        let includes = Includes::<Country>::parse("states:20, currency").unwrap();
+
+        assert_eq!(
+            includes.includes,
+            [
+                Include { relation: "states", limit: 20 },
+                Include { relation: "currency", limit: DEFAULT_LIMIT },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_includes_are_rejected() {
+        for text in ["states:0", &format!("states:{}", MAX_LIMIT + 1), "states:x", "states:"] {
+            let err = Includes::<Country>::parse(text).unwrap_err();
+            assert!(err.starts_with("Invalid limit for include states"), "{text}: {err}");
+        }
+
+        assert!(Includes::<Country>::parse(&format!("states:{MAX_LIMIT}")).is_ok());
+        assert!(Includes::<Country>::parse("planets").unwrap_err().starts_with("Unknown include: planets"));
+    }
+
+    #[test]
+    fn relations_are_fetched() {
+        let db = Database::seeded("include-fetch");
+        let conn = db.connection().unwrap();
+        let mut country = Country::get(&conn, "BR").unwrap();
+
+        Includes::parse("states:1, currency").unwrap().fetch(&conn, &mut country).unwrap();
+
+        assert!(matches!(&country.states, Many::Data(states) if states.len() == 1));
+        assert!(matches!(&country.currency, EntityLabel::Data(currency) if currency.symbol == "R$"));
+    }
+
+    #[test]
+    fn dangling_references_are_kept() {
+        let db = Database::seeded("include-dangling");
+        let conn = db.connection().unwrap();
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+            INSERT INTO cities (id, name, state_id, state, country_id, country, latitude, longitude)
+            VALUES (4, 'Atlantis', 99, 'Sunken', 'BR', 'Brazil', 0, 0);",
+        )
+        .unwrap();
+        let mut city = City::get(&conn, "4").unwrap();
+
+        Includes::parse("state, country").unwrap().fetch(&conn, &mut city).unwrap();
+
+        assert!(matches!(&city.state, EntityLabel::KeyLabel(key, label) if key.0 == Some(99) && label == "Sunken"));
+        assert!(matches!(&city.country, EntityLabel::Data(country) if country.name == "Brazil"));
+    }
+}
@@ -0,0 +1,367 @@
+//! Per-client rate limiting
+//!
+//! Every client, told apart by the API key it was authenticated with or else
+//! by its IP address, has a token bucket refilled at a steady rate up to a
+//! burst size. Requests take tokens in proportion to the rows they ask for
+//! through `limit`, so paging with huge pages runs out of tokens as fast as
+//! making many small requests, and GraphQL queries take more of them with
+//! their complexity. Clients without enough tokens get a
+//! `429 Too Many Requests` telling them with `Retry-After` when to try again.
+//!
+//! The limit runs after the authorization, so a key only gets a bucket of its
+//! own once it is known to be valid. Requests with invalid keys are counted
+//! by address in buckets of their own, and are refused before the key is even
+//! looked up once it is empty. IPv6 clients are told apart by their /64
+//! network, which a single host usually has all of.
+//!
+//! Past `MAX_CLIENTS`, the least recently seen client is only forgotten once its
+//! bucket is full again, as a new bucket would be. While none is, the new
+//! clients share a bucket, so throttled clients can't be pushed out to start
+//! over.
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use lru::LruCache;
+use std::{
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use world_tables_base::Int;
+
+use crate::auth::KeyId;
+
+/// Rows a request may ask for with each token it takes
+const ROWS_PER_TOKEN: usize = 100;
+/// Complexity of a GraphQL query for each token it takes, about the fields of
+/// `ROWS_PER_TOKEN` rows
+pub const COMPLEXITY_PER_TOKEN: usize = ROWS_PER_TOKEN * 10;
+/// Clients tracked before forgetting the least recently seen ones
+const MAX_CLIENTS: usize = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Client {
+    /// Authenticated with the API key of this id
+    Key(Int),
+    Ip(IpAddr),
+    /// Connected through a Unix socket
+    Unknown,
+}
+
+impl Client {
+    /// Client of a request, by its authenticated key or else by its address
+    pub fn of<B>(request: &Request<B>) -> Self {
+        let extensions = request.extensions();
+
+        if let Some(KeyId(id)) = extensions.get::<KeyId>() {
+            return Client::Key(*id);
+        }
+
+        extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| Client::address(addr.ip()))
+            .unwrap_or(Client::Unknown)
+    }
+
+    /// Client of an address, the /64 network of an IPv6 one
+    pub fn address(ip: IpAddr) -> Self {
+        match ip.to_canonical() {
+            IpAddr::V6(ip) => Client::Ip(IpAddr::V6((u128::from(ip) & !u128::from(u64::MAX)).into())),
+            ip => Client::Ip(ip),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    clients: LruCache<Client, Bucket>,
+    /// Bucket of the new clients while every known one is being throttled
+    overflow: Bucket,
+}
+
+/// Token buckets of the clients, shared by all the requests
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// Tokens added to a bucket per second, 0 disables the limit
+    rate: f64,
+    /// Tokens a bucket holds at most
+    burst: f64,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst: burst.max(1.0),
+            buckets: Arc::new(Mutex::new(
+                Buckets {
+                    clients: LruCache::new(NonZeroUsize::new(MAX_CLIENTS).unwrap()),
+                    overflow: Bucket { tokens: burst.max(1.0), updated: Instant::now() },
+                }
+            )),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rate > 0.0
+    }
+
+    /// Takes tokens from the bucket of a client, returning the seconds to wait
+    /// when there are not enough of them
+    pub fn take(&self, client: Client, cost: f64) -> Result<(), f64> {
+        // a request costing more than a full bucket would never go through
+        let cost = cost.min(self.burst);
+
+        self.with_bucket(client, |bucket| {
+            if bucket.tokens >= cost {
+                bucket.tokens -= cost;
+                Ok(())
+            } else {
+                Err((cost - bucket.tokens) / self.rate)
+            }
+        })
+    }
+
+    /// Checks that a client has a token left, without taking it
+    pub fn check(&self, client: Client) -> Result<(), f64> {
+        self.with_bucket(client, |bucket| {
+            if bucket.tokens >= 1.0 {
+                Ok(())
+            } else {
+                Err((1.0 - bucket.tokens) / self.rate)
+            }
+        })
+    }
+
+    /// Handle to charge `client` for more later
+    pub fn charge(&self, client: Client) -> Charge {
+        Charge { limiter: self.clone(), client }
+    }
+
+    /// Runs `f` on the bucket of a client refilled up to now, making it the
+    /// most recently seen one
+    fn with_bucket(&self, client: Client, f: impl FnOnce(&mut Bucket) -> Result<(), f64>) -> Result<(), f64> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let refilled = |bucket: &Bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * self.rate).min(self.burst)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { clients, overflow } = &mut *buckets;
+
+        let known = clients.contains(&client)
+            || clients.len() < clients.cap().get()
+            // the bucket pushed out must be as full as a new one
+            || clients.peek_lru().is_some_and(|(_, bucket)| refilled(bucket) >= self.burst);
+
+        let bucket = if known {
+            clients.get_or_insert_mut(client, || Bucket { tokens: self.burst, updated: now })
+        } else {
+            overflow
+        };
+
+        bucket.tokens = refilled(bucket);
+        bucket.updated = now;
+
+        f(bucket)
+    }
+}
+
+/// Handle to take more tokens from the bucket a request was charged to, for
+/// the handlers that only know its cost once they've parsed it
+#[derive(Clone)]
+pub struct Charge {
+    limiter: RateLimiter,
+    client: Client,
+}
+
+impl Charge {
+    pub fn take(&self, cost: f64) -> Result<(), f64> {
+        self.limiter.take(self.client.clone(), cost)
+    }
+}
+
+pub async fn rate_limit<B>(
+    State(limiter): State<RateLimiter>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !limiter.is_enabled() {
+        return next.run(request).await;
+    }
+
+    let client = Client::of(&request);
+
+    let rows = request
+        .uri()
+        .query()
+        .and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("limit="))
+                .and_then(|limit| limit.parse::<usize>().ok())
+        })
+        .unwrap_or_default();
+
+    let cost = rows.div_ceil(ROWS_PER_TOKEN).max(1) as f64;
+
+    match limiter.take(client.clone(), cost) {
+        Ok(()) => {
+            request.extensions_mut().insert(limiter.charge(client));
+            next.run(request).await
+        },
+        Err(wait) => too_many_requests(wait),
+    }
+}
+
+pub fn too_many_requests(wait: f64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, HeaderValue::from(wait.ceil() as u64))],
+        "Too many requests, try again later",
+    )
+        .into_response()
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    use crate::{Database, auth::{authorize, Anonymous, ApiKey, FailedAttempts, Role}};
+
+    /// Throttled for good once a bucket is empty
+    const RATE: f64 = 0.001;
+    const BURST: f64 = 3.0;
+
+    fn app(db: &Database) -> Router {
+        limited_app(db, RateLimiter::new(RATE, BURST), FailedAttempts(RateLimiter::new(RATE, BURST)))
+    }
+
+    fn limited_app(db: &Database, limiter: RateLimiter, attempts: FailedAttempts) -> Router {
+        Router::new()
+            .route("/data", get(|| async { "data" }))
+            .layer(middleware::from_fn_with_state(limiter, rate_limit))
+            .layer(middleware::from_fn_with_state(Anonymous(Some(Role::Read)), authorize))
+            .layer(Extension(attempts))
+            .layer(Extension(db.clone()))
+    }
+
+    async fn send(app: &Router, path: &str, key: Option<&str>) -> StatusCode {
+        let mut request = Request::get(path);
+        if let Some(key) = key {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {key}"));
+        }
+
+        let mut request = request.body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 1], 4000))));
+
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn invalid_keys_are_limited_by_address() {
+        let app = app(&Database::temporary("limit-invalid"));
+
+        for n in 0..BURST as usize {
+            assert_eq!(send(&app, "/data", Some(&format!("wt_guess{n}"))).await, StatusCode::UNAUTHORIZED);
+        }
+
+        assert_eq!(send(&app, "/data", Some("wt_another")).await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(send(&app, "/data", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn invalid_keys_are_limited_without_a_rate_limit() {
+        let app = limited_app(&Database::temporary("limit-disabled"), RateLimiter::new(0.0, BURST), FailedAttempts::default());
+
+        let mut statuses = Vec::new();
+        for n in 0..100 {
+            statuses.push(send(&app, "/data", Some(&format!("wt_guess{n}"))).await);
+        }
+
+        assert!(statuses.contains(&StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(send(&app, "/data", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn valid_keys_have_their_own_bucket() {
+        let db = Database::temporary("limit-valid");
+        let (_, key) = ApiKey::create(&db.connection().unwrap(), "test", Role::Read).unwrap();
+        let app = app(&db);
+
+        for _ in 0..BURST as usize {
+            assert_eq!(send(&app, "/data", None).await, StatusCode::OK);
+        }
+        assert_eq!(send(&app, "/data", None).await, StatusCode::TOO_MANY_REQUESTS);
+
+        assert_eq!(send(&app, "/data", Some(&key)).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn limit_takes_tokens_by_rows() {
+        let app = app(&Database::temporary("limit-rows"));
+
+        assert_eq!(send(&app, &format!("/data?limit={}", 2 * ROWS_PER_TOKEN), None).await, StatusCode::OK);
+        assert_eq!(send(&app, &format!("/data?limit={}", 2 * ROWS_PER_TOKEN), None).await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(send(&app, "/data", None).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn throttled_clients_are_never_forgotten() {
+        let limiter = RateLimiter::new(RATE, 1.0);
+        let throttled = Client::Ip(IpAddr::from([192, 0, 2, 1]));
+        let others = |range: std::ops::Range<u32>| range.map(|n| Client::Ip(IpAddr::from(n.to_be_bytes())));
+
+        // idle clients, with full buckets
+        for client in others(1..MAX_CLIENTS as u32) {
+            assert!(limiter.take(client, 0.0).is_ok());
+        }
+        assert!(limiter.take(throttled.clone(), 1.0).is_ok());
+
+        // push out the idle ones
+        for client in others(MAX_CLIENTS as u32..2 * MAX_CLIENTS as u32 - 1) {
+            assert!(limiter.take(client, 1.0).is_ok());
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().clients.len(), MAX_CLIENTS);
+        assert!(limiter.take(throttled.clone(), 1.0).is_err());
+
+        // every known client is throttled, so the new ones share a bucket
+        let mut new = others(2 * MAX_CLIENTS as u32..2 * MAX_CLIENTS as u32 + 2);
+        assert!(limiter.take(new.next().unwrap(), 1.0).is_ok());
+        assert!(limiter.take(new.next().unwrap(), 1.0).is_err());
+        assert!(limiter.take(throttled, 1.0).is_err());
+    }
+
+    #[test]
+    fn ipv6_clients_are_told_apart_by_network() {
+        let client = |ip: &str| Client::address(ip.parse().unwrap());
+
+        assert_eq!(client("2001:db8:1:2:aaaa::1"), client("2001:db8:1:2:bbbb::2"));
+        assert_ne!(client("2001:db8:1:2::1"), client("2001:db8:1:3::1"));
+        assert_eq!(client("::ffff:192.0.2.1"), client("192.0.2.1"));
+        assert_ne!(client("192.0.2.1"), client("192.0.2.2"));
+    }
+}
@@ -0,0 +1,474 @@
+//! Listening for connections
+//!
+//! By default the server listens on a random TCP port of 127.0.0.1, which any
+//! local user can connect to, or on the address given with `--address`. With
+//! `--socket` it listens on a Unix domain socket instead, whose file
+//! permissions, set with `--socket-mode`, decide who can connect.
+//!
+//! With `--tls-cert` and `--tls-key` the TCP listener serves HTTPS with
+//! `rustls`, optionally along with a plain HTTP listener on `--redirect-http`
+//! that redirects every request to HTTPS.
+//!
+//! The `Endpoint` of a listener is where its clients connect, which is what
+//! the GUI is told and where the server probes its own readiness.
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{header, uri::Authority, HeaderMap, StatusCode, Uri},
+    response::Redirect,
+    Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ServerName,
+};
+use std::{
+    fmt,
+    fs::File,
+    future::Future,
+    io::BufReader,
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::Path,
+};
+
+/// Time the open connections of an HTTPS server have to finish on shutdown,
+/// as event streams never do on their own
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Where the server accepts connections
+pub enum Listener {
+    Tcp(TcpListener),
+    Tls(TcpListener, Tls),
+    #[cfg(unix)]
+    Unix(PathBuf, tokio::net::UnixListener),
+}
+
+/// Certificate files and options of HTTPS
+pub struct TlsOptions {
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key of the certificate
+    pub key: PathBuf,
+    /// PEM certificate of the CA the GUI trusts, the certificate itself if none
+    pub ca: Option<PathBuf>,
+    /// Address of a plain HTTP listener redirecting to HTTPS
+    pub redirect: Option<SocketAddr>,
+}
+
+/// HTTPS configuration of a listener
+pub struct Tls {
+    config: RustlsConfig,
+    /// First certificate of the chain, the one of the server
+    certificate: Certificate,
+    ca: PathBuf,
+    redirect: Option<TcpListener>,
+}
+
+impl Listener {
+    /// Listens on the socket file at `path` when given, else on `address`, or
+    /// a random port of the loopback interface without one
+    pub fn bind(address: Option<SocketAddr>, socket: Option<PathBuf>, mode: u32) -> Result<Self> {
+        match socket {
+            None => {
+                let address = address.unwrap_or_else(|| (Ipv4Addr::LOCALHOST, 0).into());
+                let listener = TcpListener::bind(address).with_context(|| format!("Failed binding {address}"))?;
+                Ok(Listener::Tcp(listener))
+            },
+            #[cfg(unix)]
+            Some(path) => {
+                let listener = bind_socket(&path, mode)?;
+                Ok(Listener::Unix(path, listener))
+            },
+            #[cfg(not(unix))]
+            Some(_) => {
+                let _ = mode;
+                anyhow::bail!("Unix domain sockets are not supported on this platform")
+            },
+        }
+    }
+
+    /// Serves HTTPS instead of HTTP on the TCP listener
+    pub async fn with_tls(self, options: TlsOptions) -> Result<Self> {
+        let Listener::Tcp(listener) = self else {
+            anyhow::bail!("HTTPS is only served over TCP");
+        };
+
+        let config = RustlsConfig::from_pem_file(&options.cert, &options.key)
+            .await
+            .context("Failed loading the TLS certificate and key")?;
+
+        let file = File::open(&options.cert).with_context(|| format!("Failed opening {:?}", options.cert))?;
+        let certificate = rustls_pemfile::certs(&mut BufReader::new(file))?
+            .into_iter()
+            .next()
+            .map(Certificate)
+            .with_context(|| format!("No certificate in {:?}", options.cert))?;
+
+        let redirect = options
+            .redirect
+            .map(|address| TcpListener::bind(address).with_context(|| format!("Failed binding {address}")))
+            .transpose()?;
+
+        // the GUI runs in the directory of the executable
+        let ca = options.ca.unwrap_or(options.cert);
+        let ca = std::fs::canonicalize(&ca).with_context(|| format!("Failed finding {ca:?}"))?;
+
+        Ok(Listener::Tls(listener, Tls { config, certificate, ca, redirect }))
+    }
+
+    /// Where clients connect to this listener
+    pub fn endpoint(&self) -> Result<Endpoint> {
+        Ok(match self {
+            Listener::Tcp(listener) => Endpoint::Tcp(local(listener.local_addr()?)),
+            Listener::Tls(listener, tls) => Endpoint::Https {
+                addr: local(listener.local_addr()?),
+                certificate: tls.certificate.clone(),
+                ca: tls.ca.clone(),
+            },
+            #[cfg(unix)]
+            Listener::Unix(path, _) => Endpoint::Unix(path.clone()),
+        })
+    }
+
+    /// Serves the app until `shutdown` completes
+    pub async fn serve(self, app: Router, shutdown: impl Future<Output = ()>) -> Result<()> {
+        match self {
+            Listener::Tcp(listener) => {
+                axum::Server::from_tcp(listener)?
+                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                    .with_graceful_shutdown(shutdown)
+                    .await?;
+            },
+            Listener::Tls(listener, tls) => {
+                let handle = Handle::new();
+                let port = listener.local_addr()?.port();
+
+                let server = axum_server::from_tcp_rustls(listener, tls.config)
+                    .handle(handle.clone())
+                    .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+
+                let redirect = tls.redirect.map(|listener| {
+                    axum_server::from_tcp(listener)
+                        .handle(handle.clone())
+                        .serve(Router::new().fallback(redirect).with_state(port).into_make_service())
+                });
+
+                let servers = async {
+                    match redirect {
+                        Some(redirect) => tokio::try_join!(server, redirect).map(|_| ()),
+                        None => server.await,
+                    }
+                };
+                tokio::pin!(servers);
+
+                tokio::select! {
+                    result = &mut servers => result?,
+                    () = shutdown => {
+                        handle.graceful_shutdown(Some(GRACE_PERIOD));
+                        servers.await?;
+                    },
+                }
+            },
+            #[cfg(unix)]
+            Listener::Unix(path, listener) => {
+                // connections have no address, so the rate limit shares a single
+                // bucket between the requests without an API key
+                axum::Server::builder(hyperlocal::SocketIncoming::from_listener(listener))
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(shutdown)
+                    .await?;
+
+                fs::remove_file(&path).with_context(|| format!("Failed removing socket {path:?}"))?;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{addr}"),
+                Err(_) => write!(f, "TCP"),
+            },
+            Listener::Tls(listener, tls) => {
+                match listener.local_addr() {
+                    Ok(addr) => write!(f, "https://{addr}")?,
+                    Err(_) => write!(f, "HTTPS")?,
+                }
+
+                match tls.redirect.as_ref().map(TcpListener::local_addr) {
+                    Some(Ok(addr)) => write!(f, ", redirecting http://{addr}"),
+                    _ => Ok(()),
+                }
+            },
+            #[cfg(unix)]
+            Listener::Unix(path, _) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Redirects a plain HTTP request to the same host and path on the HTTPS
+/// `port`
+async fn redirect(State(port): State<u16>, headers: HeaderMap, uri: Uri) -> Result<Redirect, StatusCode> {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    Ok(match port {
+        443 => Redirect::permanent(&format!("https://{}{path}", host.host())),
+        port => Redirect::permanent(&format!("https://{}:{port}{path}", host.host())),
+    })
+}
+
+/// Address to connect to `addr` locally, as listening on all the interfaces
+/// includes the loopback one
+fn local(addr: SocketAddr) -> SocketAddr {
+    match addr.ip().is_unspecified() {
+        true => (Ipv4Addr::LOCALHOST, addr.port()).into(),
+        false => addr,
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=======================  ENDPOINT  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Address of a listener
+#[derive(Clone, Debug)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    Https {
+        addr: SocketAddr,
+        certificate: Certificate,
+        ca: PathBuf,
+    },
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    /// Arguments telling the GUI where to connect
+    pub fn gui_args(&self) -> Result<Vec<String>> {
+        Ok(match self {
+            Endpoint::Tcp(addr) => vec!["-a".into(), addr.to_string()],
+            // the name the certificates of local servers are made for
+            Endpoint::Https { addr, ca, .. } => vec![
+                "-a".into(),
+                format!("https://localhost:{}", addr.port()),
+                "--ca-cert".into(),
+                ca.to_str().context("invalid unicode on CA certificate path")?.into(),
+            ],
+            #[cfg(unix)]
+            Endpoint::Unix(path) => vec![
+                "--socket".into(),
+                path.to_str().context("invalid unicode on socket path")?.into(),
+            ],
+        })
+    }
+
+    /// Status of `GET /readyz` on this endpoint
+    pub async fn readiness(&self) -> Result<StatusCode> {
+        let response = match self {
+            Endpoint::Tcp(addr) => {
+                let uri: Uri = format!("http://{addr}/readyz").parse()?;
+                hyper::Client::new().get(uri).await?
+            },
+            Endpoint::Https { addr, certificate, .. } => {
+                let config = rustls::ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_custom_certificate_verifier(Arc::new(Pinned(certificate.clone())))
+                    .with_no_client_auth();
+
+                let connector = hyper_rustls::HttpsConnectorBuilder::new()
+                    .with_tls_config(config)
+                    .https_only()
+                    .enable_http1()
+                    .build();
+
+                let uri: Uri = format!("https://{addr}/readyz").parse()?;
+                hyper::Client::builder().build::<_, hyper::Body>(connector).get(uri).await?
+            },
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                hyper::Client::builder()
+                    .build::<_, hyper::Body>(hyperlocal::UnixConnector)
+                    .get(hyperlocal::Uri::new(path, "/readyz").into())
+                    .await?
+            },
+        };
+
+        Ok(response.status())
+    }
+}
+
+/// Verifier trusting only the certificate the server was given, which is all
+/// the server needs to probe itself, whatever names it was made for
+struct Pinned(Certificate);
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match *end_entity == self.0 {
+            true => Ok(ServerCertVerified::assertion()),
+            false => Err(rustls::Error::General("not the certificate of the server".into())),
+        }
+    }
+}
+
+/// Binds a socket file at `path` with the permissions of `mode`
+///
+/// The socket is bound in a private directory, where nobody else can connect
+/// before its permissions are set, and then moved to `path`. Only a socket
+/// left behind by a server that didn't shut down cleanly is replaced there,
+/// never another kind of file.
+#[cfg(unix)]
+fn bind_socket(path: &Path, mode: u32) -> Result<tokio::net::UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            // a server still accepting on the socket keeps it
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => anyhow::bail!("{path:?} is already in use by another server"),
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    fs::remove_file(path).with_context(|| format!("Failed removing old socket {path:?}"))?;
+                },
+                Err(err) => return Err(err).with_context(|| format!("Failed checking socket {path:?}")),
+            }
+        },
+        Ok(_) => anyhow::bail!("{path:?} exists and is not a socket"),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+        Err(err) => return Err(err).with_context(|| format!("Failed checking {path:?}")),
+    }
+
+    let file_name = path.file_name().with_context(|| format!("No file name in socket path {path:?}"))?;
+    let private = path.with_file_name(format!(".{}.{}", file_name.to_string_lossy(), std::process::id()));
+
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .with_context(|| format!("Failed creating {private:?}"))?;
+
+    let bound = (|| {
+        let socket = private.join(file_name);
+        let listener = tokio::net::UnixListener::bind(&socket)
+            .with_context(|| format!("Failed binding socket {path:?}"))?;
+
+        fs::set_permissions(&socket, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Failed setting permissions of socket {path:?}"))?;
+        fs::rename(&socket, path).with_context(|| format!("Failed moving socket to {path:?}"))?;
+
+        Ok(listener)
+    })();
+
+    let _ = fs::remove_dir_all(&private);
+    bound
+}
+
+/// Parses permissions in octal, like `600`
+pub fn parse_mode(mode: &str) -> Result<u32> {
+    let mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .with_context(|| format!("invalid octal permissions: {mode}"))?;
+
+    anyhow::ensure!(mode <= 0o777, "permissions out of range: {mode:o}");
+    Ok(mode)
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// Empty directory of its own for each test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("world-tables-listen-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn socket_has_the_mode_once_bound() {
+        let dir = test_dir("mode");
+        let path = dir.join("server.sock");
+
+        let _listener = bind_socket(&path, 0o600).unwrap();
+        let metadata = fs::symlink_metadata(&path).unwrap();
+
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1, "private directory left behind");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stale_socket_is_replaced() {
+        let dir = test_dir("stale");
+        let path = dir.join("server.sock");
+
+        drop(bind_socket(&path, 0o600).unwrap());
+        assert!(bind_socket(&path, 0o600).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn live_socket_is_kept() {
+        let dir = test_dir("live");
+        let path = dir.join("server.sock");
+
+        let _listener = bind_socket(&path, 0o600).unwrap();
+        let err = bind_socket(&path, 0o600).unwrap_err();
+
+        assert!(err.to_string().contains("already in use"));
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_files_are_never_removed() {
+        let dir = test_dir("other");
+        let file = dir.join("data.txt");
+        fs::write(&file, "keep").unwrap();
+
+        assert!(bind_socket(&file, 0o600).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep");
+
+        let link = dir.join("link.sock");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        assert!(bind_socket(&link, 0o600).is_err());
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,12 @@
 
 use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
 use axum::{
-    http::{HeaderMap, StatusCode},
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post, MethodRouter, Route},
     extract::{Path, Query},//FromRequestParts,
     Extension,
     Router,
@@ -13,35 +16,276 @@ use directories::ProjectDirs;
 use log::{info, debug};
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
-    net::TcpListener,
-    process::Command,
+    convert::Infallible,
+    process::{Command, ExitCode},
     path::PathBuf,
     env,
-    thread,
-    time,
+    net::SocketAddr,
+    time::{self, Duration},
 };
 use tokio::signal;
-use tower_http::compression::CompressionLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tower::{Layer, Service};
+use tower_http::compression::{
+    predicate::{DefaultPredicate, NotForContentType, Predicate},
+    CompressionLayer,
+};
 
-use world_tables_base::{Model, Country, State, City, WorldRegion, WorldSubregion, Currency, UrlBuilder, Metadata};
-use world_tables_data::MIGRATIONS;
+use world_tables_base::{Model, Keyed, Selectable, Country, State, City, WorldRegion, WorldSubregion, Currency, UrlBuilder, Metadata};
+use world_tables_data::{MIGRATIONS, fill_timezones};
+
+mod admin;
+mod auth;
+mod cache;
+mod conditional;
+mod cors;
+mod events;
+mod export;
+mod fields;
+mod format;
+mod graphql;
+mod grpc;
+mod health;
+mod include;
+mod limit;
+mod listen;
+mod metrics;
+mod openapi;
+mod stats;
+mod supervisor;
+mod trace;
+mod v1;
+mod web;
+
+use admin::CheckpointMode;
+use auth::{Anonymous, ApiKey, FailedAttempts, Role, authorize};
+use cache::{ResponseCache, Uncached, cached};
+use conditional::{CachePolicy, DataVersion, conditional};
+use events::Events;
+use export::Exports;
+use fields::Fields;
+use include::Includes;
+use limit::{RateLimiter, rate_limit};
+use listen::{Listener, TlsOptions};
+use metrics::timed;
+use supervisor::GuiExit;
+use format::{CsvRecord, Format, Geometry, list_response, object_response};
+use openapi::OPENAPI;
+use trace::LogFormat;
 
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 //<<>><==========================  MAIN  ============================><<>>//
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+#[clap(propagate_version = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Role of the requests without an API key
+    #[arg(long, value_enum, default_value_t = Role::Read, value_name = "ROLE")]
+    anonymous_role: Role,
+
+    /// Refuse the requests without an API key
+    #[arg(long, conflicts_with = "anonymous_role")]
+    require_key: bool,
+
+    /// Seconds clients may reuse a GET response before revalidating it
+    #[arg(long, default_value_t = 60, value_name = "SECONDS")]
+    max_age: u64,
+
+    /// Number of GET responses kept in memory, 0 disables the cache
+    #[arg(long, default_value_t = 256, value_name = "RESPONSES")]
+    cache_capacity: usize,
+
+    /// Megabytes of GET responses kept in memory, a sixteenth of it at most for each
+    #[arg(long, default_value_t = 64, value_name = "MEGABYTES")]
+    cache_size: usize,
+
+    /// Requests per second allowed to each client, by API key or IP address, 0 disables the limit but for invalid keys
+    #[arg(long, default_value_t = 20.0, value_name = "REQUESTS")]
+    rate_limit: f64,
+
+    /// Requests a client may make at once before being held to the rate limit
+    #[arg(long, default_value_t = 40.0, value_name = "REQUESTS")]
+    rate_burst: f64,
+
+    /// Origin allowed to make cross-origin requests, `*` for any, repeatable
+    #[arg(long = "cors-origin", value_name = "ORIGIN")]
+    cors_origins: Vec<String>,
+
+    /// Method allowed in cross-origin requests, repeatable
+    #[arg(long = "cors-method", value_name = "METHOD", default_values_t = ["GET", "HEAD", "POST"].map(String::from))]
+    cors_methods: Vec<String>,
+
+    /// Response header exposed to cross-origin scripts besides the ones of the API, repeatable
+    #[arg(long = "cors-expose-header", value_name = "HEADER")]
+    cors_exposed_headers: Vec<String>,
+
+    /// Milliseconds between checks for changes published on /events
+    #[arg(long, default_value_t = 1000, value_name = "MILLISECONDS")]
+    events_interval: u64,
+
+    /// Format of the logs, `json` logging each request with its id, method, route, status and latency as fields
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, value_name = "FORMAT")]
+    log_format: LogFormat,
+
+    /// Directory of the web GUI bundle served at /app, `web` next to the server executable by default
+    #[arg(long, value_name = "DIR")]
+    web_dir: Option<PathBuf>,
+
+    /// Address to listen on, a random port of 127.0.0.1 by default
+    #[arg(long, value_name = "ADDR", conflicts_with = "socket")]
+    address: Option<SocketAddr>,
+
+    /// PEM certificate chain, serving HTTPS along with `--tls-key`
+    #[arg(long, value_name = "PATH", requires = "tls_key", conflicts_with = "socket")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the TLS certificate
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM certificate of the CA the launched GUI trusts, the TLS certificate itself by default
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    tls_ca: Option<PathBuf>,
+
+    /// Also listen for plain HTTP on this address, redirecting every request to HTTPS
+    #[arg(long, value_name = "ADDR", requires = "tls_cert")]
+    redirect_http: Option<SocketAddr>,
+
+    /// Address of the gRPC service, which is off without one
+    #[arg(long, value_name = "ADDR")]
+    grpc_address: Option<SocketAddr>,
+
+    /// Listen on a Unix domain socket at this path instead of a TCP port of 127.0.0.1
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Permissions of the socket file, in octal, deciding which local users can connect
+    #[arg(long, default_value = "600", value_parser = listen::parse_mode, value_name = "MODE")]
+    socket_mode: u32,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Manages the API keys, instead of running the server
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+    /// Backs up and checks the database, even while a server is running
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// Creates a key, printing it only this once
+    Create {
+        /// Who or what the key is for
+        name: String,
+
+        /// Permissions of the key
+        #[arg(short, long, value_enum, default_value_t = Role::Read)]
+        role: Role,
+    },
+    /// Revokes a key, refusing its requests from then on
+    Revoke {
+        /// Id of the key, as listed
+        id: usize,
+    },
+    /// Lists the keys, without the keys themselves
+    List,
+}
+
+impl KeysCommand {
+    fn execute(self, db: &Database) -> Result<()> {
+        let conn = db.connection()?;
+
+        match self {
+            KeysCommand::Create { name, role } => {
+                let (api_key, key) = ApiKey::create(&conn, &name, role)?;
+                println!("Created key {} for {} with the {} role:", api_key.id, api_key.name, api_key.role);
+                println!("{key}");
+            },
+            KeysCommand::Revoke { id } => {
+                if !ApiKey::revoke(&conn, id)? {
+                    bail!("no active key {id} to revoke");
+                }
+                println!("Revoked key {id}");
+            },
+            KeysCommand::List => {
+                println!("{:>4}  {:<24}  {:<6}  {:<19}  REVOKED", "ID", "NAME", "ROLE", "CREATED");
+                for api_key in ApiKey::all(&conn)? {
+                    println!(
+                        "{:>4}  {:<24}  {:<6}  {:<19}  {}",
+                        api_key.id,
+                        api_key.name,
+                        api_key.role,
+                        api_key.created_at,
+                        api_key.revoked_at.as_deref().unwrap_or("-"),
+                    );
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Subcommand)]
+enum DbCommand {
+    /// Copies the database to a new file with the online backup API
+    Backup {
+        /// File to create
+        path: PathBuf,
+    },
+    /// Writes a compacted copy of the database to a new file with `VACUUM INTO`
+    Vacuum {
+        /// File to create
+        path: PathBuf,
+    },
+    /// Checks the integrity and the foreign keys of the database, failing on
+    /// any problem
+    Check,
+    /// Checkpoints the write-ahead log into the database
+    Checkpoint {
+        #[arg(short, long, value_enum, default_value_t = CheckpointMode::Passive)]
+        mode: CheckpointMode,
+    },
+}
+
+impl DbCommand {
+    fn execute(self, db: &Database) -> Result<ExitCode> {
+        let conn = db.connection()?;
+
+        let (report, ok) = match self {
+            DbCommand::Backup { path } => (serde_json::to_string_pretty(&admin::backup(&conn, &path)?)?, true),
+            DbCommand::Vacuum { path } => (serde_json::to_string_pretty(&admin::vacuum_into(&conn, &path)?)?, true),
+            DbCommand::Check => {
+                let report = admin::check(&conn)?;
+                (serde_json::to_string_pretty(&report)?, report.ok)
+            },
+            DbCommand::Checkpoint { mode } => (serde_json::to_string_pretty(&admin::checkpoint(&conn, mode)?)?, true),
+        };
+
+        println!("{report}");
+
+        Ok(if ok { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+    }
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "world_tables_server=trace".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+async fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
+
+    trace::init(cli.log_format);
 
     let mut db_path = ProjectDirs::from("", "", "world-tables")
         .expect("no valid home directory path could be retrieved from the operating system")
@@ -71,75 +315,263 @@ async fn main() -> Result<()> {
         }
     }
 
-    let url = UrlBuilder::new();
+    let db = init_db(db_path)?;
 
-    let app = Router::new()
-        .route("/", get(api_index))
-        .route(&url.for_metadata().path(), get(metadata))
-
-        .route(&url.for_countries().path(), get(countries_index))
-        .route(&url.for_states().path(), get(states_index))
-        .route(&url.for_cities().path(), get(cities_index))
-        .route(&url.for_world_regions().path(), get(world_regions_index))
-        .route(&url.for_world_subregions().path(), get(world_subregions_index))
-        .route(&url.for_currencies().path(), get(currencies_index))
-
-        .route(&url.for_country(":key").path(), get(country_data))
-        .route(&url.for_state(":key").path(), get(state_data))
-        .route(&url.for_city(":key").path(), get(city_data))
-        .route(&url.for_world_region(":key").path(), get(region_data))
-        .route(&url.for_world_subregion(":key").path(), get(subregion_data))
-        .route(&url.for_currency(":key").path(), get(currency_data))
-
-        .route(&url.for_countries_from_region(":key").path(), get(countries_from_region))
-        .route(&url.for_countries_from_subregion(":key").path(), get(countries_from_subregion))
-        .route(&url.for_countries_from_currency(":key").path(), get(countries_from_currency))
-        .route(&url.for_states_from_country(":key").path(), get(states_from_country))
-        .route(&url.for_cities_from_country(":key").path(), get(cities_from_country))
-        .route(&url.for_cities_from_state(":key").path(), get(cities_from_state))
-        .route(&url.for_subregions_from_region(":key").path(), get(subregions_from_region))
-
-        .layer(init_db(db_path)?)
-        .layer(CompressionLayer::new());
-
-    let listener = TcpListener::bind("127.0.0.1:0")?;
-    let addr = listener.local_addr()?;
+    match cli.command {
+        Some(Commands::Keys { command }) => return command.execute(&db).map(|()| ExitCode::SUCCESS),
+        Some(Commands::Db { command }) => return command.execute(&db),
+        None => {},
+    }
+
+    let anonymous = Anonymous((!cli.require_key).then_some(cli.anonymous_role));
+    // shared by the REST routes and the gRPC calls
+    let limits = grpc::Limits {
+        anonymous,
+        rate: RateLimiter::new(cli.rate_limit, cli.rate_burst),
+        attempts: FailedAttempts::default(),
+        exports: Exports::new(db.pool_max_size()),
+    };
+    let grpc_db = db.0.clone();
+
+
+    let events = Events::spawn(db.0.clone(), Duration::from_millis(cli.events_interval))?;
+
+    // event streams are flushed event by event, which compression would hold back
+    let compression = CompressionLayer::new()
+        .compress_when(DefaultPredicate::new().and(NotForContentType::const_new("text/event-stream")));
+
+    let cors = cors::layer(&cli.cors_origins, &cli.cors_methods, &cli.cors_exposed_headers)?;
+
+    let web_dir = cli.web_dir.unwrap_or_else(|| work_dir.join("web"));
+
+    let api = api_router();
+    let routes = metrics::Routes::new(&[api.paths.clone(), probe_router().paths].concat());
+    let cache_policy = CachePolicy { max_age: Duration::from_secs(cli.max_age), uncached: metrics::Routes::new(&api.uncached) };
+
+    let app = api
+        .into_router()
+        .layer(middleware::from_fn(cached))
+        .layer(middleware::from_fn_with_state(cache_policy, conditional))
+        .layer(Extension(ResponseCache::new(cli.cache_capacity, cli.cache_size.saturating_mul(1024 * 1024))))
+        .layer(Extension(limits.exports.clone()))
+        .layer(Extension(graphql::schema()))
+        .layer(middleware::from_fn_with_state(limits.rate.clone(), rate_limit))
+        .layer(middleware::from_fn_with_state(anonymous, authorize))
+        .layer(Extension(limits.attempts.clone()))
+        .layer(middleware::from_fn_with_state(routes.clone(), metrics::track))
+        .layer(Extension(events))
+        // probes and the web GUI skip the cache, authorization and rate limit
+        .merge(probe_router().into_router())
+        .merge(web::router(&web_dir))
+        .layer(db)
+        .layer(compression)
+        .layer(middleware::from_fn_with_state(routes, trace::trace));
+
+    let app = match cors {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
+
+    let listener = Listener::bind(cli.address, cli.socket, cli.socket_mode)?;
+
+    let grpc_listener = match cli.grpc_address {
+        Some(addr) => Some(tokio::net::TcpListener::bind(addr).await.with_context(|| format!("Failed binding {addr}"))?),
+        None => None,
+    };
+    let grpc_tls = cli.tls_cert.clone().zip(cli.tls_key.clone());
+
+    let listener = match cli.tls_cert.zip(cli.tls_key) {
+        Some((cert, key)) => listener.with_tls(TlsOptions { cert, key, ca: cli.tls_ca, redirect: cli.redirect_http }).await?,
+        None => listener,
+    };
+    let gui_exit = supervisor::spawn("./world-tables-gui", work_dir, listener.endpoint()?);
 
     //let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    info!("Listening on {}", &addr);
+    info!("Listening on {}", &listener);
+
+    let grpc = async {
+        match grpc_listener {
+            Some(grpc_listener) => {
+                info!("gRPC listening on {}", grpc_listener.local_addr()?);
+                grpc::serve(grpc_listener, grpc_tls, grpc_db, limits, shutdown_signal(gui_exit.clone())).await
+            },
+            None => Ok(()),
+        }
+    };
 
-    thread::spawn(move || {
-        thread::sleep(time::Duration::from_millis(1500));
+    tokio::try_join!(listener.serve(app, shutdown_signal(gui_exit.clone())), grpc)?;
 
-        let _ = Command::new("./world-tables-gui")
-            .current_dir(work_dir)
-            .arg("-a")
-            .arg(addr.to_string())
-            .output()
-            .expect("failed launching GUI app");
+    // a failing GUI fails the whole app
+    Ok(match gui_exit.code() {
+        Some(code) if code != 0 => ExitCode::from(u8::try_from(code).unwrap_or(1)),
+        _ => ExitCode::SUCCESS,
+    })
+}
 
-        #[cfg(unix)]
-        Command::new("kill")
-            .arg("-SIGTERM")
-            .arg(std::process::id().to_string())
-            .spawn()
-            .expect("failed killing the server");
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  ROUTES  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 
-        #[cfg(windows)]
-        Command::new("taskkill")
-            .arg("/F")
-            .arg("/PID")
-            .arg(std::process::id().to_string())
-            .spawn()
-            .expect("failed killing the server");
-    });
+/// Router wrapper that keeps track of the registered paths, so they can be
+/// checked against the OpenAPI document, and of the ones answered with the
+/// state of the server, which are never cached nor answered `304`
+pub struct ApiRouter {
+    router: Router,
+    paths: Vec<String>,
+    uncached: Vec<String>,
+}
 
-    axum::Server::from_tcp(listener)?
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+impl Default for ApiRouter {
+    fn default() -> Self {
+        Self {
+            router: Router::new(),
+            paths: Vec::new(),
+            uncached: Vec::new(),
+        }
+    }
+}
+
+impl ApiRouter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn route(mut self, path: &str, method_router: MethodRouter) -> Self {
+        self.paths.push(path.to_string());
+        self.router = self.router.route(path, method_router);
+        self
+    }
+
+    pub fn uncached_route(mut self, path: &str, method_router: MethodRouter) -> Self {
+        self.uncached.push(path.to_string());
+        self.route(path, method_router)
+    }
+
+    pub fn nest(mut self, prefix: &str, other: ApiRouter) -> Self {
+        self.paths.extend(other.paths.into_iter().map(|path| format!("{prefix}{path}")));
+        self.uncached.extend(other.uncached.into_iter().map(|path| format!("{prefix}{path}")));
+        self.router = self.router.nest(prefix, other.router);
+        self
+    }
+
+    pub fn merge(mut self, other: ApiRouter) -> Self {
+        self.paths.extend(other.paths);
+        self.uncached.extend(other.uncached);
+        self.router = self.router.merge(other.router);
+        self
+    }
 
-    Ok(())
+    /// Wraps the routes only, leaving the fallback of unmatched requests as it is
+    pub fn route_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.router = self.router.route_layer(layer);
+        self
+    }
+
+    pub fn into_router(self) -> Router {
+        self.router
+    }
+}
+
+/// Types each version of the API serializes its responses into
+pub trait Contract {
+    type Metadata: From<Metadata> + Serialize + Send + 'static;
+    type Country: From<Country> + Serialize + CsvRecord + Geometry + Send + 'static;
+    type State: From<State> + Serialize + CsvRecord + Geometry + Send + 'static;
+    type City: From<City> + Serialize + CsvRecord + Geometry + Send + 'static;
+    type WorldRegion: From<WorldRegion> + Serialize + CsvRecord + Geometry + Send + 'static;
+    type WorldSubregion: From<WorldSubregion> + Serialize + CsvRecord + Geometry + Send + 'static;
+    type Currency: From<Currency> + Serialize + CsvRecord + Geometry + Send + 'static;
+}
+
+/// The unversioned contract, serializing the `world-tables-base` structs as they are
+pub struct Legacy;
+
+impl Contract for Legacy {
+    type Metadata = Metadata;
+    type Country = Country;
+    type State = State;
+    type City = City;
+    type WorldRegion = WorldRegion;
+    type WorldSubregion = WorldSubregion;
+    type Currency = Currency;
+}
+
+fn api_router() -> ApiRouter {
+    ApiRouter::new()
+        .route("/", get(api_index))
+        .route("/openapi.json", get(openapi))
+        .uncached_route("/cache", get(cache_stats))
+        .route("/graphql", get(graphql::graphiql).merge(post(graphql::graphql)))
+        .uncached_route("/events", get(events::events))
+        .uncached_route("/metrics", get(metrics::metrics))
+        .uncached_route("/export/:entity", get(export::export))
+        .route("/stats", get(stats::stats))
+        .route("/stats/:entity/:grouping", get(stats::group_stats))
+        .route("/admin/backup", post(admin::backup_route))
+        .route("/admin/vacuum", post(admin::vacuum_route))
+        .uncached_route("/admin/check", get(admin::check_route))
+        .route("/admin/checkpoint", post(admin::checkpoint_route))
+        .nest("/v1", resource_router::<v1::V1>())
+        // unprefixed routes are kept as aliases for clients that predate v1
+        .merge(resource_router::<Legacy>().route_layer(middleware::from_fn(deprecated)))
+}
+
+/// Health probes, answered outside most of the middleware
+fn probe_router() -> ApiRouter {
+    ApiRouter::new()
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+}
+
+fn resource_router<C: Contract>() -> ApiRouter {
+    let url = UrlBuilder::new();
+
+    ApiRouter::new()
+        .route(&url.for_metadata().path(), get(metadata::<C::Metadata>))
+
+        .route(&url.for_countries().path(), get(countries_index::<C::Country>))
+        .route(&url.for_states().path(), get(states_index::<C::State>))
+        .route(&url.for_cities().path(), get(cities_index::<C::City>))
+        .route(&url.for_world_regions().path(), get(world_regions_index::<C::WorldRegion>))
+        .route(&url.for_world_subregions().path(), get(world_subregions_index::<C::WorldSubregion>))
+        .route(&url.for_currencies().path(), get(currencies_index::<C::Currency>))
+
+        .route(&url.for_country(":key").path(), get(country_data::<C::Country>))
+        .route(&url.for_state(":key").path(), get(state_data::<C::State>))
+        .route(&url.for_city(":key").path(), get(city_data::<C::City>))
+        .route(&url.for_world_region(":key").path(), get(region_data::<C::WorldRegion>))
+        .route(&url.for_world_subregion(":key").path(), get(subregion_data::<C::WorldSubregion>))
+        .route(&url.for_currency(":key").path(), get(currency_data::<C::Currency>))
+
+        .route(&url.for_countries_from_region(":key").path(), get(countries_from_region::<C::Country>))
+        .route(&url.for_countries_from_subregion(":key").path(), get(countries_from_subregion::<C::Country>))
+        .route(&url.for_countries_from_currency(":key").path(), get(countries_from_currency::<C::Country>))
+        .route(&url.for_states_from_country(":key").path(), get(states_from_country::<C::State>))
+        .route(&url.for_cities_from_country(":key").path(), get(cities_from_country::<C::City>))
+        .route(&url.for_cities_from_state(":key").path(), get(cities_from_state::<C::City>))
+        .route(&url.for_subregions_from_region(":key").path(), get(subregions_from_region::<C::WorldSubregion>))
+}
+
+/// Marks responses of the unprefixed routes as deprecated, pointing to their
+/// `/v1` successor
+async fn deprecated<B>(request: Request<B>, next: Next<B>) -> Response {
+    let successor = format!("</v1{}>; rel=\"successor-version\"", request.uri().path());
+    let mut response = next.run(request).await;
+
+    let headers = response.headers_mut();
+    headers.insert("Deprecation", HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.insert(header::LINK, link);
+    }
+
+    response
 }
 
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
@@ -167,6 +599,43 @@ impl Pagination {
     }
 }
 
+/// Most keys accepted by a single lookup
+pub const MAX_KEYS: usize = 1000;
+
+/// Longest `Missing-Keys` header, well under the limits proxies and clients
+/// put on headers
+const MISSING_KEYS_BYTES: usize = 2048;
+
+/// Keys of the objects to look up at once, comma separated in the `keys` query
+/// parameter instead of paginating the list
+#[derive(Debug, Default, Deserialize)]
+pub struct Keys {
+    pub keys: Option<String>,
+}
+
+impl Keys {
+    pub fn to_list(&self) -> Result<Option<Vec<&str>>, String> {
+        let Some(keys) = &self.keys else { return Ok(None) };
+
+        let keys = keys.split(',').map(str::trim).filter(|key| !key.is_empty()).collect::<Vec<_>>();
+
+        if keys.len() > MAX_KEYS {
+            return Err(format!("Too many keys: {}, at most {MAX_KEYS} can be looked up at once", keys.len()));
+        }
+
+        // keys are ISO codes and ids, and the missing ones are sent back in a header
+        if let Some(key) = keys.iter().find(|key| !key.chars().all(|c| c.is_ascii_alphanumeric())) {
+            return Err(format!("Invalid key: {key}"));
+        }
+
+        Ok(Some(keys))
+    }
+}
+
+fn convert<T, D: From<T>>(objects: Vec<T>) -> Vec<D> {
+    objects.into_iter().map(D::from).collect()
+}
+
 fn pagination_headers(pagination: Pagination, count: usize, total_count: usize) -> HeaderMap {
     let mut headers = HeaderMap::with_capacity(5);
     headers.insert("Pagination-Count", count.into());
@@ -177,6 +646,27 @@ fn pagination_headers(pagination: Pagination, count: usize, total_count: usize)
     headers
 }
 
+fn keys_headers(count: usize, missing: &[String]) -> Result<HeaderMap> {
+    // only the missing keys that fit are listed, with `Missing-Count` telling
+    // when some were left out
+    let mut listed = String::new();
+    for key in missing {
+        if listed.len() + key.len() + 1 > MISSING_KEYS_BYTES {
+            break;
+        }
+        if !listed.is_empty() {
+            listed.push(',');
+        }
+        listed.push_str(key);
+    }
+
+    let mut headers = HeaderMap::with_capacity(3);
+    headers.insert("Keys-Count", count.into());
+    headers.insert("Missing-Count", missing.len().into());
+    headers.insert("Missing-Keys", listed.parse()?);
+    Ok(headers)
+}
+
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 //<<>><====================  INDEX HANDLERS  ========================><<>>//
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
@@ -185,81 +675,124 @@ async fn api_index() -> impl IntoResponse {
     "World tables API"
 }
 
-async fn metadata(Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError> {
+async fn cache_stats(Extension(cache): Extension<ResponseCache>) -> impl IntoResponse {
+    (Extension(Uncached), Json(cache.stats()))
+}
+
+async fn openapi() -> impl IntoResponse {
+    Json(&*OPENAPI)
+}
+
+async fn metadata<D>(Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError>
+where
+    D: From<Metadata> + Serialize
+{
     let conn = db.connection()?;
 
     let meta = Metadata {
         version: env!("CARGO_PKG_VERSION").to_string(),
-        countries: Country::count(&conn)?,
-        states: State::count(&conn)?,
-        cities: City::count(&conn)?,
-        regions: WorldRegion::count(&conn)?,
-        subregions: WorldSubregion::count(&conn)?,
-        currencies: Currency::count(&conn)?,
+        countries: timed!(Country::count(&conn))?,
+        states: timed!(State::count(&conn))?,
+        cities: timed!(City::count(&conn))?,
+        regions: timed!(WorldRegion::count(&conn))?,
+        subregions: timed!(WorldSubregion::count(&conn))?,
+        currencies: timed!(Currency::count(&conn))?,
     };
 
-    Ok(Json(meta))
+    Ok(Json(D::from(meta)))
 }
 
-async fn index<T>(db: Database, pagination: Option<Query<Pagination>>) -> Result<impl IntoResponse, AppError>
+async fn index<T, D>(
+    db: Database,
+    pagination: Option<Query<Pagination>>,
+    keys: Keys,
+    format: Format,
+    fields: Fields<T>,
+) -> Result<Response, AppError>
 where
-    T: Model + serde::ser::Serialize
+    T: Selectable + Keyed,
+    T::KeyType: ToString,
+    D: From<T> + Serialize + CsvRecord + Geometry,
 {
+    let conn = db.connection()?;
+
+    let keys = match keys.to_list() {
+        Ok(keys) => keys,
+        Err(err) => return Ok((StatusCode::BAD_REQUEST, err).into_response()),
+    };
+
+    if let Some(keys) = keys {
+        let all = T::FIELDS.iter().map(|(field, _)| *field).collect::<Vec<_>>();
+        let (objects, missing) = timed!(T::select_keys(&conn, fields.selection().unwrap_or(&all), &keys))?;
+
+        return Ok(list_response(format, keys_headers(objects.len(), &missing)?, convert::<T, D>(objects), fields.output())?);
+    }
+
     let Query(pagination) = pagination.unwrap_or_default();
     let (limit, offset) = pagination.to_limit_offset();
 
-    let (total_count, objects) = T::all(&*db.connection()?, limit, offset)?;
+    let (total_count, objects) = match fields.selection() {
+        Some(selection) => (timed!(T::count(&conn))?, timed!(T::select(&conn, selection, &[], limit, offset))?),
+        None => timed!(T::all(&conn, limit, offset))?,
+    };
 
-    Ok(
-        (
-            pagination_headers(pagination, objects.len(), total_count),
-            Json(objects)
-        )
-    )
+    Ok(list_response(format, pagination_headers(pagination, objects.len(), total_count), convert::<T, D>(objects), fields.output())?)
 }
 
-async fn countries_index(pagination: Option<Query<Pagination>>, Extension(db): Extension<Database>
+async fn countries_index<D: From<Country> + Serialize + CsvRecord + Geometry>(pagination: Option<Query<Pagination>>, Query(keys): Query<Keys>, format: Format, fields: Fields<Country>, Extension(db): Extension<Database>
 ) -> Result<impl IntoResponse, AppError> {
 
-    index::<Country>(db, pagination).await
+    index::<Country, D>(db, pagination, keys, format, fields).await
 }
 
-async fn states_index(pagination: Option<Query<Pagination>>, Extension(db): Extension<Database>
+async fn states_index<D: From<State> + Serialize + CsvRecord + Geometry>(pagination: Option<Query<Pagination>>, Query(keys): Query<Keys>, format: Format, fields: Fields<State>, Extension(db): Extension<Database>
 ) -> Result<impl IntoResponse, AppError> {
 
-    index::<State>(db, pagination).await
+    index::<State, D>(db, pagination, keys, format, fields).await
 }
 
-async fn cities_index(
+async fn cities_index<D: From<City> + Serialize + CsvRecord + Geometry>(
     pagination: Option<Query<Pagination>>,
+    Query(keys): Query<Keys>,
+    format: Format,
+    fields: Fields<City>,
     Extension(db): Extension<Database>
 ) -> Result<impl IntoResponse, AppError>
 {
-    index::<City>(db, pagination).await
+    index::<City, D>(db, pagination, keys, format, fields).await
 }
 
-async fn world_regions_index(
+async fn world_regions_index<D: From<WorldRegion> + Serialize + CsvRecord + Geometry>(
     pagination: Option<Query<Pagination>>,
+    Query(keys): Query<Keys>,
+    format: Format,
+    fields: Fields<WorldRegion>,
     Extension(db): Extension<Database>
 ) -> Result<impl IntoResponse, AppError>
 {
-    index::<WorldRegion>(db, pagination).await
+    index::<WorldRegion, D>(db, pagination, keys, format, fields).await
 }
 
-async fn world_subregions_index(
+async fn world_subregions_index<D: From<WorldSubregion> + Serialize + CsvRecord + Geometry>(
     pagination: Option<Query<Pagination>>,
+    Query(keys): Query<Keys>,
+    format: Format,
+    fields: Fields<WorldSubregion>,
     Extension(db): Extension<Database>
 ) -> Result<impl IntoResponse, AppError>
 {
-    index::<WorldSubregion>(db, pagination).await
+    index::<WorldSubregion, D>(db, pagination, keys, format, fields).await
 }
 
-async fn currencies_index(
+async fn currencies_index<D: From<Currency> + Serialize + CsvRecord + Geometry>(
     pagination: Option<Query<Pagination>>,
+    Query(keys): Query<Keys>,
+    format: Format,
+    fields: Fields<Currency>,
     Extension(db): Extension<Database>
 ) -> Result<impl IntoResponse, AppError>
 {
-    index::<Currency>(db, pagination).await
+    index::<Currency, D>(db, pagination, keys, format, fields).await
 }
 
 
@@ -268,204 +801,295 @@ async fn currencies_index(
 //<<>><====================  OBJECT HANDLERS  =======================><<>>//
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 
-async fn country_data(Path(key): Path<String>, Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError> {
+async fn country_data<D: From<Country> + Serialize + CsvRecord + Geometry>(
+    Path(key): Path<String>,
+    format: Format,
+    includes: Includes<Country>,
+    fields: Fields<Country>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, AppError> {
     let conn = db.connection()?;
-    let country = Country::get(&conn, &key)?;
-    let states = State::from_country_count(&conn, &key)?;
-    let cities = City::from_country_count(&conn, &key)?;
+    let mut country = match fields.selection() {
+        Some(selection) => timed!(Country::select_one(&conn, selection, &key))?,
+        None => timed!(Country::get(&conn, &key))?,
+    };
+    includes.fetch(&conn, &mut country)?;
+    let states = timed!(State::from_country_count(&conn, &key))?;
+    let cities = timed!(City::from_country_count(&conn, &key))?;
 
     let mut headers = HeaderMap::with_capacity(2);
     headers.insert("States-Count", states.into());
     headers.insert("Cities-Count", cities.into());
 
-    Ok( (headers, Json(country)) )
+    Ok(object_response(format, headers, D::from(country), fields.output())?)
 }
 
-async fn state_data(Path(key): Path<String>, Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError> {
+async fn state_data<D: From<State> + Serialize + CsvRecord + Geometry>(
+    Path(key): Path<String>,
+    format: Format,
+    includes: Includes<State>,
+    fields: Fields<State>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, AppError> {
     let conn = db.connection()?;
-    let state = State::get(&conn, &key)?;
-    let cities = City::from_state_count(&conn, &key)?;
+    let mut state = match fields.selection() {
+        Some(selection) => timed!(State::select_one(&conn, selection, &key))?,
+        None => timed!(State::get(&conn, &key))?,
+    };
+    includes.fetch(&conn, &mut state)?;
+    let cities = timed!(City::from_state_count(&conn, &key))?;
 
     let mut headers = HeaderMap::with_capacity(1);
     headers.insert("Cities-Count", cities.into());
 
-    Ok( (headers, Json(state)) )
+    Ok(object_response(format, headers, D::from(state), fields.output())?)
 }
 
-async fn city_data(Path(key): Path<String>, Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError> {
-    Ok( Json(City::get(&*db.connection()?, &key)?) )
+async fn city_data<D: From<City> + Serialize + CsvRecord + Geometry>(
+    Path(key): Path<String>,
+    format: Format,
+    includes: Includes<City>,
+    fields: Fields<City>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = db.connection()?;
+    let mut city = match fields.selection() {
+        Some(selection) => timed!(City::select_one(&conn, selection, &key))?,
+        None => timed!(City::get(&conn, &key))?,
+    };
+    includes.fetch(&conn, &mut city)?;
+
+    Ok(object_response(format, HeaderMap::new(), D::from(city), fields.output())?)
 }
 
-async fn region_data(Path(key): Path<String>, Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError> {
+async fn region_data<D: From<WorldRegion> + Serialize + CsvRecord + Geometry>(
+    Path(key): Path<String>,
+    format: Format,
+    includes: Includes<WorldRegion>,
+    fields: Fields<WorldRegion>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, AppError> {
     let conn = db.connection()?;
-    let region = WorldRegion::get(&conn, &key)?;
-    let countries = Country::from_region_count(&conn, &key)?;
-    let subregions = WorldSubregion::from_region_count(&conn, &key)?;
+    let mut region = match fields.selection() {
+        Some(selection) => timed!(WorldRegion::select_one(&conn, selection, &key))?,
+        None => timed!(WorldRegion::get(&conn, &key))?,
+    };
+    includes.fetch(&conn, &mut region)?;
+    let countries = timed!(Country::from_region_count(&conn, &key))?;
+    let subregions = timed!(WorldSubregion::from_region_count(&conn, &key))?;
 
     let mut headers = HeaderMap::with_capacity(2);
     headers.insert("Countries-Count", countries.into());
     headers.insert("Subregions-Count", subregions.into());
 
-    Ok( (headers, Json(region)) )
+    Ok(object_response(format, headers, D::from(region), fields.output())?)
 }
 
-async fn subregion_data(Path(key): Path<String>, Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError> {
+async fn subregion_data<D: From<WorldSubregion> + Serialize + CsvRecord + Geometry>(
+    Path(key): Path<String>,
+    format: Format,
+    includes: Includes<WorldSubregion>,
+    fields: Fields<WorldSubregion>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, AppError> {
     let conn = db.connection()?;
-    let subregion = WorldSubregion::get(&conn, &key)?;
-    let countries = Country::from_subregion_count(&conn, &key)?;
+    let mut subregion = match fields.selection() {
+        Some(selection) => timed!(WorldSubregion::select_one(&conn, selection, &key))?,
+        None => timed!(WorldSubregion::get(&conn, &key))?,
+    };
+    includes.fetch(&conn, &mut subregion)?;
+    let countries = timed!(Country::from_subregion_count(&conn, &key))?;
 
     let mut headers = HeaderMap::with_capacity(1);
     headers.insert("Countries-Count", countries.into());
 
-    Ok( (headers, Json(subregion)) )
+    Ok(object_response(format, headers, D::from(subregion), fields.output())?)
 }
 
-async fn currency_data(Path(key): Path<String>, Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError> {
+async fn currency_data<D: From<Currency> + Serialize + CsvRecord + Geometry>(
+    Path(key): Path<String>,
+    format: Format,
+    includes: Includes<Currency>,
+    fields: Fields<Currency>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, AppError> {
     let conn = db.connection()?;
-    let currency = Currency::get(&conn, &key)?;
-    let countries = Country::from_currency_count(&conn, &key)?;
+    let mut currency = match fields.selection() {
+        Some(selection) => timed!(Currency::select_one(&conn, selection, &key))?,
+        None => timed!(Currency::get(&conn, &key))?,
+    };
+    includes.fetch(&conn, &mut currency)?;
+    let countries = timed!(Country::from_currency_count(&conn, &key))?;
 
     let mut headers = HeaderMap::with_capacity(1);
     headers.insert("Countries-Count", countries.into());
 
-    Ok( (headers, Json(currency)) )
+    Ok(object_response(format, headers, D::from(currency), fields.output())?)
 }
 
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 //<<>><===================  FILTERED HANDLERS  ======================><<>>//
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 
-async fn countries_from_region(
+async fn countries_from_region<D: From<Country> + Serialize + CsvRecord + Geometry>(
     Path(key): Path<String>,
     pagination: Option<Query<Pagination>>,
+    format: Format,
+    fields: Fields<Country>,
     Extension(db): Extension<Database>)
 -> Result<impl IntoResponse, AppError>
 {
     let Query(pagination) = pagination.unwrap_or_default();
     let (limit, offset) = pagination.to_limit_offset();
 
-    let (total_count, objects) = Country::from_region(&*db.connection()?, &key, limit, offset)?;
+    let conn = db.connection()?;
+    let (total_count, objects) = match fields.selection() {
+        Some(selection) => (
+            timed!(Country::from_region_count(&conn, &key))?,
+            timed!(Country::select(&conn, selection, &[("world_region_id", &key)], limit, offset))?,
+        ),
+        None => timed!(Country::from_region(&conn, &key, limit, offset))?,
+    };
 
-    Ok(
-        (
-            pagination_headers(pagination, objects.len(), total_count),
-            Json(objects)
-        )
-    )
+    Ok(list_response(format, pagination_headers(pagination, objects.len(), total_count), convert::<_, D>(objects), fields.output())?)
 }
 
-async fn countries_from_subregion(
+async fn countries_from_subregion<D: From<Country> + Serialize + CsvRecord + Geometry>(
     Path(key): Path<String>,
     pagination: Option<Query<Pagination>>,
+    format: Format,
+    fields: Fields<Country>,
     Extension(db): Extension<Database>)
 -> Result<impl IntoResponse, AppError>
 {
     let Query(pagination) = pagination.unwrap_or_default();
     let (limit, offset) = pagination.to_limit_offset();
 
-    let (total_count, objects) = Country::from_subregion(&*db.connection()?, &key, limit, offset)?;
+    let conn = db.connection()?;
+    let (total_count, objects) = match fields.selection() {
+        Some(selection) => (
+            timed!(Country::from_subregion_count(&conn, &key))?,
+            timed!(Country::select(&conn, selection, &[("world_subregion_id", &key)], limit, offset))?,
+        ),
+        None => timed!(Country::from_subregion(&conn, &key, limit, offset))?,
+    };
 
-    Ok(
-        (
-            pagination_headers(pagination, objects.len(), total_count),
-            Json(objects)
-        )
-    )
+    Ok(list_response(format, pagination_headers(pagination, objects.len(), total_count), convert::<_, D>(objects), fields.output())?)
 }
 
-async fn countries_from_currency(
+async fn countries_from_currency<D: From<Country> + Serialize + CsvRecord + Geometry>(
     Path(key): Path<String>,
     pagination: Option<Query<Pagination>>,
+    format: Format,
+    fields: Fields<Country>,
     Extension(db): Extension<Database>)
 -> Result<impl IntoResponse, AppError>
 {
     let Query(pagination) = pagination.unwrap_or_default();
     let (limit, offset) = pagination.to_limit_offset();
 
-    let (total_count, objects) = Country::from_currency(&*db.connection()?, &key, limit, offset)?;
+    let conn = db.connection()?;
+    let (total_count, objects) = match fields.selection() {
+        Some(selection) => (
+            timed!(Country::from_currency_count(&conn, &key))?,
+            timed!(Country::select(&conn, selection, &[("currency_id", &key)], limit, offset))?,
+        ),
+        None => timed!(Country::from_currency(&conn, &key, limit, offset))?,
+    };
 
-    Ok(
-        (
-            pagination_headers(pagination, objects.len(), total_count),
-            Json(objects)
-        )
-    )
+    Ok(list_response(format, pagination_headers(pagination, objects.len(), total_count), convert::<_, D>(objects), fields.output())?)
 }
 
-async fn states_from_country(
+async fn states_from_country<D: From<State> + Serialize + CsvRecord + Geometry>(
     Path(key): Path<String>,
     pagination: Option<Query<Pagination>>,
+    format: Format,
+    fields: Fields<State>,
     Extension(db): Extension<Database>)
 -> Result<impl IntoResponse, AppError>
 {
     let Query(pagination) = pagination.unwrap_or_default();
     let (limit, offset) = pagination.to_limit_offset();
 
-    let (total_count, objects) = State::from_country(&*db.connection()?, &key, limit, offset)?;
+    let conn = db.connection()?;
+    let (total_count, objects) = match fields.selection() {
+        Some(selection) => (
+            timed!(State::from_country_count(&conn, &key))?,
+            timed!(State::select(&conn, selection, &[("country_id", &key)], limit, offset))?,
+        ),
+        None => timed!(State::from_country(&conn, &key, limit, offset))?,
+    };
 
-    Ok(
-        (
-            pagination_headers(pagination, objects.len(), total_count),
-            Json(objects)
-        )
-    )
+    Ok(list_response(format, pagination_headers(pagination, objects.len(), total_count), convert::<_, D>(objects), fields.output())?)
 }
 
-async fn cities_from_country(
+async fn cities_from_country<D: From<City> + Serialize + CsvRecord + Geometry>(
     Path(key): Path<String>,
     pagination: Option<Query<Pagination>>,
+    format: Format,
+    fields: Fields<City>,
     Extension(db): Extension<Database>)
 -> Result<impl IntoResponse, AppError>
 {
     let Query(pagination) = pagination.unwrap_or_default();
     let (limit, offset) = pagination.to_limit_offset();
 
-    let (total_count, objects) = City::from_country(&*db.connection()?, &key, limit, offset)?;
+    let conn = db.connection()?;
+    let (total_count, objects) = match fields.selection() {
+        Some(selection) => (
+            timed!(City::from_country_count(&conn, &key))?,
+            timed!(City::select(&conn, selection, &[("country_id", &key)], limit, offset))?,
+        ),
+        None => timed!(City::from_country(&conn, &key, limit, offset))?,
+    };
 
-    Ok(
-        (
-            pagination_headers(pagination, objects.len(), total_count),
-            Json(objects)
-        )
-    )
+    Ok(list_response(format, pagination_headers(pagination, objects.len(), total_count), convert::<_, D>(objects), fields.output())?)
 }
 
-async fn cities_from_state(
+async fn cities_from_state<D: From<City> + Serialize + CsvRecord + Geometry>(
     Path(key): Path<String>,
     pagination: Option<Query<Pagination>>,
+    format: Format,
+    fields: Fields<City>,
     Extension(db): Extension<Database>)
 -> Result<impl IntoResponse, AppError>
 {
     let Query(pagination) = pagination.unwrap_or_default();
     let (limit, offset) = pagination.to_limit_offset();
 
-    let (total_count, objects) = City::from_state(&*db.connection()?, &key, limit, offset)?;
+    let conn = db.connection()?;
+    let (total_count, objects) = match fields.selection() {
+        Some(selection) => (
+            timed!(City::from_state_count(&conn, &key))?,
+            timed!(City::select(&conn, selection, &[("state_id", &key)], limit, offset))?,
+        ),
+        None => timed!(City::from_state(&conn, &key, limit, offset))?,
+    };
 
-    Ok(
-        (
-            pagination_headers(pagination, objects.len(), total_count),
-            Json(objects)
-        )
-    )
+    Ok(list_response(format, pagination_headers(pagination, objects.len(), total_count), convert::<_, D>(objects), fields.output())?)
 }
 
-async fn subregions_from_region(
+async fn subregions_from_region<D: From<WorldSubregion> + Serialize + CsvRecord + Geometry>(
     Path(key): Path<String>,
     pagination: Option<Query<Pagination>>,
+    format: Format,
+    fields: Fields<WorldSubregion>,
     Extension(db): Extension<Database>)
 -> Result<impl IntoResponse, AppError>
 {
     let Query(pagination) = pagination.unwrap_or_default();
     let (limit, offset) = pagination.to_limit_offset();
 
-    let (total_count, objects) = WorldSubregion::from_region(&*db.connection()?, &key, limit, offset)?;
+    let conn = db.connection()?;
+    let (total_count, objects) = match fields.selection() {
+        Some(selection) => (
+            timed!(WorldSubregion::from_region_count(&conn, &key))?,
+            timed!(WorldSubregion::select(&conn, selection, &[("sub.world_region_id", &key)], limit, offset))?,
+        ),
+        None => timed!(WorldSubregion::from_region(&conn, &key, limit, offset))?,
+    };
 
-    Ok(
-        (
-            pagination_headers(pagination, objects.len(), total_count),
-            Json(objects)
-        )
-    )
+    Ok(list_response(format, pagination_headers(pagination, objects.len(), total_count), convert::<_, D>(objects), fields.output())?)
 }
 
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
@@ -478,11 +1102,15 @@ struct AppError(anyhow::Error);
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        tracing::error!("{:#}", self.0);
+
+        // the id lets the logs about the request be found from the error
+        let message = match trace::current_id() {
+            Some(id) => format!("Something went wrong: {} (request id {})", self.0, id.to_str().unwrap_or_default()),
+            None => format!("Something went wrong: {}", self.0),
+        };
+
+        (StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
     }
 }
 
@@ -514,6 +1142,7 @@ where
 #[derive(Clone)]
 pub struct Database {
     pool: Pool<SqliteConnectionManager>,
+    path: PathBuf,
 }
 
 impl Database {
@@ -525,11 +1154,77 @@ impl Database {
                 Ok(())
             });
         let pool = Pool::new(manager)?;
-        Ok(Extension(Self { pool }))
+        Ok(Extension(Self { pool, path: path.into() }))
     }
 
     pub fn connection(&self) -> Result<PooledConnection<SqliteConnectionManager>> {
-        Ok(self.pool.get()?)
+        let start = time::Instant::now();
+        let conn = self.pool.get()?;
+        metrics::record_pool_wait(start.elapsed());
+        Ok(conn)
+    }
+
+    pub fn pool_state(&self) -> r2d2::State {
+        self.pool.state()
+    }
+
+    pub fn pool_max_size(&self) -> u32 {
+        self.pool.max_size()
+    }
+
+    /// Directory of the copies made through the `/admin` routes
+    pub fn backup_dir(&self) -> PathBuf {
+        self.path.with_file_name("backups")
+    }
+
+    pub fn data_version(&self) -> Result<DataVersion> {
+        DataVersion::of(&self.path)
+    }
+}
+
+#[cfg(test)]
+impl Database {
+    /// Migrated, empty database in a file of its own, for tests
+    pub fn temporary(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("world-tables-{name}-{}.db3", std::process::id()));
+
+        for suffix in ["", "-wal", "-shm"] {
+            let mut file = path.clone().into_os_string();
+            file.push(suffix);
+            let _ = std::fs::remove_file(file);
+        }
+
+        init_db(path).expect("failed creating test database").0
+    }
+
+    /// Temporary database with a few objects of every entity, for tests
+    ///
+    /// Brazil has two states and Portugal one, each with a city, besides the
+    /// regions and subregions the migrations add.
+    pub fn seeded(name: &str) -> Self {
+        let db = Self::temporary(name);
+
+        db.connection().unwrap().execute_batch(
+            "INSERT INTO currencies (iso, name, symbol) VALUES ('BRL', 'Brazilian real', 'R$'), ('EUR', 'Euro', '€');
+            INSERT INTO countries (
+                iso2, iso3, name, code, currency_id, currency, tld, native,
+                world_region_id, world_region, world_subregion_id, world_subregion, latitude, longitude, emoji, emoji_u
+            )
+            VALUES ('BR', 'BRA', 'Brazil', 76, 'BRL', 'Brazilian real', '.br', 'Brasil',
+                    2, 'Americas', 9, 'South America', -10, -55, '', ''),
+                ('PT', 'PRT', 'Portugal', 620, 'EUR', 'Euro', '.pt', 'Portugal',
+                    4, 'Europe', 17, 'Southern Europe', 39.5, -8, '', '');
+            INSERT INTO states (id, name, code, country_id, country, latitude, longitude)
+            VALUES (1, 'Sao Paulo', 'SP', 'BR', 'Brazil', -23.5, -46.5),
+                (2, 'Lisbon', '11', 'PT', 'Portugal', 38.75, -9.25),
+                (3, 'Bahia', 'BA', 'BR', 'Brazil', -12.5, -41.75);
+            INSERT INTO cities (id, name, state_id, state, country_id, country, latitude, longitude)
+            VALUES (1, 'Sao Paulo', 1, 'Sao Paulo', 'BR', 'Brazil', -23.5, -46.625),
+                (2, 'Lisbon', 2, 'Lisbon', 'PT', 'Portugal', 38.75, -9.125),
+                (3, 'Salvador', 3, 'Bahia', 'BR', 'Brazil', -13, -38.5);"
+        ).expect("failed seeding test database");
+
+        db
     }
 }
 
@@ -540,6 +1235,8 @@ pub fn init_db(path: PathBuf) -> Result<Extension<Database>> {
     conn.pragma_update(None, "journal_mode", "WAL")?;
     // Update the database schema, atomically
     MIGRATIONS.to_latest(&mut conn)?;
+    // databases seeded before the timezones table have none of them
+    fill_timezones(&mut conn)?;
 
     Ok(db)
 }
@@ -548,7 +1245,7 @@ pub fn init_db(path: PathBuf) -> Result<Extension<Database>> {
 //<<>><=======================  SHUTDOWN  ===========================><<>>//
 //<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
 
-async fn shutdown_signal() {
+async fn shutdown_signal(gui_exit: GuiExit) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -569,5 +1266,40 @@ async fn shutdown_signal() {
     tokio::select! {
         _ = ctrl_c => {},
         _ = terminate => {},
+        _ = gui_exit.exited() => {},
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openapi_documents_all_routes() {
+        let paths = OPENAPI["paths"].as_object().expect("OpenAPI document has no paths");
+
+        for route in api_router().paths.into_iter().chain(probe_router().paths) {
+            let template = openapi::path_template(&route);
+            assert!(paths.contains_key(&template), "route {route} is missing from the OpenAPI document");
+        }
+    }
+
+    #[test]
+    fn missing_keys_header_is_capped() {
+        let missing = (0..MAX_KEYS).map(|key| format!("{key:08}")).collect::<Vec<_>>();
+        let headers = keys_headers(0, &missing).unwrap();
+
+        let listed = headers["Missing-Keys"].to_str().unwrap();
+        assert!(listed.len() <= MISSING_KEYS_BYTES);
+        assert!(listed.starts_with("00000000,00000001,"));
+        assert!(!listed.ends_with(','));
+        assert_eq!(headers["Missing-Count"], MAX_KEYS.to_string().as_str());
+
+        let headers = keys_headers(1, &["XX".to_string(), "YY".to_string()]).unwrap();
+        assert_eq!(headers["Missing-Keys"], "XX,YY");
     }
 }
@@ -0,0 +1,258 @@
+//! Prometheus metrics
+//!
+//! A middleware counts the requests and times them by method, route template
+//! and status, a layer of the gRPC server does the same for its calls, the
+//! `Model` calls of the handlers are timed with `timed!`, and
+//! so is each checkout of a pooled connection. `/metrics` serves all of it in
+//! the Prometheus text format, along with the state of the pool and the
+//! counters of the response cache.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use lazy_static::lazy_static;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{Database, ResponseCache, Uncached};
+
+/// Upper bounds, in seconds, of the histogram buckets
+const BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Route label of the requests matching none of the routes, so scanning for
+/// paths doesn't create a label for each of them
+const UNMATCHED: &str = "unmatched";
+
+lazy_static! {
+    static ref METRICS: Metrics = Metrics::default();
+}
+
+#[derive(Default)]
+struct Metrics {
+    /// Requests by method, route and status
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    /// Request durations by method and route
+    request_durations: Mutex<BTreeMap<(String, String), Histogram>>,
+    /// Query durations by model and operation
+    query_durations: Mutex<BTreeMap<(&'static str, &'static str), Histogram>>,
+    /// Time waiting for a pooled connection
+    pool_waits: Mutex<Histogram>,
+    /// gRPC calls by method and status code
+    grpc_calls: Mutex<BTreeMap<(String, String), u64>>,
+    /// gRPC call durations by method
+    grpc_durations: Mutex<BTreeMap<String, Histogram>>,
+}
+
+#[derive(Clone, Default)]
+struct Histogram {
+    /// Observations in each bucket, not cumulative
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+
+        for (bound, count) in BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{{labels}{separator}le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+/// Runs a query of the `M` model, recording how long it took
+pub fn time_query<M, R>(operation: &'static str, query: impl FnOnce() -> R) -> R {
+    let start = Instant::now();
+    let result = query();
+    let model = std::any::type_name::<M>().rsplit("::").next().unwrap_or_default();
+
+    METRICS.query_durations.lock().unwrap().entry((model, operation)).or_default().observe(start.elapsed());
+
+    result
+}
+
+/// Runs a `Model` call, like `timed!(Country::get(&conn, &key))`, recording
+/// how long it took by model and operation
+macro_rules! timed {
+    ($model:ident :: $operation:ident ( $($arg:expr),* $(,)? )) => {
+        $crate::metrics::time_query::<$model, _>(stringify!($operation), || $model::$operation($($arg),*))
+    };
+}
+
+pub(crate) use timed;
+
+/// Records the time spent waiting for a pooled connection
+pub fn record_pool_wait(duration: Duration) {
+    METRICS.pool_waits.lock().unwrap().observe(duration);
+}
+
+/// Records a gRPC call, labeled like the routes with `UNMATCHED` when there's
+/// no such method
+pub fn record_grpc(method: &str, code: tonic::Code, duration: Duration) {
+    let method = if code == tonic::Code::Unimplemented { UNMATCHED } else { method };
+
+    *METRICS.grpc_calls.lock().unwrap().entry((method.to_string(), format!("{code:?}"))).or_default() += 1;
+    METRICS.grpc_durations.lock().unwrap().entry(method.to_string()).or_default().observe(duration);
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><======================  MIDDLEWARE  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Route templates, like `/v1/country/:key`, the requests are labeled with
+#[derive(Clone)]
+pub struct Routes(Arc<Vec<Vec<String>>>);
+
+impl Routes {
+    pub fn new(paths: &[String]) -> Self {
+        Self(Arc::new(paths.iter().map(|path| segments(path)).collect()))
+    }
+
+    /// Template of the route matching a path, preferring literal segments
+    /// over parameters like the router does
+    pub fn template(&self, path: &str) -> String {
+        self.find(path).map_or_else(|| UNMATCHED.to_string(), |route| format!("/{}", route.join("/")))
+    }
+
+    /// Whether one of the routes matches a path
+    pub fn contains(&self, path: &str) -> bool {
+        self.find(path).is_some()
+    }
+
+    fn find(&self, path: &str) -> Option<&Vec<String>> {
+        let path = segments(path);
+
+        self.0
+            .iter()
+            .filter(|route| {
+                route.len() == path.len() &&
+                    route.iter().zip(&path).all(|(route, path)| route.starts_with(':') || route == path)
+            })
+            .min_by_key(|route| route.iter().filter(|segment| segment.starts_with(':')).count())
+    }
+}
+
+fn segments(path: &str) -> Vec<String> {
+    path.split('/').filter(|segment| !segment.is_empty()).map(String::from).collect()
+}
+
+pub async fn track<B>(
+    State(routes): State<Routes>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = request.method().to_string();
+    let route = routes.template(request.uri().path());
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    let elapsed = start.elapsed();
+    let status = response.status().as_u16();
+
+    *METRICS.requests.lock().unwrap().entry((method.clone(), route.clone(), status)).or_default() += 1;
+    METRICS.request_durations.lock().unwrap().entry((method, route)).or_default().observe(elapsed);
+
+    response
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  HANDLER  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+pub async fn metrics(
+    Extension(db): Extension<Database>,
+    Extension(cache): Extension<ResponseCache>,
+) -> impl IntoResponse {
+    let mut out = String::new();
+
+    out.push_str("# HELP http_requests_total Requests answered, by method, route template and status.\n");
+    out.push_str("# TYPE http_requests_total counter\n");
+    for ((method, route, status), count) in METRICS.requests.lock().unwrap().iter() {
+        let _ = writeln!(out, "http_requests_total{{method=\"{method}\",route=\"{route}\",status=\"{status}\"}} {count}");
+    }
+
+    out.push_str("# HELP http_request_duration_seconds Time to answer requests, by method and route template.\n");
+    out.push_str("# TYPE http_request_duration_seconds histogram\n");
+    for ((method, route), histogram) in METRICS.request_durations.lock().unwrap().iter() {
+        histogram.write(&mut out, "http_request_duration_seconds", &format!("method=\"{method}\",route=\"{route}\""));
+    }
+
+    out.push_str("# HELP grpc_calls_total gRPC calls answered, by method and status code.\n");
+    out.push_str("# TYPE grpc_calls_total counter\n");
+    for ((method, code), count) in METRICS.grpc_calls.lock().unwrap().iter() {
+        let _ = writeln!(out, "grpc_calls_total{{method=\"{method}\",code=\"{code}\"}} {count}");
+    }
+
+    out.push_str("# HELP grpc_call_duration_seconds Time to answer gRPC calls, by method.\n");
+    out.push_str("# TYPE grpc_call_duration_seconds histogram\n");
+    for (method, histogram) in METRICS.grpc_durations.lock().unwrap().iter() {
+        histogram.write(&mut out, "grpc_call_duration_seconds", &format!("method=\"{method}\""));
+    }
+
+    out.push_str("# HELP db_query_duration_seconds Time querying SQLite, by model and operation.\n");
+    out.push_str("# TYPE db_query_duration_seconds histogram\n");
+    for ((model, operation), histogram) in METRICS.query_durations.lock().unwrap().iter() {
+        histogram.write(&mut out, "db_query_duration_seconds", &format!("model=\"{model}\",operation=\"{operation}\""));
+    }
+
+    let state = db.pool_state();
+
+    out.push_str("# HELP db_pool_connections Connections in the pool, by whether they are idle or in use.\n");
+    out.push_str("# TYPE db_pool_connections gauge\n");
+    let _ = writeln!(out, "db_pool_connections{{state=\"idle\"}} {}", state.idle_connections);
+    let _ = writeln!(out, "db_pool_connections{{state=\"in_use\"}} {}", state.connections - state.idle_connections);
+
+    out.push_str("# HELP db_pool_max_connections Connections the pool may open.\n");
+    out.push_str("# TYPE db_pool_max_connections gauge\n");
+    let _ = writeln!(out, "db_pool_max_connections {}", db.pool_max_size());
+
+    out.push_str("# HELP db_pool_wait_seconds Time waiting for a pooled connection.\n");
+    out.push_str("# TYPE db_pool_wait_seconds histogram\n");
+    METRICS.pool_waits.lock().unwrap().write(&mut out, "db_pool_wait_seconds", "");
+
+    let stats = cache.stats();
+
+    out.push_str("# HELP response_cache_entries Responses kept in the cache.\n");
+    out.push_str("# TYPE response_cache_entries gauge\n");
+    let _ = writeln!(out, "response_cache_entries {}", stats.entries);
+
+    out.push_str("# HELP response_cache_bytes Bytes taken by the responses kept in the cache.\n");
+    out.push_str("# TYPE response_cache_bytes gauge\n");
+    let _ = writeln!(out, "response_cache_bytes {}", stats.bytes);
+
+    out.push_str("# HELP response_cache_requests_total Cache lookups, by whether they were a hit or a miss.\n");
+    out.push_str("# TYPE response_cache_requests_total counter\n");
+    let _ = writeln!(out, "response_cache_requests_total{{result=\"hit\"}} {}", stats.hits);
+    let _ = writeln!(out, "response_cache_requests_total{{result=\"miss\"}} {}", stats.misses);
+
+    (
+        Extension(Uncached),
+        [(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"))],
+        out,
+    )
+}
@@ -0,0 +1,1318 @@
+use serde_json::{json, Map, Value};
+
+use world_tables_base::{UrlBuilder, Selectable, Groupable, Grouping, Country, State, City, WorldRegion, WorldSubregion, Currency};
+
+use crate::{MAX_KEYS, export, include::{Includable, DEFAULT_LIMIT, MAX_LIMIT}};
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  DOCUMENT  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+lazy_static::lazy_static! {
+    pub static ref OPENAPI: Value = document();
+}
+
+/// Converts an axum route path like `/country/:key` into an OpenAPI path
+/// template like `/country/{key}`
+pub fn path_template(path: &str) -> String {
+    path
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(param) => format!("{{{param}}}"),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn document() -> Value {
+    let mut paths = Map::new();
+
+    let mut add = |path: String, item: Value| {
+        paths.insert(path_template(&path), item);
+    };
+
+    add("/".into(), json!({
+        "get": {
+            "summary": "API index",
+            "operationId": "api_index",
+            "responses": {
+                "200": {
+                    "description": "Plain text greeting",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            },
+        },
+    }));
+
+    add("/openapi.json".into(), json!({
+        "get": {
+            "summary": "This OpenAPI document",
+            "operationId": "openapi",
+            "responses": {
+                "200": {
+                    "description": "OpenAPI 3.1 document",
+                    "content": { "application/json": { "schema": { "type": "object" } } },
+                },
+            },
+        },
+    }));
+
+    add("/cache".into(), json!({
+        "get": {
+            "summary": "Counters of the in-memory response cache, for the `admin` role",
+            "operationId": "cache_stats",
+            "responses": {
+                "200": {
+                    "description": "Cache capacity, stored responses and hit/miss counters",
+                    "content": { "application/json": { "schema": schema_ref("CacheStats") } },
+                },
+            },
+        },
+    }));
+
+    add("/graphql".into(), json!({
+        "get": {
+            "summary": "GraphiQL page to explore the GraphQL schema",
+            "operationId": "graphiql",
+            "responses": {
+                "200": {
+                    "description": "GraphiQL HTML page",
+                    "content": { "text/html": { "schema": { "type": "string" } } },
+                },
+            },
+        },
+        "post": {
+            "summary": "GraphQL query over all the entities and their relations",
+            "operationId": "graphql",
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/json": {
+                        "schema": {
+                            "type": "object",
+                            "required": ["query"],
+                            "properties": {
+                                "query": { "type": "string" },
+                                "operationName": { "type": "string" },
+                                "variables": { "type": "object" },
+                            },
+                        },
+                    },
+                },
+            },
+            "responses": {
+                "200": {
+                    "description": "GraphQL response with the `data` and `errors` of the query",
+                    "content": { "application/json": { "schema": { "type": "object" } } },
+                },
+                "400": { "$ref": "#/components/responses/BadRequest" },
+            },
+        },
+    }));
+
+    add("/events".into(), json!({
+        "get": {
+            "summary": "Stream of the entities created, updated and deleted",
+            "operationId": "events",
+            "parameters": [{
+                "name": "Last-Event-ID",
+                "in": "header",
+                "description": "Id of the last change received, to replay the changes made since then",
+                "schema": { "type": "integer", "minimum": 0 },
+            }],
+            "responses": {
+                "200": {
+                    "description": "Server-Sent Events stream. Each `change` event has the change id as event id \
+                        and a `Change` as JSON data. A `lagged` event tells how many changes were dropped \
+                        for a client too slow to read them, or no longer kept for a `Last-Event-ID` that is too old, \
+                        and the client should reload what it shows.",
+                    "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                },
+                "500": { "$ref": "#/components/responses/Error" },
+            },
+        },
+    }));
+
+    add("/metrics".into(), json!({
+        "get": {
+            "summary": "Prometheus metrics of the requests, queries, connection pool and cache, for the `admin` role",
+            "operationId": "metrics",
+            "responses": {
+                "200": {
+                    "description": "Metrics in the Prometheus text format, with the requests labeled by route template",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            },
+        },
+    }));
+
+    add("/export/:entity".into(), json!({
+        "get": {
+            "summary": "Every object of an entity, streamed as NDJSON or CSV",
+            "operationId": "export",
+            "parameters": [
+                {
+                    "name": "entity",
+                    "in": "path",
+                    "required": true,
+                    "description": "Entity to export",
+                    "schema": { "type": "string", "enum": export::ENTITIES },
+                },
+                {
+                    "name": "format",
+                    "in": "query",
+                    "description": "Overrides the media type negotiated from the `Accept` header, NDJSON when none is asked for",
+                    "schema": { "type": "string", "enum": ["ndjson", "csv"] },
+                },
+            ],
+            "responses": {
+                "200": {
+                    "description": "The objects in the v1 representation, one per line, read and sent as they are \
+                        stepped through. A body cut short means the export failed midway, or that the client \
+                        stopped reading for too long.",
+                    "headers": {
+                        "Content-Disposition": {
+                            "description": "File name to save the export as",
+                            "schema": { "type": "string" },
+                        },
+                    },
+                    "content": {
+                        "application/x-ndjson": { "schema": { "type": "string" } },
+                        "text/csv": { "schema": { "type": "string" } },
+                    },
+                },
+                "400": { "$ref": "#/components/responses/BadRequest" },
+                "404": {
+                    "description": "No entity with that name",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+                "406": { "$ref": "#/components/responses/NotAcceptable" },
+                "500": { "$ref": "#/components/responses/Error" },
+                "503": {
+                    "description": "Too many exports running at once",
+                    "headers": {
+                        "Retry-After": {
+                            "description": "Seconds to wait before trying again",
+                            "schema": { "type": "integer" },
+                        },
+                    },
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            },
+        },
+    }));
+
+    add("/stats".into(), conditional(json!({
+        "get": {
+            "summary": "Number of objects of every entity, with the extent of their coordinates",
+            "operationId": "stats",
+            "responses": {
+                "200": {
+                    "description": "Stats of each entity by the name of its export",
+                    "content": {
+                        "application/json": {
+                            "schema": {
+                                "type": "object",
+                                "required": export::ENTITIES,
+                                "properties": export::ENTITIES
+                                    .iter()
+                                    .map(|entity| (entity.to_string(), schema_ref("Stats")))
+                                    .collect::<Map<_, _>>(),
+                            },
+                        },
+                    },
+                },
+                "500": { "$ref": "#/components/responses/Error" },
+            },
+        },
+    })));
+
+    add("/stats/:entity/:grouping".into(), conditional(json!({
+        "get": {
+            "summary": "Number of objects of an entity related to each object of another, with the extent of their coordinates",
+            "operationId": "group_stats",
+            "parameters": [
+                {
+                    "name": "entity",
+                    "in": "path",
+                    "required": true,
+                    "description": "Entity to count",
+                    "schema": { "type": "string", "enum": export::ENTITIES },
+                },
+                {
+                    "name": "grouping",
+                    "in": "path",
+                    "required": true,
+                    "description": format!("Field relating the objects to the ones they are grouped by: {}", groupings()),
+                    "schema": { "type": "string" },
+                },
+            ],
+            "responses": {
+                "200": {
+                    "description": "One group per related object, largest first, with a `null` key for the objects related to none",
+                    "content": { "application/json": { "schema": { "type": "array", "items": schema_ref("Group") } } },
+                },
+                "404": {
+                    "description": "No entity with that name, or no grouping of it with that name",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+                "500": { "$ref": "#/components/responses/Error" },
+            },
+        },
+    })));
+
+    for (kind, summary) in [
+        ("backup", "Online backup of the database with the SQLite backup API, for the `admin` role"),
+        ("vacuum", "Compacted copy of the database written with `VACUUM INTO`, for the `admin` role"),
+    ] {
+        add(format!("/admin/{kind}"), json!({
+            "post": {
+                "summary": summary,
+                "operationId": kind,
+                "parameters": [{
+                    "name": "name",
+                    "in": "query",
+                    "description": format!(
+                        "File name of the copy in the `backups` directory next to the database, \
+                        `world-<UTC time>-{kind}.db3` by default",
+                    ),
+                    "schema": { "type": "string" },
+                }],
+                "responses": {
+                    "200": {
+                        "description": "The copy written",
+                        "content": { "application/json": { "schema": schema_ref("CopyReport") } },
+                    },
+                    "400": {
+                        "description": "The name isn't a plain file name",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                    "409": {
+                        "description": "A copy with that name already exists",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                    "500": { "$ref": "#/components/responses/Error" },
+                },
+            },
+        }));
+    }
+
+    add("/admin/check".into(), json!({
+        "get": {
+            "summary": "`PRAGMA integrity_check` and `PRAGMA foreign_key_check` of the database, for the `admin` role",
+            "operationId": "check",
+            "responses": {
+                "200": {
+                    "description": "The problems found, if any",
+                    "content": { "application/json": { "schema": schema_ref("CheckReport") } },
+                },
+                "500": { "$ref": "#/components/responses/Error" },
+            },
+        },
+    }));
+
+    add("/admin/checkpoint".into(), json!({
+        "post": {
+            "summary": "`PRAGMA wal_checkpoint` of the database, for the `admin` role",
+            "operationId": "checkpoint",
+            "parameters": [{
+                "name": "mode",
+                "in": "query",
+                "schema": { "type": "string", "enum": ["passive", "full", "restart", "truncate"], "default": "passive" },
+            }],
+            "responses": {
+                "200": {
+                    "description": "Frames in the write-ahead log and checkpointed, `-1` when not in WAL mode",
+                    "content": { "application/json": { "schema": schema_ref("CheckpointReport") } },
+                },
+                "400": { "$ref": "#/components/responses/BadRequest" },
+                "500": { "$ref": "#/components/responses/Error" },
+            },
+        },
+    }));
+
+    for version in [V1, LEGACY] {
+        for (path, item) in resource_paths(&version) {
+            add(path, item);
+        }
+    }
+
+    // any route may refuse a request for its API key or the rate of requests
+    for operation in paths.values_mut().filter_map(Value::as_object_mut).flat_map(|item| item.values_mut()) {
+        operation["responses"]["401"] = json!({ "$ref": "#/components/responses/Unauthorized" });
+        operation["responses"]["403"] = json!({ "$ref": "#/components/responses/Forbidden" });
+        operation["responses"]["429"] = json!({ "$ref": "#/components/responses/TooManyRequests" });
+    }
+
+    // probes are answered without a key and aren't rate limited
+    paths.insert("/healthz".into(), json!({
+        "get": {
+            "summary": "Liveness probe, answering as long as the server process is serving requests",
+            "operationId": "healthz",
+            "security": [],
+            "responses": {
+                "200": {
+                    "description": "Server is alive",
+                    "content": { "application/json": { "schema": schema_ref("Health") } },
+                },
+            },
+        },
+    }));
+
+    paths.insert("/readyz".into(), json!({
+        "get": {
+            "summary": "Readiness probe, checking the database connection, schema version and core tables",
+            "operationId": "readyz",
+            "security": [],
+            "responses": {
+                "200": {
+                    "description": "Server is ready to answer requests",
+                    "content": { "application/json": { "schema": schema_ref("Readiness") } },
+                },
+                "503": {
+                    "description": "Some check failed, with its error in the response",
+                    "content": { "application/json": { "schema": schema_ref("Readiness") } },
+                },
+            },
+        },
+    }));
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "World Tables API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Countries, states, cities, currencies and world regions. \
+                List responses are paginated with the `page` and `limit` query parameters \
+                and report their position through the `Pagination-*` headers. Object \
+                responses report the size of their related lists through `*-Count` headers. \
+                GET responses carry `ETag` and `Last-Modified` validators for conditional requests. \
+                The unprefixed routes are deprecated aliases of the `/v1` routes. \
+                Requests may send an API key as a bearer token, with a `read`, `editor` or `admin` role; \
+                without one they get the role the server was started with, if any. \
+                Every response carries an `X-Request-Id` header, the one of the request when it sent one.",
+        },
+        "security": [{ "ApiKey": [] }, {}],
+        "paths": paths,
+        "components": {
+            "schemas": schemas().into_iter().chain(v1_schemas()).collect::<Map<_, _>>(),
+            "parameters": parameters(),
+            "headers": headers(),
+            "securitySchemes": {
+                "ApiKey": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "API key created with `world-tables-server keys create`",
+                },
+            },
+            "responses": {
+                "Unauthorized": {
+                    "description": "Missing, invalid or revoked API key",
+                    "headers": { "WWW-Authenticate": { "schema": { "type": "string" } } },
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+                "TooManyRequests": {
+                    "description": "The client, told apart by its API key or IP address, made too many requests \
+                        or asked for too many rows with `limit` lately",
+                    "headers": {
+                        "Retry-After": {
+                            "description": "Seconds to wait before trying again",
+                            "schema": { "type": "integer", "minimum": 1 },
+                        },
+                    },
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+                "Forbidden": {
+                    "description": "The role of the API key doesn't allow the request",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+                "NotModified": {
+                    "description": "The representation matching `If-None-Match` or `If-Modified-Since` is still current",
+                },
+                "BadRequest": {
+                    "description": "Unknown `format` or invalid request",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+                "NotAcceptable": {
+                    "description": "None of the media types in the `Accept` header is supported, or GeoJSON was asked for objects without coordinates",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+                "Error": {
+                    "description": "Server or database error, with the id of the request to find it in the logs",
+                    "headers": { "X-Request-Id": { "$ref": "#/components/headers/X-Request-Id" } },
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            },
+        },
+    })
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=======================  OPERATIONS  =========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Version of the API the resource routes are documented for
+struct Version {
+    prefix: &'static str,
+    schema_prefix: &'static str,
+    deprecated: bool,
+}
+
+const V1: Version = Version { prefix: "/v1", schema_prefix: "v1.", deprecated: false };
+const LEGACY: Version = Version { prefix: "", schema_prefix: "", deprecated: true };
+
+impl Version {
+    fn schema(&self, name: &str) -> Value {
+        schema_ref(&format!("{}{name}", self.schema_prefix))
+    }
+
+    fn operation_id(&self, id: &str) -> String {
+        match self.prefix.strip_prefix('/') {
+            Some(prefix) => format!("{prefix}_{id}"),
+            None => id.to_string(),
+        }
+    }
+}
+
+fn resource_paths(version: &Version) -> Vec<(String, Value)> {
+    let url = UrlBuilder::new();
+    let key = ":key";
+
+    let metadata = json!({
+        "get": {
+            "summary": "Version and row counts of every table",
+            "operationId": version.operation_id("metadata"),
+            "deprecated": version.deprecated,
+            "responses": {
+                "200": {
+                    "description": "Server metadata",
+                    "content": { "application/json": { "schema": version.schema("Metadata") } },
+                },
+            },
+        },
+    });
+
+    let paths = vec![
+        (url.for_metadata().path(), metadata),
+
+        (url.for_countries().path(), list(version, "countries_index", "List countries", "Country", None)),
+        (url.for_states().path(), list(version, "states_index", "List states", "State", None)),
+        (url.for_cities().path(), list(version, "cities_index", "List cities", "City", None)),
+        (url.for_world_regions().path(), list(version, "world_regions_index", "List world regions", "WorldRegion", None)),
+        (url.for_world_subregions().path(), list(version, "world_subregions_index", "List world subregions", "WorldSubregion", None)),
+        (url.for_currencies().path(), list(version, "currencies_index", "List currencies", "Currency", None)),
+
+        (url.for_country(key).path(), object(version, "country_data", "Get a country", "Country", "Country ISO2 code", &["States-Count", "Cities-Count"])),
+        (url.for_state(key).path(), object(version, "state_data", "Get a state", "State", "State id", &["Cities-Count"])),
+        (url.for_city(key).path(), object(version, "city_data", "Get a city", "City", "City id", &[])),
+        (url.for_world_region(key).path(), object(version, "region_data", "Get a world region", "WorldRegion", "World region id", &["Countries-Count", "Subregions-Count"])),
+        (url.for_world_subregion(key).path(), object(version, "subregion_data", "Get a world subregion", "WorldSubregion", "World subregion id", &["Countries-Count"])),
+        (url.for_currency(key).path(), object(version, "currency_data", "Get a currency", "Currency", "Currency ISO code", &["Countries-Count"])),
+
+        (url.for_countries_from_region(key).path(), list(version, "countries_from_region", "List countries of a world region", "Country", Some("World region id"))),
+        (url.for_countries_from_subregion(key).path(), list(version, "countries_from_subregion", "List countries of a world subregion", "Country", Some("World subregion id"))),
+        (url.for_countries_from_currency(key).path(), list(version, "countries_from_currency", "List countries using a currency", "Country", Some("Currency ISO code"))),
+        (url.for_states_from_country(key).path(), list(version, "states_from_country", "List states of a country", "State", Some("Country ISO2 code"))),
+        (url.for_cities_from_country(key).path(), list(version, "cities_from_country", "List cities of a country", "City", Some("Country ISO2 code"))),
+        (url.for_cities_from_state(key).path(), list(version, "cities_from_state", "List cities of a state", "City", Some("State id"))),
+        (url.for_subregions_from_region(key).path(), list(version, "subregions_from_region", "List subregions of a world region", "WorldSubregion", Some("World region id"))),
+    ];
+
+    paths
+        .into_iter()
+        .map(|(path, item)| (format!("{}{path}", version.prefix), conditional(item)))
+        .collect()
+}
+
+/// Adds the validator headers and the `304` response of conditional GETs
+fn conditional(mut item: Value) -> Value {
+    let responses = &mut item["get"]["responses"];
+
+    for (name, _) in VALIDATOR_HEADERS {
+        responses["200"]["headers"][name] = header_ref(name);
+    }
+
+    responses["304"] = json!({ "$ref": "#/components/responses/NotModified" });
+
+    item
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+fn header_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/headers/{name}") })
+}
+
+fn key_parameter(description: &str) -> Value {
+    json!({
+        "name": "key",
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn list(version: &Version, operation_id: &str, summary: &str, schema: &str, key: Option<&str>) -> Value {
+    let mut parameters = vec![
+        json!({ "$ref": "#/components/parameters/Page" }),
+        json!({ "$ref": "#/components/parameters/Limit" }),
+        fields_parameter(schema),
+        json!({ "$ref": "#/components/parameters/Format" }),
+    ];
+
+    if located(schema) {
+        parameters.push(json!({ "$ref": "#/components/parameters/Geometry" }));
+    }
+
+    match key {
+        Some(description) => parameters.insert(0, key_parameter(description)),
+        None => parameters.insert(2, json!({ "$ref": "#/components/parameters/Keys" })),
+    }
+
+    let mut content = Map::new();
+    content.insert(
+        "application/json".to_string(),
+        json!({ "schema": { "type": "array", "items": version.schema(schema) } }),
+    );
+    content.extend(alternative_content(schema, "FeatureCollection"));
+
+    let mut headers = PAGINATION_HEADERS
+        .iter()
+        .map(|(name, _)| (name.to_string(), header_ref(name)))
+        .collect::<Map<_, _>>();
+
+    if key.is_none() {
+        headers.extend(KEYS_HEADERS.iter().map(|(name, _)| (name.to_string(), header_ref(name))));
+    }
+
+    if version.deprecated {
+        headers.extend(deprecation_headers());
+    }
+
+    json!({
+        "get": {
+            "summary": summary,
+            "operationId": version.operation_id(operation_id),
+            "deprecated": version.deprecated,
+            "parameters": parameters,
+            "responses": {
+                "200": {
+                    "description": format!("A page of {schema} objects"),
+                    "headers": headers,
+                    "content": content,
+                },
+                "400": { "$ref": "#/components/responses/BadRequest" },
+                "406": { "$ref": "#/components/responses/NotAcceptable" },
+                "500": { "$ref": "#/components/responses/Error" },
+            },
+        },
+    })
+}
+
+fn object(version: &Version, operation_id: &str, summary: &str, schema: &str, key: &str, counts: &[&str]) -> Value {
+    let mut headers = counts
+        .iter()
+        .map(|name| (name.to_string(), header_ref(name)))
+        .collect::<Map<_, _>>();
+
+    if version.deprecated {
+        headers.extend(deprecation_headers());
+    }
+
+    let mut parameters = vec![
+        key_parameter(key),
+        include_parameter(schema),
+        fields_parameter(schema),
+        json!({ "$ref": "#/components/parameters/Format" }),
+    ];
+    if located(schema) {
+        parameters.push(json!({ "$ref": "#/components/parameters/Geometry" }));
+    }
+
+    let mut content = Map::new();
+    content.insert("application/json".to_string(), json!({ "schema": version.schema(schema) }));
+    content.extend(alternative_content(schema, "Feature"));
+
+    json!({
+        "get": {
+            "summary": summary,
+            "operationId": version.operation_id(operation_id),
+            "deprecated": version.deprecated,
+            "parameters": parameters,
+            "responses": {
+                "200": {
+                    "description": format!("The {schema} object"),
+                    "headers": headers,
+                    "content": content,
+                },
+                "400": { "$ref": "#/components/responses/BadRequest" },
+                "406": { "$ref": "#/components/responses/NotAcceptable" },
+                "500": { "$ref": "#/components/responses/Error" },
+            },
+        },
+    })
+}
+
+fn include_parameter(schema: &str) -> Value {
+    let relations = match schema {
+        "Country" => Country::RELATIONS,
+        "State" => State::RELATIONS,
+        "City" => City::RELATIONS,
+        "WorldRegion" => WorldRegion::RELATIONS,
+        "WorldSubregion" => WorldSubregion::RELATIONS,
+        "Currency" => Currency::RELATIONS,
+        _ => &[],
+    };
+
+    json!({
+        "name": "include",
+        "in": "query",
+        "description": format!(
+            "Comma separated relations to embed in the object, one of: {}. \
+            List relations take an optional limit after a colon, like `countries:20`, \
+            from 1 to {MAX_LIMIT} and {DEFAULT_LIMIT} by default.",
+            relations.join(", "),
+        ),
+        "schema": { "type": "string" },
+    })
+}
+
+fn fields_parameter(schema: &str) -> Value {
+    let fields = match schema {
+        "Country" => Country::FIELDS,
+        "State" => State::FIELDS,
+        "City" => City::FIELDS,
+        "WorldRegion" => WorldRegion::FIELDS,
+        "WorldSubregion" => WorldSubregion::FIELDS,
+        "Currency" => Currency::FIELDS,
+        _ => &[],
+    };
+
+    json!({
+        "name": "fields",
+        "in": "query",
+        "description": format!(
+            "Comma separated fields to respond with, some of: {}. \
+            Other fields are left out of the objects, except the included relations.",
+            fields.iter().map(|(field, _)| *field).collect::<Vec<_>>().join(", "),
+        ),
+        "schema": { "type": "string" },
+    })
+}
+
+/// Whether objects of the schema have coordinates, so are available as GeoJSON
+fn located(schema: &str) -> bool {
+    matches!(schema, "Country" | "State" | "City")
+}
+
+/// Media types other than JSON a response can be negotiated to, with the
+/// GeoJSON object type used for located schemas
+fn alternative_content(schema: &str, geojson_type: &str) -> Map<String, Value> {
+    let mut content = Map::new();
+    content.insert(
+        "application/x-ndjson".to_string(),
+        json!({ "schema": { "type": "string", "description": format!("One JSON {schema} object per line") } }),
+    );
+    content.insert(
+        "text/csv".to_string(),
+        json!({
+            "schema": {
+                "type": "string",
+                "description": "One row per object, with references split into `<field>_key` and `<field>_name` columns",
+            },
+        }),
+    );
+    content.insert(
+        "application/msgpack".to_string(),
+        json!({ "schema": { "type": "string", "contentMediaType": "application/msgpack" } }),
+    );
+
+    if located(schema) {
+        content.insert(
+            "application/geo+json".to_string(),
+            json!({
+                "schema": {
+                    "type": "object",
+                    "description": format!(
+                        "GeoJSON {geojson_type} of Point features, with the other {schema} fields as properties"
+                    ),
+                    "required": ["type"],
+                    "properties": { "type": { "const": geojson_type } },
+                },
+            }),
+        );
+    }
+
+    content
+}
+
+fn deprecation_headers() -> Map<String, Value> {
+    ["Deprecation", "Link"]
+        .iter()
+        .map(|name| (name.to_string(), header_ref(name)))
+        .collect()
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=======================  COMPONENTS  =========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+const PAGINATION_HEADERS: [(&str, &str); 5] = [
+    ("Pagination-Count", "Number of objects in this page"),
+    ("Pagination-Total-Count", "Number of objects in all pages"),
+    ("Pagination-Page", "Current page number, starting at 1"),
+    ("Pagination-Limit", "Maximum number of objects per page"),
+    ("Pagination-Total-Pages", "Number of pages for the current limit"),
+];
+
+const COUNT_HEADERS: [(&str, &str); 4] = [
+    ("States-Count", "Number of states related to this object"),
+    ("Cities-Count", "Number of cities related to this object"),
+    ("Countries-Count", "Number of countries related to this object"),
+    ("Subregions-Count", "Number of world subregions related to this object"),
+];
+
+const KEYS_HEADERS: [(&str, &str); 3] = [
+    ("Keys-Count", "Number of objects found for the `keys` parameter"),
+    ("Missing-Count", "Number of keys of the `keys` parameter not found"),
+    ("Missing-Keys", "Comma separated keys of the `keys` parameter not found, cut short to fit in a header, then listing fewer than `Missing-Count`"),
+];
+
+const VALIDATOR_HEADERS: [(&str, &str); 3] = [
+    ("ETag", "Strong entity tag of the response, changing with the data and the request"),
+    ("Last-Modified", "Last time the database was written to"),
+    ("Cache-Control", "`max-age` clients may reuse the response for before revalidating"),
+];
+
+fn parameters() -> Value {
+    json!({
+        "Page": {
+            "name": "page",
+            "in": "query",
+            "description": "Page number, starting at 1. Must be given together with `limit`.",
+            "schema": { "type": "integer", "minimum": 1, "default": 1 },
+        },
+        "Limit": {
+            "name": "limit",
+            "in": "query",
+            "description": "Maximum number of objects per page. Must be given together with `page`.",
+            "schema": { "type": "integer", "minimum": 1, "default": 10 },
+        },
+        "Keys": {
+            "name": "keys",
+            "in": "query",
+            "description": format!(
+                "Comma separated keys of the objects to look up at once, up to {MAX_KEYS}, \
+                instead of paginating the list. Keys not found are reported in the `Missing-Keys` header.",
+            ),
+            "schema": { "type": "string" },
+        },
+        "Format": {
+            "name": "format",
+            "in": "query",
+            "description": "Overrides the media type negotiated from the `Accept` header",
+            "schema": { "type": "string", "enum": ["json", "csv", "ndjson", "msgpack", "geojson"] },
+        },
+        "Geometry": {
+            "name": "geometry",
+            "in": "query",
+            "description": "For GeoJSON lists, whether objects without coordinates are skipped or kept with a `null` geometry",
+            "schema": { "type": "string", "enum": ["skip", "null"], "default": "skip" },
+        },
+    })
+}
+
+fn headers() -> Value {
+    let mut headers = PAGINATION_HEADERS
+        .iter()
+        .chain(COUNT_HEADERS.iter())
+        .chain(KEYS_HEADERS[..2].iter())
+        .map(|(name, description)| {
+            (
+                name.to_string(),
+                json!({ "description": description, "schema": { "type": "integer", "minimum": 0 } }),
+            )
+        })
+        .collect::<Map<_, _>>();
+
+    headers.extend(KEYS_HEADERS[2..].iter().chain(VALIDATOR_HEADERS.iter()).map(|(name, description)| {
+        (name.to_string(), json!({ "description": description, "schema": { "type": "string" } }))
+    }));
+
+    headers.insert("Deprecation".into(), json!({
+        "description": "Present on the deprecated unprefixed routes",
+        "schema": { "const": "true" },
+    }));
+    headers.insert("Link".into(), json!({
+        "description": "Points to the `/v1` successor of a deprecated route",
+        "schema": { "type": "string" },
+    }));
+
+    headers.insert("X-Request-Id".into(), json!({
+        "description": "Id of the request in the server logs, taken from the request when it sends one",
+        "schema": { "type": "string" },
+    }));
+
+    headers.into()
+}
+
+/// Schema for a `dbent` EntityLabel as serialized by serde
+fn entity_label(key_type: &str) -> Value {
+    json!({
+        "description": "Reference to another object as its key and label, or \"None\"",
+        "oneOf": [
+            {
+                "type": "object",
+                "required": ["KeyLabel"],
+                "properties": {
+                    "KeyLabel": {
+                        "type": "array",
+                        "prefixItems": [
+                            { "type": [key_type, "null"] },
+                            { "type": "string" },
+                        ],
+                        "minItems": 2,
+                        "maxItems": 2,
+                    },
+                },
+            },
+            {
+                "type": "object",
+                "required": ["Data"],
+                "properties": { "Data": { "type": "object" } },
+            },
+            { "const": "None" },
+        ],
+    })
+}
+
+/// Schema for a `dbent` Many as serialized by serde
+fn many(schema: &str) -> Value {
+    json!({
+        "description": "Related objects, currently always \"None\" on responses",
+        "oneOf": [
+            {
+                "type": "object",
+                "required": ["Data"],
+                "properties": { "Data": { "type": "array", "items": schema_ref(schema) } },
+            },
+            { "enum": ["NotFetched", "None"] },
+        ],
+    })
+}
+
+fn schemas() -> Map<String, Value> {
+    let int_key = json!({ "type": ["integer", "null"] });
+    let string_key = json!({ "type": ["string", "null"] });
+    let float = json!({ "type": "number", "format": "float" });
+    let optional_float = json!({ "type": ["number", "null"], "format": "float" });
+
+    json!({
+        "Country": {
+            "type": "object",
+            "description": "List routes only fill `iso2`, `name`, `region`, `subregion`, `latitude` \
+                and `longitude`, leaving the other fields with default values",
+            "properties": {
+                "iso2": string_key,
+                "iso3": { "type": "string" },
+                "name": { "type": "string" },
+                "code": { "type": "integer", "minimum": 0 },
+                "capital": entity_label("integer"),
+                "currency": entity_label("string"),
+                "tld": { "type": "string" },
+                "native": { "type": "string" },
+                "region": entity_label("integer"),
+                "subregion": entity_label("integer"),
+                "latitude": float,
+                "longitude": float,
+                "emoji": { "type": "string" },
+                "emoji_u": { "type": "string" },
+                "states": many("State"),
+                "cities": many("City"),
+                "timezones": many("Timezone"),
+            },
+        },
+        "Timezone": {
+            "type": "object",
+            "properties": {
+                "id": int_key,
+                "zone_name": { "type": "string" },
+                "gmt_offset": { "type": "integer", "description": "Offset from UTC in seconds" },
+                "gmt_offset_name": { "type": "string" },
+                "abbreviation": { "type": "string" },
+                "tz_name": { "type": "string" },
+                "country": entity_label("string"),
+            },
+        },
+        "State": {
+            "type": "object",
+            "properties": {
+                "id": int_key,
+                "name": { "type": "string" },
+                "code": { "type": "string" },
+                "country": entity_label("string"),
+                "latitude": optional_float,
+                "longitude": optional_float,
+                "cities": many("City"),
+            },
+        },
+        "City": {
+            "type": "object",
+            "properties": {
+                "id": int_key,
+                "name": { "type": "string" },
+                "state": entity_label("integer"),
+                "country": entity_label("string"),
+                "latitude": optional_float,
+                "longitude": optional_float,
+            },
+        },
+        "Currency": {
+            "type": "object",
+            "properties": {
+                "iso": string_key,
+                "name": { "type": "string" },
+                "symbol": { "type": "string" },
+                "countries": many("Country"),
+            },
+        },
+        "WorldRegion": {
+            "type": "object",
+            "properties": {
+                "id": int_key,
+                "name": { "type": "string" },
+                "subregions": many("WorldSubregion"),
+                "countries": many("Country"),
+            },
+        },
+        "WorldSubregion": {
+            "type": "object",
+            "properties": {
+                "id": int_key,
+                "name": { "type": "string" },
+                "region": entity_label("integer"),
+                "countries": many("Country"),
+            },
+        },
+        "Metadata": metadata_schema(),
+        "Change": {
+            "type": "object",
+            "required": ["id", "entity", "key", "action"],
+            "properties": {
+                "id": { "type": "integer", "minimum": 0 },
+                "entity": {
+                    "type": "string",
+                    "enum": ["country", "state", "city", "region", "subregion", "currency", "timezone"],
+                },
+                "key": { "type": "string" },
+                "action": { "type": "string", "enum": ["create", "update", "delete"] },
+            },
+        },
+        "Health": {
+            "type": "object",
+            "required": ["status", "version"],
+            "properties": {
+                "status": { "type": "string", "const": "alive" },
+                "version": { "type": "string", "description": "Server version" },
+            },
+        },
+        "Readiness": {
+            "type": "object",
+            "required": ["ready", "connection", "migrations", "tables"],
+            "properties": {
+                "ready": { "type": "boolean", "description": "Whether all the checks passed" },
+                "connection": {
+                    "type": "object",
+                    "required": ["ok"],
+                    "description": "A pooled database connection could be obtained",
+                    "properties": {
+                        "ok": { "type": "boolean" },
+                        "error": { "type": "string" },
+                    },
+                },
+                "migrations": {
+                    "type": "object",
+                    "required": ["ok", "version", "latest"],
+                    "description": "The database schema is at the latest migration",
+                    "properties": {
+                        "ok": { "type": "boolean" },
+                        "version": { "type": ["integer", "null"], "description": "Schema version of the database" },
+                        "latest": { "type": ["integer", "null"], "description": "Schema version after all the migrations" },
+                        "error": { "type": "string" },
+                    },
+                },
+                "tables": {
+                    "type": "object",
+                    "required": ["ok", "loaded"],
+                    "description": "The core tables aren't empty",
+                    "properties": {
+                        "ok": { "type": "boolean" },
+                        "loaded": {
+                            "type": "object",
+                            "description": "Whether each core table has any rows",
+                            "additionalProperties": { "type": "boolean" },
+                        },
+                        "error": { "type": "string" },
+                    },
+                },
+            },
+        },
+        "Stats": {
+            "type": "object",
+            "required": ["count", "extent"],
+            "properties": {
+                "count": { "type": "integer", "minimum": 0 },
+                "extent": {
+                    "oneOf": [schema_ref("Extent"), { "type": "null" }],
+                    "description": "Extent of the objects with coordinates, `null` when there are none",
+                },
+            },
+        },
+        "Group": {
+            "type": "object",
+            "required": ["key", "name", "count", "extent"],
+            "properties": {
+                "key": { "type": ["string", "null"], "description": "Key of the related object, as text" },
+                "name": { "type": "string" },
+                "count": { "type": "integer", "minimum": 0 },
+                "extent": { "oneOf": [schema_ref("Extent"), { "type": "null" }] },
+            },
+        },
+        "Extent": {
+            "type": "object",
+            "description": "Bounding box of the coordinates, not wrapping around the antimeridian, and their mean",
+            "required": ["south", "west", "north", "east", "latitude", "longitude"],
+            "properties": {
+                "south": { "type": "number" },
+                "west": { "type": "number" },
+                "north": { "type": "number" },
+                "east": { "type": "number" },
+                "latitude": { "type": "number", "description": "Latitude of the centroid" },
+                "longitude": { "type": "number", "description": "Longitude of the centroid" },
+            },
+        },
+        "CopyReport": {
+            "type": "object",
+            "required": ["path", "bytes", "duration_ms"],
+            "properties": {
+                "path": { "type": "string", "description": "Absolute path of the copy on the server" },
+                "bytes": { "type": "integer", "minimum": 0 },
+                "duration_ms": { "type": "number" },
+            },
+        },
+        "CheckReport": {
+            "type": "object",
+            "required": ["ok", "integrity_errors", "foreign_key_violations"],
+            "properties": {
+                "ok": { "type": "boolean", "description": "Whether no problem was found" },
+                "integrity_errors": { "type": "array", "items": { "type": "string" } },
+                "foreign_key_violations": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["table", "rowid", "parent", "fkid"],
+                        "properties": {
+                            "table": { "type": "string" },
+                            "rowid": { "type": ["integer", "null"] },
+                            "parent": { "type": "string" },
+                            "fkid": { "type": "integer" },
+                        },
+                    },
+                },
+            },
+        },
+        "CheckpointReport": {
+            "type": "object",
+            "required": ["mode", "busy", "log_frames", "checkpointed_frames"],
+            "properties": {
+                "mode": { "type": "string", "enum": ["passive", "full", "restart", "truncate"] },
+                "busy": { "type": "boolean", "description": "Whether a reader or writer kept the checkpoint from completing" },
+                "log_frames": { "type": "integer" },
+                "checkpointed_frames": { "type": "integer" },
+            },
+        },
+        "CacheStats": {
+            "type": "object",
+            "required": ["capacity", "entries", "bytes", "max_bytes", "hits", "misses"],
+            "properties": {
+                "capacity": { "type": "integer", "minimum": 0, "description": "Maximum number of responses kept, 0 when disabled" },
+                "entries": { "type": "integer", "minimum": 0 },
+                "bytes": { "type": "integer", "minimum": 0, "description": "Bytes taken by the responses kept" },
+                "max_bytes": { "type": "integer", "minimum": 0, "description": "Maximum bytes of responses kept, 0 when disabled" },
+                "hits": { "type": "integer", "minimum": 0 },
+                "misses": { "type": "integer", "minimum": 0 },
+            },
+        },
+    })
+    .as_object()
+    .cloned()
+    .unwrap_or_default()
+}
+
+/// Groupings of each entity with stats, as documentation text
+fn groupings() -> String {
+    let names = |groupings: &[Grouping]| match groupings {
+        [] => "none".to_string(),
+        _ => groupings.iter().map(|grouping| format!("`{}`", grouping.name)).collect::<Vec<_>>().join(", "),
+    };
+
+    [
+        ("countries", names(Country::GROUPINGS)),
+        ("states", names(State::GROUPINGS)),
+        ("cities", names(City::GROUPINGS)),
+        ("regions", names(WorldRegion::GROUPINGS)),
+        ("subregions", names(WorldSubregion::GROUPINGS)),
+        ("currencies", names(Currency::GROUPINGS)),
+    ]
+    .iter()
+    .map(|(entity, names)| format!("{names} for `{entity}`"))
+    .collect::<Vec<_>>()
+    .join("; ")
+}
+
+fn metadata_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["version", "countries", "states", "cities", "regions", "subregions", "currencies"],
+        "properties": {
+            "version": { "type": "string" },
+            "countries": { "type": "integer", "minimum": 0 },
+            "states": { "type": "integer", "minimum": 0 },
+            "cities": { "type": "integer", "minimum": 0 },
+            "regions": { "type": "integer", "minimum": 0 },
+            "subregions": { "type": "integer", "minimum": 0 },
+            "currencies": { "type": "integer", "minimum": 0 },
+        },
+    })
+}
+
+/// Schemas for the DTOs of the `v1` module
+fn v1_schemas() -> Map<String, Value> {
+    let reference = |key_type: &str| json!({
+        "description": "Reference to another object, the key may be null when only its name is known",
+        "type": ["object", "null"],
+        "required": ["key", "name"],
+        "properties": {
+            "key": { "type": [key_type, "null"] },
+            "name": { "type": "string" },
+        },
+    });
+    let included_reference = |key_type: &str, schema: &str| {
+        let mut reference = reference(key_type);
+        reference["description"] = "Reference to another object, with the whole object in `data` \
+            when it was asked for with `include`".into();
+        reference["properties"]["data"] = schema_ref(&format!("v1.{schema}"));
+        reference
+    };
+    let included = |schema: &str| json!({
+        "description": "Only present when asked for with `include`",
+        "type": "array",
+        "items": schema_ref(&format!("v1.{schema}")),
+    });
+    let float = json!({ "type": "number", "format": "float" });
+    let optional_float = json!({ "type": ["number", "null"], "format": "float" });
+
+    json!({
+        "v1.Country": {
+            "type": "object",
+            "description": "List routes only fill `iso2`, `name`, `region`, `subregion`, `latitude` \
+                and `longitude`, leaving the other fields with default values",
+            "required": ["iso2", "iso3", "name", "code", "capital", "currency", "tld", "native",
+                "region", "subregion", "latitude", "longitude", "emoji", "emoji_u"],
+            "properties": {
+                "iso2": { "type": "string" },
+                "iso3": { "type": "string" },
+                "name": { "type": "string" },
+                "code": { "type": "integer", "minimum": 0 },
+                "capital": reference("integer"),
+                "currency": included_reference("string", "Currency"),
+                "tld": { "type": "string" },
+                "native": { "type": "string" },
+                "region": reference("integer"),
+                "subregion": reference("integer"),
+                "latitude": float,
+                "longitude": float,
+                "emoji": { "type": "string" },
+                "emoji_u": { "type": "string" },
+                "states": included("State"),
+                "cities": included("City"),
+                "timezones": included("Timezone"),
+            },
+        },
+        "v1.Timezone": {
+            "type": "object",
+            "required": ["id", "zone_name", "gmt_offset", "gmt_offset_name", "abbreviation", "tz_name"],
+            "properties": {
+                "id": { "type": "integer", "minimum": 0 },
+                "zone_name": { "type": "string" },
+                "gmt_offset": { "type": "integer", "description": "Offset from UTC in seconds" },
+                "gmt_offset_name": { "type": "string" },
+                "abbreviation": { "type": "string" },
+                "tz_name": { "type": "string" },
+            },
+        },
+        "v1.State": {
+            "type": "object",
+            "required": ["id", "name", "code", "country", "latitude", "longitude"],
+            "properties": {
+                "id": { "type": "integer", "minimum": 0 },
+                "name": { "type": "string" },
+                "code": { "type": "string" },
+                "country": included_reference("string", "Country"),
+                "latitude": optional_float,
+                "longitude": optional_float,
+                "cities": included("City"),
+            },
+        },
+        "v1.City": {
+            "type": "object",
+            "required": ["id", "name", "state", "country", "latitude", "longitude"],
+            "properties": {
+                "id": { "type": "integer", "minimum": 0 },
+                "name": { "type": "string" },
+                "state": included_reference("integer", "State"),
+                "country": included_reference("string", "Country"),
+                "latitude": optional_float,
+                "longitude": optional_float,
+            },
+        },
+        "v1.Currency": {
+            "type": "object",
+            "required": ["iso", "name", "symbol"],
+            "properties": {
+                "iso": { "type": "string" },
+                "name": { "type": "string" },
+                "symbol": { "type": "string" },
+                "countries": included("Country"),
+            },
+        },
+        "v1.WorldRegion": {
+            "type": "object",
+            "required": ["id", "name"],
+            "properties": {
+                "id": { "type": "integer", "minimum": 0 },
+                "name": { "type": "string" },
+                "subregions": included("WorldSubregion"),
+                "countries": included("Country"),
+            },
+        },
+        "v1.WorldSubregion": {
+            "type": "object",
+            "required": ["id", "name", "region"],
+            "properties": {
+                "id": { "type": "integer", "minimum": 0 },
+                "name": { "type": "string" },
+                "region": included_reference("integer", "WorldRegion"),
+                "countries": included("Country"),
+            },
+        },
+        "v1.Metadata": metadata_schema(),
+    })
+    .as_object()
+    .cloned()
+    .unwrap_or_default()
+}
@@ -0,0 +1,167 @@
+//! Aggregate statistics
+//!
+//! `/stats` answers the number of objects of every entity with the extent of
+//! their coordinates, and `/stats/:entity/:grouping` the same for the objects
+//! grouped by a related entity, like the cities of each country, each in a
+//! single `GROUP BY` query instead of a count per related object.
+
+use anyhow::Result;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use rusqlite::Connection;
+use serde_json::json;
+
+use world_tables_base::{Groupable, Group, Country, State, City, WorldRegion, WorldSubregion, Currency};
+
+use crate::{AppError, Database, metrics::timed};
+
+pub async fn stats(Extension(db): Extension<Database>) -> Result<impl IntoResponse, AppError> {
+    let conn = db.connection()?;
+
+    Ok(Json(json!({
+        "countries": timed!(Country::stats(&conn))?,
+        "states": timed!(State::stats(&conn))?,
+        "cities": timed!(City::stats(&conn))?,
+        "regions": timed!(WorldRegion::stats(&conn))?,
+        "subregions": timed!(WorldSubregion::stats(&conn))?,
+        "currencies": timed!(Currency::stats(&conn))?,
+    })))
+}
+
+pub async fn group_stats(
+    Path((entity, grouping)): Path<(String, String)>,
+    Extension(db): Extension<Database>,
+) -> Result<Response, AppError> {
+    let conn = db.connection()?;
+
+    let groups = match entity.as_str() {
+        "countries" => groups::<Country>(&conn, &grouping)?,
+        "states" => groups::<State>(&conn, &grouping)?,
+        "cities" => groups::<City>(&conn, &grouping)?,
+        "regions" => groups::<WorldRegion>(&conn, &grouping)?,
+        "subregions" => groups::<WorldSubregion>(&conn, &grouping)?,
+        "currencies" => groups::<Currency>(&conn, &grouping)?,
+        _ => None,
+    };
+
+    Ok(match groups {
+        Some(groups) => Json(groups).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("No stats of {entity} by {grouping}")).into_response(),
+    })
+}
+
+/// Stats of the objects of `T` by `grouping`, `None` when `T` can't be
+/// grouped that way
+fn groups<T: Groupable>(conn: &Connection, grouping: &str) -> Result<Option<Vec<Group>>> {
+    if T::grouping(grouping).is_none() {
+        return Ok(None);
+    }
+
+    timed!(T::group_stats(conn, grouping)).map(Some)
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    fn app(name: &str) -> Router {
+        Router::new()
+            .route("/stats", get(stats))
+            .route("/stats/:entity/:grouping", get(group_stats))
+            .layer(Extension(Database::seeded(name)))
+    }
+
+    async fn send(app: &Router, path: &str) -> (StatusCode, Value) {
+        let response = app.clone().oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn totals_have_the_extent_of_the_coordinates() {
+        let (status, stats) = send(&app("stats-totals"), "/stats").await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(stats["countries"]["count"], 2);
+        assert_eq!(
+            stats["countries"]["extent"],
+            serde_json::json!({ "south": -10.0, "west": -55.0, "north": 39.5, "east": -8.0, "latitude": 14.75, "longitude": -31.5 }),
+        );
+        assert_eq!(stats["cities"]["count"], 3);
+        assert_eq!(stats["currencies"]["count"], 2);
+        assert_eq!(stats["currencies"]["extent"], Value::Null);
+        assert_eq!(stats["regions"]["count"], 6);
+    }
+
+    #[tokio::test]
+    async fn largest_groups_come_first() {
+        let app = app("stats-groups");
+
+        let (status, groups) = send(&app, "/stats/states/country").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(groups[0]["key"], "BR");
+        assert_eq!(groups[0]["name"], "Brazil");
+        assert_eq!(groups[0]["count"], 2);
+        assert_eq!(groups[0]["extent"]["south"], -23.5);
+        assert_eq!(groups[0]["extent"]["north"], -12.5);
+        assert_eq!(groups[0]["extent"]["west"], -46.5);
+        assert_eq!(groups[0]["extent"]["east"], -41.75);
+        assert_eq!(groups[1]["key"], "PT");
+        assert_eq!(groups[1]["count"], 1);
+
+        // through the countries of the cities
+        let (_, groups) = send(&app, "/stats/cities/region").await;
+        let groups = groups.as_array().unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!((&groups[0]["name"], &groups[0]["count"]), (&"Americas".into(), &2.into()));
+        assert_eq!((&groups[1]["name"], &groups[1]["count"]), (&"Europe".into(), &1.into()));
+    }
+
+    #[test]
+    fn subregions_are_grouped_by_their_region() {
+        let db = Database::seeded("stats-subregions");
+        let expected: Vec<(String, String, usize)> = db
+            .connection()
+            .unwrap()
+            .prepare(
+                "SELECT world_regions.id, world_regions.name, COUNT(*) FROM world_subregions
+                JOIN world_regions ON world_regions.id = world_subregions.world_region_id
+                GROUP BY world_regions.id ORDER BY COUNT(*) DESC, CAST(world_regions.id AS TEXT)",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?.to_string(), row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let groups = groups::<WorldSubregion>(&db.connection().unwrap(), "region").unwrap().unwrap();
+        let groups = groups
+            .into_iter()
+            .map(|group| (group.key.unwrap(), group.name, group.stats.count))
+            .collect::<Vec<_>>();
+
+        assert_eq!(groups, expected);
+    }
+
+    #[tokio::test]
+    async fn unknown_groupings_are_not_found() {
+        let app = app("stats-unknown");
+
+        assert_eq!(send(&app, "/stats/currencies/country").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(send(&app, "/stats/countries/planet").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(send(&app, "/stats/planets/region").await.0, StatusCode::NOT_FOUND);
+    }
+}
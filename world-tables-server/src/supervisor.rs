@@ -0,0 +1,160 @@
+//! Supervision of the GUI app
+//!
+//! The GUI is launched once the server answers `200 OK` on `/readyz`, and
+//! what it writes to stdout and stderr is logged line by line by the server.
+//! When it exits, its exit code is sent through a channel that completes the
+//! server shutdown, and the server then exits with the same code.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+    sync::watch,
+    time::{self, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::listen::Endpoint;
+
+/// How long the server has to become ready before giving up on the GUI
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time between readiness probes
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Exit code used when the GUI can't tell its own
+const FAILURE: i32 = 1;
+
+/// Receives the exit code of the GUI once it has exited
+#[derive(Clone)]
+pub struct GuiExit(watch::Receiver<Option<i32>>);
+
+impl GuiExit {
+    /// Completes when the GUI has exited, never if it wasn't launched
+    pub async fn exited(mut self) {
+        while self.0.borrow().is_none() {
+            if self.0.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Exit code of the GUI, if it has exited
+    pub fn code(&self) -> Option<i32> {
+        *self.0.borrow()
+    }
+}
+
+/// Launches `program` in `work_dir` to connect to `endpoint` when the server
+/// is ready, and watches it until it exits
+pub fn spawn(program: impl AsRef<Path>, work_dir: PathBuf, endpoint: Endpoint) -> GuiExit {
+    let program = program.as_ref().to_path_buf();
+    let (tx, rx) = watch::channel(None);
+
+    tokio::spawn(async move {
+        if !wait_ready(&endpoint).await {
+            error!("Server not ready after {READY_TIMEOUT:?}, shutting down");
+            let _ = tx.send(Some(FAILURE));
+            return;
+        }
+
+        if let Some(code) = supervise(&program, &work_dir, &endpoint).await {
+            let _ = tx.send(Some(code));
+        }
+    });
+
+    GuiExit(rx)
+}
+
+/// Whether `/readyz` answered `200 OK` before the timeout
+async fn wait_ready(endpoint: &Endpoint) -> bool {
+    let deadline = Instant::now() + READY_TIMEOUT;
+
+    while Instant::now() < deadline {
+        match endpoint.readiness().await {
+            Ok(status) if status.is_success() => return true,
+            Ok(status) => info!("Waiting for the server to be ready ({status})"),
+            Err(err) => info!("Waiting for the server to be ready ({err:#})"),
+        }
+
+        time::sleep(PROBE_INTERVAL).await;
+    }
+
+    false
+}
+
+/// Runs the GUI to completion, returning its exit code, or `None` when it
+/// couldn't be launched and the server keeps serving without it
+async fn supervise(program: &Path, work_dir: &Path, endpoint: &Endpoint) -> Option<i32> {
+    let args = match endpoint.gui_args() {
+        Ok(args) => args,
+        Err(err) => {
+            error!("Failed launching GUI app: {err:#}");
+            return None;
+        },
+    };
+
+    let mut child = match Command::new(program)
+        .current_dir(work_dir)
+        .args(args)
+        // the lines end up in the server log, which has its own colors
+        .env("NO_COLOR", "1")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            error!("Failed launching GUI app {program:?}, serving without it: {err}");
+            return None;
+        },
+    };
+
+    info!(pid = child.id(), "Launched GUI app");
+
+    let stdout = child.stdout.take().map(|stdout| tokio::spawn(forward(stdout, "stdout")));
+    let stderr = child.stderr.take().map(|stderr| tokio::spawn(forward(stderr, "stderr")));
+
+    let code = match child.wait().await {
+        Ok(status) if status.success() => {
+            info!("GUI app exited");
+            0
+        },
+        Ok(status) => {
+            warn!("GUI app exited with {status}");
+            status.code().unwrap_or(FAILURE)
+        },
+        Err(err) => {
+            error!("Failed waiting for GUI app: {err}");
+            FAILURE
+        },
+    };
+
+    // the last lines are logged before the server starts shutting down
+    for task in [stdout, stderr].into_iter().flatten() {
+        let _ = task.await;
+    }
+
+    Some(code)
+}
+
+/// Logs every line read from `output` of the GUI
+async fn forward(output: impl AsyncRead + Unpin, stream: &'static str) {
+    let mut lines = BufReader::new(output).lines();
+
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => info!(stream, "gui: {line}"),
+            Ok(None) => break,
+            Err(err) => {
+                warn!(stream, "Failed reading GUI output: {err}");
+                break;
+            },
+        }
+    }
+}
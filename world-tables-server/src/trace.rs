@@ -0,0 +1,104 @@
+//! Request tracing
+//!
+//! Every request gets an id, the one sent in its `X-Request-Id` header when
+//! it's a sensible one or else a random one, which is sent back in the same
+//! header. The request is handled inside a span with that id, its method and
+//! its route template, so all the logs about it can be told apart, and ends
+//! with a log of its status and latency. Errors answered to the request
+//! mention the id too, for clients to report along with them.
+
+use axum::{
+    extract::State,
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use clap::ValueEnum;
+use std::time::Instant;
+use tracing::{info, info_span, Instrument};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::metrics::Routes;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest id taken from a request, longer ones are replaced
+const MAX_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// Id of the request being handled
+    static CURRENT_ID: HeaderValue;
+}
+
+/// How the logs are written
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// A JSON object per line, with the fields of the request spans
+    Json,
+}
+
+/// Sets up the logs of the server, filtered by `RUST_LOG`
+pub fn init(format: LogFormat) {
+    let json = format == LogFormat::Json;
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| "world_tables_server=trace".into()))
+        .with(json.then(|| tracing_subscriber::fmt::layer().json().with_current_span(true).with_span_list(false)))
+        .with((!json).then(tracing_subscriber::fmt::layer))
+        .init();
+}
+
+/// Id of the request being handled, if any
+pub fn current_id() -> Option<HeaderValue> {
+    CURRENT_ID.try_with(HeaderValue::clone).ok()
+}
+
+pub async fn trace<B>(
+    State(routes): State<Routes>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID)
+        .filter(|id| is_valid(id))
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_str(&format!("{:032x}", rand::random::<u128>())).unwrap());
+
+    request.headers_mut().insert(REQUEST_ID, id.clone());
+
+    let span = info_span!(
+        "request",
+        request_id = id.to_str().unwrap_or_default(),
+        method = %request.method(),
+        route = routes.template(request.uri().path()),
+    );
+    let start = Instant::now();
+
+    let mut response = CURRENT_ID
+        .scope(id.clone(), next.run(request))
+        .instrument(span.clone())
+        .await;
+
+    span.in_scope(|| {
+        info!(
+            status = response.status().as_u16(),
+            latency_ms = start.elapsed().as_secs_f64() * 1000.0,
+            "finished request",
+        );
+    });
+
+    response.headers_mut().insert(REQUEST_ID, id);
+    response
+}
+
+/// Whether an id sent by a client can be used in the logs as it is
+fn is_valid(id: &HeaderValue) -> bool {
+    let id = id.as_bytes();
+
+    !id.is_empty() &&
+        id.len() <= MAX_ID_LEN &&
+        id.iter().all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(byte))
+}
@@ -0,0 +1,579 @@
+//! Stable v1 contract of the API
+//!
+//! These types are what the `/v1` routes serialize. They are kept apart from the
+//! `world-tables-base` structs so changes to those, or to the serde derives of
+//! `dbent` types like `EntityLabel` and `Many`, don't leak to clients.
+
+use serde::Serialize;
+
+use world_tables_base::{self as base, EntityLabel, Keyed, Label};
+
+use crate::{Contract, format::{CsvRecord, Geometry, optional}};
+
+/// The v1 contract, serializing the DTOs of this module
+pub struct V1;
+
+impl Contract for V1 {
+    type Metadata = Metadata;
+    type Country = Country;
+    type State = State;
+    type City = City;
+    type WorldRegion = WorldRegion;
+    type WorldSubregion = WorldSubregion;
+    type Currency = Currency;
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=======================  REFERENCE  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+/// Reference to another object by its key and name
+///
+/// The key may be missing when only the name of the related object is known,
+/// like a capital city that is not in the cities table. The whole object is
+/// in `data` when it was asked for with the `include` query parameter.
+#[derive(Clone, Debug, Serialize)]
+pub struct Reference<K, T = ()> {
+    pub key: Option<K>,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Box<T>>,
+}
+
+fn int_reference<T, D>(label: &EntityLabel<base::Int, T, String>) -> Option<Reference<u64, D>>
+where
+    T: Keyed<KeyType = base::Int> + Label<LabelType = String>,
+{
+    Some(
+        Reference {
+            key: label.key().ok()?.map(|key| key as u64),
+            name: label.label().ok()?.clone(),
+            data: None,
+        }
+    )
+}
+
+fn string_reference<T, D>(label: &EntityLabel<String, T, String>) -> Option<Reference<String, D>>
+where
+    T: Keyed<KeyType = String> + Label<LabelType = String>,
+{
+    Some(
+        Reference {
+            key: label.key().ok()?.0.clone(),
+            name: label.label().ok()?.clone(),
+            data: None,
+        }
+    )
+}
+
+/// The referenced object, when it was included
+fn included<K, T, L, D>(label: &EntityLabel<K, T, L>) -> Option<Box<D>>
+where
+    T: Clone,
+    D: From<T>,
+{
+    label.data().ok().map(|data| Box::new(D::from(data.clone())))
+}
+
+impl<K, T> Reference<K, T> {
+    fn with_data(self, data: Option<Box<T>>) -> Self {
+        Self { data, ..self }
+    }
+}
+
+impl<K: std::fmt::Display, T> Reference<K, T> {
+    fn csv_columns(reference: &Option<Self>) -> [String; 2] {
+        match reference {
+            Some(Reference { key, name, .. }) => [optional(key.as_ref()), name.clone()],
+            None => Default::default(),
+        }
+    }
+}
+
+/// Objects of a list relation, when it was included
+fn many<T, D: From<T>>(many: base::Many<T>) -> Option<Vec<D>> {
+    match many {
+        base::Many::Data(objects) => Some(objects.into_iter().map(D::from).collect()),
+        _ => None,
+    }
+}
+
+fn int_key(key: &base::Key<base::Int>) -> u64 {
+    key.map(|key| key as u64).unwrap_or_default()
+}
+
+fn string_key(key: &base::Key<String>) -> String {
+    key.0.clone().unwrap_or_default()
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  ENTITIES  ==========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Country {
+    pub iso2: String,
+    pub iso3: String,
+    pub name: String,
+    pub code: u32,
+    pub capital: Option<Reference<u64>>,
+    pub currency: Option<Reference<String, Currency>>,
+    pub tld: String,
+    pub native: String,
+    pub region: Option<Reference<u64>>,
+    pub subregion: Option<Reference<u64>>,
+    pub latitude: f32,
+    pub longitude: f32,
+    pub emoji: String,
+    pub emoji_u: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub states: Option<Vec<State>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cities: Option<Vec<City>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezones: Option<Vec<Timezone>>,
+}
+
+impl From<base::Country> for Country {
+    fn from(country: base::Country) -> Self {
+        Self {
+            iso2: string_key(&country.iso2),
+            capital: int_reference(&country.capital),
+            currency: string_reference(&country.currency)
+                .map(|reference| reference.with_data(included(&country.currency))),
+            region: int_reference(&country.region),
+            subregion: int_reference(&country.subregion),
+            iso3: country.iso3,
+            name: country.name,
+            code: country.code,
+            tld: country.tld,
+            native: country.native,
+            latitude: country.latitude,
+            longitude: country.longitude,
+            emoji: country.emoji,
+            emoji_u: country.emoji_u,
+            states: many(country.states),
+            cities: many(country.cities),
+            timezones: many(country.timezones),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct State {
+    pub id: u64,
+    pub name: String,
+    pub code: String,
+    pub country: Option<Reference<String, Country>>,
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cities: Option<Vec<City>>,
+}
+
+impl From<base::State> for State {
+    fn from(state: base::State) -> Self {
+        Self {
+            id: int_key(&state.id),
+            country: string_reference(&state.country)
+                .map(|reference| reference.with_data(included(&state.country))),
+            name: state.name,
+            code: state.code,
+            latitude: state.latitude,
+            longitude: state.longitude,
+            cities: many(state.cities),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct City {
+    pub id: u64,
+    pub name: String,
+    pub state: Option<Reference<u64, State>>,
+    pub country: Option<Reference<String, Country>>,
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+}
+
+impl From<base::City> for City {
+    fn from(city: base::City) -> Self {
+        Self {
+            id: int_key(&city.id),
+            state: int_reference(&city.state)
+                .map(|reference| reference.with_data(included(&city.state))),
+            country: string_reference(&city.country)
+                .map(|reference| reference.with_data(included(&city.country))),
+            name: city.name,
+            latitude: city.latitude,
+            longitude: city.longitude,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Currency {
+    pub iso: String,
+    pub name: String,
+    pub symbol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countries: Option<Vec<Country>>,
+}
+
+impl From<base::Currency> for Currency {
+    fn from(currency: base::Currency) -> Self {
+        Self {
+            iso: string_key(&currency.iso),
+            name: currency.name,
+            symbol: currency.symbol,
+            countries: many(currency.countries),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WorldRegion {
+    pub id: u64,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subregions: Option<Vec<WorldSubregion>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countries: Option<Vec<Country>>,
+}
+
+impl From<base::WorldRegion> for WorldRegion {
+    fn from(region: base::WorldRegion) -> Self {
+        Self {
+            id: int_key(&region.id),
+            name: region.name,
+            subregions: many(region.subregions),
+            countries: many(region.countries),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WorldSubregion {
+    pub id: u64,
+    pub name: String,
+    pub region: Option<Reference<u64, WorldRegion>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countries: Option<Vec<Country>>,
+}
+
+impl From<base::WorldSubregion> for WorldSubregion {
+    fn from(subregion: base::WorldSubregion) -> Self {
+        Self {
+            id: int_key(&subregion.id),
+            region: int_reference(&subregion.region)
+                .map(|reference| reference.with_data(included(&subregion.region))),
+            name: subregion.name,
+            countries: many(subregion.countries),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Timezone {
+    pub id: u64,
+    pub zone_name: String,
+    pub gmt_offset: i32,
+    pub gmt_offset_name: String,
+    pub abbreviation: String,
+    pub tz_name: String,
+}
+
+impl From<base::Timezone> for Timezone {
+    fn from(timezone: base::Timezone) -> Self {
+        Self {
+            id: int_key(&timezone.id),
+            zone_name: timezone.zone_name,
+            gmt_offset: timezone.gmt_offset,
+            gmt_offset_name: timezone.gmt_offset_name,
+            abbreviation: timezone.abbreviation,
+            tz_name: timezone.tz_name,
+        }
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><==========================  CSV  =============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+impl CsvRecord for Country {
+    fn csv_headers() -> &'static [&'static str] {
+        &[
+            "iso2", "iso3", "name", "code", "capital_key", "capital_name", "currency_key", "currency_name",
+            "tld", "native", "region_key", "region_name", "subregion_key", "subregion_name",
+            "latitude", "longitude", "emoji", "emoji_u",
+        ]
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        let [capital_key, capital_name] = Reference::csv_columns(&self.capital);
+        let [currency_key, currency_name] = Reference::csv_columns(&self.currency);
+        let [region_key, region_name] = Reference::csv_columns(&self.region);
+        let [subregion_key, subregion_name] = Reference::csv_columns(&self.subregion);
+
+        vec![
+            self.iso2.clone(),
+            self.iso3.clone(),
+            self.name.clone(),
+            self.code.to_string(),
+            capital_key,
+            capital_name,
+            currency_key,
+            currency_name,
+            self.tld.clone(),
+            self.native.clone(),
+            region_key,
+            region_name,
+            subregion_key,
+            subregion_name,
+            self.latitude.to_string(),
+            self.longitude.to_string(),
+            self.emoji.clone(),
+            self.emoji_u.clone(),
+        ]
+    }
+}
+
+impl CsvRecord for State {
+    fn csv_headers() -> &'static [&'static str] {
+        &["id", "name", "code", "country_key", "country_name", "latitude", "longitude"]
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        let [country_key, country_name] = Reference::csv_columns(&self.country);
+
+        vec![
+            self.id.to_string(),
+            self.name.clone(),
+            self.code.clone(),
+            country_key,
+            country_name,
+            optional(self.latitude),
+            optional(self.longitude),
+        ]
+    }
+}
+
+impl CsvRecord for City {
+    fn csv_headers() -> &'static [&'static str] {
+        &["id", "name", "state_key", "state_name", "country_key", "country_name", "latitude", "longitude"]
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        let [state_key, state_name] = Reference::csv_columns(&self.state);
+        let [country_key, country_name] = Reference::csv_columns(&self.country);
+
+        vec![
+            self.id.to_string(),
+            self.name.clone(),
+            state_key,
+            state_name,
+            country_key,
+            country_name,
+            optional(self.latitude),
+            optional(self.longitude),
+        ]
+    }
+}
+
+impl CsvRecord for WorldRegion {
+    fn csv_headers() -> &'static [&'static str] {
+        &["id", "name"]
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        vec![self.id.to_string(), self.name.clone()]
+    }
+}
+
+impl CsvRecord for WorldSubregion {
+    fn csv_headers() -> &'static [&'static str] {
+        &["id", "name", "region_key", "region_name"]
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        let [region_key, region_name] = Reference::csv_columns(&self.region);
+
+        vec![self.id.to_string(), self.name.clone(), region_key, region_name]
+    }
+}
+
+impl CsvRecord for Currency {
+    fn csv_headers() -> &'static [&'static str] {
+        &["iso", "name", "symbol"]
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        vec![self.iso.clone(), self.name.clone(), self.symbol.clone()]
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><========================  GEOJSON  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+impl Geometry for Country {
+    const LOCATED: bool = true;
+
+    fn coordinates(&self) -> Option<[f32; 2]> {
+        Some([self.longitude, self.latitude])
+    }
+}
+
+impl Geometry for State {
+    const LOCATED: bool = true;
+
+    fn coordinates(&self) -> Option<[f32; 2]> {
+        Some([self.longitude?, self.latitude?])
+    }
+}
+
+impl Geometry for City {
+    const LOCATED: bool = true;
+
+    fn coordinates(&self) -> Option<[f32; 2]> {
+        Some([self.longitude?, self.latitude?])
+    }
+}
+
+impl Geometry for WorldRegion {}
+impl Geometry for WorldSubregion {}
+impl Geometry for Currency {}
+
+/// Base structs written as CSV and GeoJSON through their v1 DTOs, for the
+/// unversioned routes
+pub trait Versioned: Clone {
+    type V1: From<Self> + CsvRecord + Geometry;
+}
+
+impl Versioned for base::Country { type V1 = Country; }
+impl Versioned for base::State { type V1 = State; }
+impl Versioned for base::City { type V1 = City; }
+impl Versioned for base::WorldRegion { type V1 = WorldRegion; }
+impl Versioned for base::WorldSubregion { type V1 = WorldSubregion; }
+impl Versioned for base::Currency { type V1 = Currency; }
+
+impl<T: Versioned> CsvRecord for T {
+    fn csv_headers() -> &'static [&'static str] {
+        T::V1::csv_headers()
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        T::V1::from(self.clone()).csv_record()
+    }
+}
+
+impl<T: Versioned> Geometry for T {
+    const LOCATED: bool = T::V1::LOCATED;
+
+    fn coordinates(&self) -> Option<[f32; 2]> {
+        T::V1::from(self.clone()).coordinates()
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><======================  PROTOCOLS  ===========================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Metadata {
+    pub version: String,
+    pub countries: u64,
+    pub states: u64,
+    pub cities: u64,
+    pub regions: u64,
+    pub subregions: u64,
+    pub currencies: u64,
+}
+
+impl From<base::Metadata> for Metadata {
+    fn from(meta: base::Metadata) -> Self {
+        Self {
+            version: meta.version,
+            countries: meta.countries as u64,
+            states: meta.states as u64,
+            cities: meta.cities as u64,
+            regions: meta.regions as u64,
+            subregions: meta.subregions as u64,
+            currencies: meta.currencies as u64,
+        }
+    }
+}
+
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+//<<>><=========================  TESTS  ============================><<>>//
+//<<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>><<>>//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{HeaderMap, Request, StatusCode}, Extension};
+    use tower::ServiceExt;
+
+    use crate::Database;
+
+    #[test]
+    fn base_objects_are_written_like_v1_ones() {
+        let city = base::City {
+            name: "Porto".to_string(),
+            latitude: Some(41.15),
+            longitude: Some(-8.61),
+            ..Default::default()
+        };
+
+        assert_eq!(base::City::csv_headers(), City::csv_headers());
+        assert_eq!(city.csv_record(), City::from(city.clone()).csv_record());
+        assert_eq!(city.coordinates(), Some([-8.61, 41.15]));
+
+        assert_eq!(<base::City as Geometry>::LOCATED, City::LOCATED);
+        assert_eq!(<base::Currency as Geometry>::LOCATED, Currency::LOCATED);
+    }
+
+    async fn send(path: &str) -> (StatusCode, HeaderMap, serde_json::Value) {
+        let app = crate::api_router().into_router().layer(Extension(Database::seeded("v1-routes")));
+        let response = app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+
+        (parts.status, parts.headers, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn v1_objects_have_references() {
+        let (status, headers, country) = send("/v1/country/BR").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get("Deprecation").is_none());
+        assert_eq!(country["iso2"], "BR");
+        assert_eq!(country["code"], 76);
+        // the seeded country has no capital
+        assert_eq!(country["capital"], serde_json::json!({ "key": null, "name": "" }));
+        assert_eq!(country["currency"], serde_json::json!({ "key": "BRL", "name": "Brazilian real" }));
+        assert_eq!(country["region"], serde_json::json!({ "key": 2, "name": "Americas" }));
+        assert_eq!(country["subregion"], serde_json::json!({ "key": 9, "name": "South America" }));
+
+        let (_, _, country) = send("/v1/country/BR?include=currency").await;
+        assert_eq!(
+            country["currency"],
+            serde_json::json!({ "key": "BRL", "name": "Brazilian real", "data": { "iso": "BRL", "name": "Brazilian real", "symbol": "R$" } }),
+        );
+    }
+
+    #[tokio::test]
+    async fn legacy_routes_are_deprecated() {
+        let (status, headers, state) = send("/state/1").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["Deprecation"], "true");
+        assert_eq!(headers[axum::http::header::LINK], "</v1/state/1>; rel=\"successor-version\"");
+        assert_eq!(state["name"], "Sao Paulo");
+
+        let (status, headers, _) = send("/nowhere").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(headers.get("Deprecation").is_none());
+    }
+}
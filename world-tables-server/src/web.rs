@@ -0,0 +1,43 @@
+//! Web GUI
+//!
+//! The GUI built for the web with `trunk`, into the directory given with
+//! `--web-dir`, is served at `/app` so it can be used from a browser without
+//! installing anything. Being a static bundle, it's served outside the API
+//! middleware, without needing an API key to load.
+
+use axum::{
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, get_service},
+    Router,
+};
+use log::{info, warn};
+use std::{io, path::Path};
+use tower_http::services::ServeDir;
+
+/// Routes serving the bundle in `dir`, or explaining its absence
+pub fn router(dir: &Path) -> Router {
+    if !dir.join("index.html").is_file() {
+        info!("No web GUI bundle in {dir:?}, /app is disabled");
+        return Router::new()
+            .route("/app", get(missing))
+            .route("/app/*path", get(missing));
+    }
+
+    info!("Serving the web GUI from {dir:?} at /app");
+
+    let files = ServeDir::new(dir).append_index_html_on_directories(true);
+    Router::new().nest_service("/app", get_service(files).handle_error(file_error))
+}
+
+async fn missing() -> impl IntoResponse {
+    (
+        StatusCode::NOT_FOUND,
+        "The web GUI wasn't built, build it with `trunk build --public-url /app/` into the `--web-dir` of the server",
+    )
+}
+
+async fn file_error(err: io::Error) -> impl IntoResponse {
+    warn!("Failed serving web GUI file: {err}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed reading the web GUI files")
+}